@@ -0,0 +1,164 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Fields, LitStr};
+
+/// Derive `agentd::extract::LlmExtract` for a struct with named fields or an
+/// enum whose variants have no fields.
+///
+/// Doc comments become schema descriptions. `#[llm(rename = "...")]` and
+/// `#[llm(description = "...")]` apply to fields and variants, and
+/// `#[llm(instructions = "...")]` on the type replaces the task description.
+#[proc_macro_derive(LlmExtract, attributes(llm))]
+pub fn derive_llm_extract(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Options given with `#[llm(...)]` and doc comments
+#[derive(Default)]
+struct LlmAttrs {
+    rename: Option<String>,
+    description: Option<String>,
+    instructions: Option<String>,
+}
+
+impl LlmAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = LlmAttrs::default();
+        let mut doc = Vec::new();
+
+        for attr in attrs {
+            if attr.path().is_ident("doc") {
+                if let syn::Meta::NameValue(syn::MetaNameValue {
+                    value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(line), .. }),
+                    ..
+                }) = &attr.meta
+                {
+                    doc.push(line.value().trim().to_string());
+                }
+            } else if attr.path().is_ident("llm") {
+                attr.parse_nested_meta(|meta| {
+                    let value = meta.value()?.parse::<LitStr>()?.value();
+                    if meta.path.is_ident("rename") {
+                        parsed.rename = Some(value);
+                    } else if meta.path.is_ident("description") {
+                        parsed.description = Some(value);
+                    } else if meta.path.is_ident("instructions") {
+                        parsed.instructions = Some(value);
+                    } else {
+                        return Err(meta.error("expected `rename`, `description` or `instructions`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        // Doc comment lines are joined into one paragraph
+        let doc = doc.into_iter().filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ");
+        if parsed.description.is_none() && !doc.is_empty() {
+            parsed.description = Some(doc);
+        }
+        Ok(parsed)
+    }
+
+    fn description(&self) -> TokenStream2 {
+        match &self.description {
+            Some(description) => quote!(::core::option::Option::Some(#description)),
+            None => quote!(::core::option::Option::None),
+        }
+    }
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = LlmAttrs::parse(&input.attrs)?;
+    let name = &input.ident;
+    let description = attrs.description();
+
+    let (json_schema, from_json) = match &input.data {
+        Data::Struct(data) => {
+            let Fields::Named(fields) = &data.fields else {
+                return Err(Error::new_spanned(name, "LlmExtract can only be derived for structs with named fields"));
+            };
+            let mut properties = Vec::new();
+            let mut initializers = Vec::new();
+            for field in &fields.named {
+                let field_attrs = LlmAttrs::parse(&field.attrs)?;
+                let ident = field.ident.as_ref().expect("named field");
+                let key = field_attrs.rename.clone().unwrap_or_else(|| ident.to_string());
+                let field_description = field_attrs.description();
+                let ty = &field.ty;
+                properties.push(quote! {
+                    (#key, #field_description, <#ty as ::agentd::extract::LlmExtract>::json_schema())
+                });
+                initializers.push(quote!(#ident: ::agentd::extract::field(value, #key)?));
+            }
+            (
+                quote! {
+                    ::agentd::extract::named_schema::<Self>(|| {
+                        ::agentd::extract::object_schema(#description, ::std::vec![#(#properties),*])
+                    })
+                },
+                quote!(::core::result::Result::Ok(Self { #(#initializers),* })),
+            )
+        }
+        Data::Enum(data) => {
+            let mut keys = Vec::new();
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                if !matches!(variant.fields, Fields::Unit) {
+                    return Err(Error::new_spanned(
+                        variant,
+                        "LlmExtract can only be derived for enums whose variants have no fields",
+                    ));
+                }
+                let variant_attrs = LlmAttrs::parse(&variant.attrs)?;
+                let ident = &variant.ident;
+                let key = variant_attrs.rename.unwrap_or_else(|| ident.to_string());
+                arms.push(quote!(::core::option::Option::Some(#key) => ::core::result::Result::Ok(Self::#ident)));
+                keys.push(key);
+            }
+            let expected = format!("expected one of {}", keys.join(", "));
+            (
+                quote!(::agentd::extract::enum_schema(#description, &[#(#keys),*])),
+                quote! {
+                    match value.as_str() {
+                        #(#arms,)*
+                        _ => ::core::result::Result::Err(::std::format!("{}, got {}", #expected, value)),
+                    }
+                },
+            )
+        }
+        Data::Union(_) => return Err(Error::new_spanned(name, "LlmExtract cannot be derived for unions")),
+    };
+
+    let instructions = attrs.instructions.map(|instructions| {
+        quote! {
+            fn instructions() -> ::std::string::String {
+                ::std::string::String::from(#instructions)
+            }
+        }
+    });
+
+    // Generic parameters must themselves be extractable
+    let type_params: Vec<_> = input.generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause.predicates.push(parse_quote!(#param: ::agentd::extract::LlmExtract));
+    }
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::agentd::extract::LlmExtract for #name #type_generics #where_clause {
+            fn json_schema() -> ::agentd::extract::Value {
+                #json_schema
+            }
+
+            fn from_json(value: &::agentd::extract::Value) -> ::core::result::Result<Self, ::std::string::String> {
+                #from_json
+            }
+
+            #instructions
+        }
+    })
+}
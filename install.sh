@@ -1,17 +1,17 @@
 #!/bin/bash
 set -e
 
-AGENTD_HOME="${HOME}/.agentd"
+AGENTD_HOME="${AGENTD_HOME:-${HOME}/.agentd}"
 AGENTD_BIN="${AGENTD_HOME}/bin"
 AGENTD_MODELS="${AGENTD_HOME}/models"
-AGENTD_CONFIG="${AGENTD_HOME}/config"
+AGENTD_CONFIG_DIR="${AGENTD_HOME}/config"
 
 echo "Installing agentd..."
 
 # Create directory structure
 mkdir -p "${AGENTD_BIN}"
 mkdir -p "${AGENTD_MODELS}"
-mkdir -p "${AGENTD_CONFIG}"
+mkdir -p "${AGENTD_CONFIG_DIR}"
 
 # Build the project
 echo "Building agentd..."
@@ -21,38 +21,9 @@ cargo build --release
 echo "Creating symlink for binary in ${AGENTD_BIN}..."
 ln -sf "$(pwd)/target/release/agentd" "${AGENTD_BIN}/agentd"
 
-# Create default config
+# Create default config files, keeping any that already exist
 echo "Creating default configuration..."
-cat > "${AGENTD_CONFIG}/config.toml" << 'EOF'
-[runtime]
-default_backend = "llama.cpp"
-llama_executable = "llama-cli"
-use_gpu = false
-gpu_layers = 0
-
-[models]
-# Models will be auto-discovered in ~/.agentd/models/
-# You can also add custom model definitions here
-
-[defaults]
-temperature = 0.7
-top_p = 0.9
-repeat_penalty = 1.1
-max_tokens = 256
-EOF
-
-# Create model registry
-echo "Creating model registry..."
-cat > "${AGENTD_CONFIG}/models.toml" << 'EOF'
-# Model Registry
-# Models are automatically discovered, but you can override settings here
-# Add your model configurations below following this format:
-#
-# [your-model-name]
-# file = "your-model-file.gguf"
-# description = "Description of your model"
-# context_size = 4096
-EOF
+AGENTD_HOME="${AGENTD_HOME}" "${AGENTD_BIN}/agentd" config init
 
 # Move any existing GGUF models from models/ directory
 if [ -d "models" ] && [ "$(ls -A models/*.gguf 2>/dev/null)" ]; then
@@ -84,7 +55,7 @@ echo ""
 echo "Installation directory: ${AGENTD_HOME}"
 echo "Binary location: ${AGENTD_BIN}/agentd"
 echo "Models directory: ${AGENTD_MODELS}"
-echo "Config directory: ${AGENTD_CONFIG}"
+echo "Config directory: ${AGENTD_CONFIG_DIR}"
 echo ""
 echo "To complete installation:"
 echo "1. Restart your shell or run: export PATH=\"\$PATH:${AGENTD_BIN}\""
@@ -0,0 +1,1038 @@
+use crate::chat::ChatMessage;
+use crate::config;
+use crate::error::LlmError;
+use crate::llm::{estimate_tokens, GenerationParams, LlmInterface, Usage};
+use crate::schema;
+use crate::tools::{self, ToolCall};
+use crate::{open_with, OpenOptions};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use regex::Regex;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Model replies allowed per run unless `with_max_steps` says otherwise
+pub const DEFAULT_MAX_STEPS: usize = 10;
+
+/// Project-local agent definitions, looked up from the current directory upwards
+pub const PROJECT_AGENTS_DIR: &str = ".agentd/agents";
+
+/// Characters of tool output passed back to the model; the rest is cut off
+pub const MAX_TOOL_OUTPUT: usize = 16_000;
+
+const DEFAULT_SYSTEM_PROMPT: &str = "You are an assistant that completes tasks using the available tools. \
+Call a tool whenever you need information or need to act, then reply with the final answer once the task is done.";
+
+/// Something the agent can do, offered to the model as a tool
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    /// JSON Schema of the arguments object
+    fn parameters(&self) -> Value;
+    /// Run the tool with arguments that satisfy `parameters`. An error is
+    /// reported to the model, which can try again.
+    fn call(&self, arguments: &Value) -> Result<String, LlmError>;
+
+    /// Whether this call only reads. Under the default policy, calls that may
+    /// change something need approval.
+    fn read_only(&self, arguments: &Value) -> bool {
+        let _ = arguments;
+        false
+    }
+
+    /// One line describing the call, shown when asking for approval and matched by policy rules
+    fn summary(&self, arguments: &Value) -> String {
+        arguments.to_string()
+    }
+
+    /// The description sent to the model
+    fn definition(&self) -> tools::Tool {
+        tools::Tool::new(self.name(), self.description(), self.parameters())
+    }
+}
+
+/// Why a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// The model replied without calling a tool
+    Answer,
+    /// The model was still calling tools after the last allowed step
+    MaxSteps,
+    /// The estimated tokens used reached the budget
+    TokenBudget,
+}
+
+impl StopReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StopReason::Answer => "answer",
+            StopReason::MaxSteps => "max_steps",
+            StopReason::TokenBudget => "token_budget",
+        }
+    }
+}
+
+/// What happens to a tool call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalAction {
+    /// Run it without asking
+    Allow,
+    /// Ask the approver
+    #[default]
+    Ask,
+    /// Refuse it; the model is told
+    Deny,
+}
+
+/// Calls to `tool` whose summary matches `arguments` get `action`. Both are
+/// patterns in which `*` matches any text; without `arguments` every call matches.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalRule {
+    pub tool: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+    pub action: ApprovalAction,
+}
+
+/// Which tool calls run without asking, under `[approval]` in an agent definition
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalPolicy {
+    /// For calls that may change something and that no rule matches
+    #[serde(default)]
+    pub default: ApprovalAction,
+    /// Checked in order; the first match wins, even for read-only calls
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<ApprovalRule>,
+    /// Ask about read-only calls no rule matches instead of running them
+    #[serde(skip)]
+    pub ask_read_only: bool,
+}
+
+impl ApprovalPolicy {
+    /// The action for a call and what it came from
+    pub fn action(&self, tool: &str, summary: &str, read_only: bool) -> (ApprovalAction, DecidedBy) {
+        let rule = self.rules.iter().find(|rule| {
+            glob_matches(&rule.tool, tool) && rule.arguments.as_deref().is_none_or(|pattern| glob_matches(pattern, summary))
+        });
+        match rule {
+            Some(rule) => (rule.action, DecidedBy::Rule),
+            None if read_only && self.ask_read_only => (ApprovalAction::Ask, DecidedBy::Default),
+            None if read_only => (ApprovalAction::Allow, DecidedBy::ReadOnly),
+            None => (self.default, DecidedBy::Default),
+        }
+    }
+
+    /// Ask wherever the policy would allow, read-only calls included; `deny` stays as it is
+    pub fn ask_instead_of_allow(&mut self) {
+        self.ask_read_only = true;
+        let actions = std::iter::once(&mut self.default).chain(self.rules.iter_mut().map(|rule| &mut rule.action));
+        for action in actions.filter(|action| **action == ApprovalAction::Allow) {
+            *action = ApprovalAction::Ask;
+        }
+    }
+}
+
+/// `*` matches any text; everything else matches itself
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let parts: Vec<String> = pattern.split('*').map(regex::escape).collect();
+    Regex::new(&format!("(?s)^{}$", parts.join(".*"))).is_ok_and(|pattern| pattern.is_match(text))
+}
+
+/// What settled whether a call could run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecidedBy {
+    /// The tool said the call only reads
+    ReadOnly,
+    Rule,
+    /// The policy's `default`
+    Default,
+    Approver,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApprovalDecision {
+    pub approved: bool,
+    pub decided_by: DecidedBy,
+    /// Why the call was refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// A call the policy says to ask about
+#[derive(Debug)]
+pub struct ApprovalRequest<'a> {
+    pub tool: &'a str,
+    pub arguments: &'a Value,
+    /// The tool's one-line summary of the call
+    pub summary: &'a str,
+}
+
+/// Decides the calls the policy asks about
+pub trait Approver: Send + Sync {
+    /// Whether to run the call. An error refuses it.
+    fn approve(&self, request: &ApprovalRequest) -> Result<bool, LlmError>;
+}
+
+impl<F> Approver for F
+where
+    F: Fn(&ApprovalRequest) -> bool + Send + Sync,
+{
+    fn approve(&self, request: &ApprovalRequest) -> Result<bool, LlmError> {
+        Ok(self(request))
+    }
+}
+
+/// Asks on the controlling terminal, which works even when stdin carries the task
+pub struct TerminalApprover;
+
+impl Approver for TerminalApprover {
+    fn approve(&self, request: &ApprovalRequest) -> Result<bool, LlmError> {
+        let mut tty = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|e| LlmError::InvalidInput(format!("cannot ask for approval without a terminal: {}", e)))?;
+        write!(tty, "Allow {}: {}? [y/N] ", request.tool, request.summary).map_err(LlmError::Io)?;
+        let mut answer = String::new();
+        BufReader::new(tty).read_line(&mut answer).map_err(LlmError::Io)?;
+        Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+    }
+}
+
+/// A tool call made during a run, with what it returned
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolRun {
+    #[serde(flatten)]
+    pub call: ToolCall,
+    pub output: String,
+    /// The tool failed, was refused, was unknown or got invalid arguments; `output` says which
+    pub error: bool,
+    /// Absent when the call never got that far: the tool was unknown or the arguments invalid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalDecision>,
+}
+
+/// Progress reported while a run is underway
+#[derive(Debug)]
+pub enum AgentEvent<'a> {
+    /// The model asked for a tool, which is about to run
+    ToolCall(&'a ToolCall),
+    ToolResult(&'a ToolRun),
+}
+
+/// The outcome of `Agent::run`
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentRun {
+    /// The final reply; empty if the run stopped before there was one
+    pub answer: String,
+    /// The answer parsed as JSON, when the agent has an output schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    pub stop_reason: StopReason,
+    /// Replies requested from the model
+    pub steps: usize,
+    pub tool_calls: Vec<ToolRun>,
+    /// Estimated tokens over all steps
+    pub usage: Usage,
+    /// The whole conversation, including tool calls and their results
+    pub messages: Vec<ChatMessage>,
+}
+
+/// A tool-calling loop: the model is offered the tools, every call it makes
+/// is run and the result fed back, until it answers or a budget runs out
+pub struct Agent {
+    llm: Box<dyn LlmInterface + Send + Sync>,
+    tools: Vec<Box<dyn Tool>>,
+    system: String,
+    max_steps: usize,
+    token_budget: Option<usize>,
+    output_schema: Option<Value>,
+    policy: ApprovalPolicy,
+    approver: Option<Box<dyn Approver>>,
+    approval_log: Option<PathBuf>,
+}
+
+impl Agent {
+    pub fn new(llm: Box<dyn LlmInterface + Send + Sync>) -> Self {
+        Self {
+            llm,
+            tools: Vec::new(),
+            system: DEFAULT_SYSTEM_PROMPT.to_string(),
+            max_steps: DEFAULT_MAX_STEPS,
+            token_budget: None,
+            output_schema: None,
+            policy: ApprovalPolicy::default(),
+            approver: None,
+            approval_log: None,
+        }
+    }
+
+    pub fn with_tool(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.push(Box::new(tool));
+        self
+    }
+
+    pub fn with_boxed_tool(mut self, tool: Box<dyn Tool>) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    /// Replace the default system prompt
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = system.into();
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Stop once this many tokens (estimated, prompts and replies together) have been used
+    pub fn with_token_budget(mut self, tokens: usize) -> Self {
+        self.token_budget = Some(tokens);
+        self
+    }
+
+    /// Require the final answer to be JSON matching `schema`. A reply that
+    /// doesn't match is sent back with the problems, using up a step.
+    pub fn with_output_schema(mut self, schema: Value) -> Self {
+        self.output_schema = Some(schema);
+        self
+    }
+
+    /// Decide which calls run without asking. By default, read-only calls run
+    /// and everything else is asked about.
+    pub fn with_approval_policy(mut self, policy: ApprovalPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Who to ask about calls the policy doesn't settle; without one they are refused
+    pub fn with_approver(mut self, approver: impl Approver + 'static) -> Self {
+        self.approver = Some(Box::new(approver));
+        self
+    }
+
+    /// Append every approval decision to this JSON-lines file
+    pub fn with_approval_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.approval_log = Some(path.into());
+        self
+    }
+
+    pub fn tools(&self) -> impl Iterator<Item = &dyn Tool> {
+        self.tools.iter().map(|tool| tool.as_ref())
+    }
+
+    /// Work on `task` until the model answers or a budget runs out
+    pub fn run(&self, task: &str) -> Result<AgentRun, LlmError> {
+        self.run_with_events(task, &mut |_| {})
+    }
+
+    /// Like `run`, reporting each tool call and result to `on_event`
+    pub fn run_with_events(&self, task: &str, on_event: &mut dyn FnMut(AgentEvent)) -> Result<AgentRun, LlmError> {
+        if let Some(schema) = &self.output_schema {
+            schema::check_schema(schema)?;
+        }
+        let definitions: Vec<tools::Tool> = self.tools.iter().map(|tool| tool.definition()).collect();
+        let definitions_tokens = estimate_tokens(&serde_json::to_string(&definitions)?);
+        let system = match &self.output_schema {
+            Some(schema) => format!(
+                "{}\n\nWhen you are done, reply with only a JSON value matching this JSON Schema:\n{}",
+                self.system, schema
+            ),
+            None => self.system.clone(),
+        };
+        let mut run = AgentRun {
+            answer: String::new(),
+            output: None,
+            stop_reason: StopReason::MaxSteps,
+            steps: 0,
+            tool_calls: Vec::new(),
+            usage: Usage::default(),
+            messages: vec![ChatMessage::system(system), ChatMessage::user(task)],
+        };
+
+        while run.steps < self.max_steps {
+            if self.token_budget.is_some_and(|budget| run.usage.total_tokens >= budget) {
+                run.stop_reason = StopReason::TokenBudget;
+                return Ok(run);
+            }
+
+            run.steps += 1;
+            let prompt_tokens = definitions_tokens + run.messages.iter().map(message_tokens).sum::<usize>();
+            let reply = match self.llm.chat_with_tools(&run.messages, &definitions) {
+                Ok(reply) => reply,
+                Err(LlmError::InvalidResponse(problem)) => {
+                    // An unreadable call is worth another try, like any other tool error
+                    add_usage(&mut run.usage, prompt_tokens, 0);
+                    run.messages.push(ChatMessage::user(format!(
+                        "Your last tool call could not be read ({}). Write it again in the required format.",
+                        problem
+                    )));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            add_usage(&mut run.usage, prompt_tokens, message_tokens(&reply));
+
+            let calls = reply.tool_calls.clone();
+            run.messages.push(reply);
+            if calls.is_empty() {
+                let answer = run.messages.last().map(|reply| reply.content.trim().to_string()).unwrap_or_default();
+                if let Some(schema) = &self.output_schema {
+                    match schema::parse_reply(&answer, schema) {
+                        Ok(output) => run.output = Some(output),
+                        Err(e) => {
+                            run.messages.push(ChatMessage::user(format!(
+                                "Your answer was rejected: {}. Reply again with only JSON matching the schema.",
+                                e
+                            )));
+                            continue;
+                        }
+                    }
+                }
+                run.answer = answer;
+                run.stop_reason = StopReason::Answer;
+                return Ok(run);
+            }
+
+            for call in calls {
+                on_event(AgentEvent::ToolCall(&call));
+                let (result, approval) = match self.find_tool(&call) {
+                    Ok(tool) => {
+                        let decision = self.decide(tool, &call);
+                        if let Some(path) = &self.approval_log {
+                            log_decision(path, tool, &call, &decision)?;
+                        }
+                        let result = match &decision.reason {
+                            None => tool.call(&call.arguments),
+                            Some(reason) => Err(LlmError::InvalidInput(format!("Not approved: {}", reason))),
+                        };
+                        (result, Some(decision))
+                    }
+                    Err(e) => (Err(e), None),
+                };
+                let (output, error) = match result {
+                    Ok(output) => (output, false),
+                    Err(e) => (format!("Error: {}", e), true),
+                };
+                let output = truncate_output(output);
+                run.messages.push(ChatMessage::tool(&call.id, output.as_str()));
+                run.tool_calls.push(ToolRun { call, output, error, approval });
+                on_event(AgentEvent::ToolResult(run.tool_calls.last().expect("just pushed")));
+            }
+        }
+
+        Ok(run)
+    }
+
+    /// The tool `call` asks for, if it exists and the arguments fit its schema
+    fn find_tool(&self, call: &ToolCall) -> Result<&dyn Tool, LlmError> {
+        let tool = self.tools.iter().find(|tool| tool.name() == call.name).ok_or_else(|| {
+            let names: Vec<&str> = self.tools.iter().map(|tool| tool.name()).collect();
+            LlmError::InvalidInput(format!("Unknown tool '{}'; available tools: {}", call.name, names.join(", ")))
+        })?;
+        schema::validate(&call.arguments, &tool.parameters())
+            .map_err(|errors| LlmError::InvalidInput(format!("Invalid arguments: {}", errors.join("; "))))?;
+        Ok(tool.as_ref())
+    }
+
+    fn decide(&self, tool: &dyn Tool, call: &ToolCall) -> ApprovalDecision {
+        let summary = tool.summary(&call.arguments);
+        let (action, decided_by) = self.policy.action(tool.name(), &summary, tool.read_only(&call.arguments));
+        let refused = |decided_by, reason: String| ApprovalDecision { approved: false, decided_by, reason: Some(reason) };
+        match (action, &self.approver) {
+            (ApprovalAction::Allow, _) => ApprovalDecision { approved: true, decided_by, reason: None },
+            (ApprovalAction::Deny, _) => refused(decided_by, "the approval policy denies this call".to_string()),
+            (ApprovalAction::Ask, None) => refused(decided_by, "this call needs approval and there is no one to ask".to_string()),
+            (ApprovalAction::Ask, Some(approver)) => {
+                let request = ApprovalRequest { tool: tool.name(), arguments: &call.arguments, summary: &summary };
+                match approver.approve(&request) {
+                    Ok(true) => ApprovalDecision { approved: true, decided_by: DecidedBy::Approver, reason: None },
+                    Ok(false) => refused(DecidedBy::Approver, "the user declined this call".to_string()),
+                    Err(e) => refused(DecidedBy::Approver, e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// Append one decision to the approval log, creating it if needed
+fn log_decision(path: &Path, tool: &dyn Tool, call: &ToolCall, decision: &ApprovalDecision) -> Result<(), LlmError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    let entry = json!({
+        "time": time,
+        "tool": call.name,
+        "summary": tool.summary(&call.arguments),
+        "arguments": call.arguments,
+        "approved": decision.approved,
+        "decided_by": decision.decided_by,
+        "reason": decision.reason,
+    });
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(LlmError::Io)?;
+    }
+    let mut log = fs::OpenOptions::new().create(true).append(true).open(path).map_err(LlmError::Io)?;
+    writeln!(log, "{}", entry).map_err(LlmError::Io)
+}
+
+fn message_tokens(message: &ChatMessage) -> usize {
+    let calls: usize = message.tool_calls.iter().map(|call| estimate_tokens(&call.arguments.to_string()) + estimate_tokens(&call.name)).sum();
+    estimate_tokens(&message.content) + calls + 4
+}
+
+fn add_usage(usage: &mut Usage, prompt_tokens: usize, completion_tokens: usize) {
+    usage.prompt_tokens += prompt_tokens;
+    usage.completion_tokens += completion_tokens;
+    usage.total_tokens += prompt_tokens + completion_tokens;
+}
+
+fn truncate_output(output: String) -> String {
+    match output.char_indices().nth(MAX_TOOL_OUTPUT) {
+        Some((cut, _)) => format!("{}\n[output truncated after {} characters]", &output[..cut], MAX_TOOL_OUTPUT),
+        None => output,
+    }
+}
+
+/// Resolve `path` inside one of `roots`; relative paths start from the first root.
+/// Symlinks and `..` are resolved before checking, so neither can escape.
+fn resolve_in(roots: &[PathBuf], path: &str) -> Result<PathBuf, LlmError> {
+    let first = roots
+        .first()
+        .ok_or_else(|| LlmError::InvalidInput("No directories are allowed".to_string()))?;
+    let resolved = first
+        .join(path)
+        .canonicalize()
+        .map_err(|e| LlmError::InvalidInput(format!("Cannot access '{}': {}", path, e)))?;
+    let allowed = roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| resolved.starts_with(root));
+    if allowed {
+        Ok(resolved)
+    } else {
+        Err(LlmError::InvalidInput(format!("'{}' is outside the allowed directories", path)))
+    }
+}
+
+fn string_argument<'a>(arguments: &'a Value, name: &str) -> Option<&'a str> {
+    arguments.get(name).and_then(Value::as_str)
+}
+
+/// `read_file`: the text of a file inside the allowed directories
+pub struct ReadFile {
+    roots: Vec<PathBuf>,
+}
+
+impl ReadFile {
+    pub fn new(roots: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        Self { roots: roots.into_iter().map(Into::into).collect() }
+    }
+}
+
+impl Tool for ReadFile {
+    fn name(&self) -> &str {
+        "read_file"
+    }
+
+    fn description(&self) -> &str {
+        "Read a text file. Relative paths start from the working directory."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": { "path": { "type": "string", "description": "File to read" } },
+            "required": ["path"],
+        })
+    }
+
+    fn read_only(&self, _arguments: &Value) -> bool {
+        true
+    }
+
+    fn summary(&self, arguments: &Value) -> String {
+        string_argument(arguments, "path").unwrap_or(".").to_string()
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String, LlmError> {
+        let path = resolve_in(&self.roots, string_argument(arguments, "path").unwrap_or_default())?;
+        let bytes = fs::read(&path).map_err(LlmError::Io)?;
+        String::from_utf8(bytes).map_err(|_| LlmError::InvalidInput(format!("{} is not a text file", path.display())))
+    }
+}
+
+/// `list_directory`: the entries of a directory inside the allowed directories
+pub struct ListDirectory {
+    roots: Vec<PathBuf>,
+}
+
+impl ListDirectory {
+    pub fn new(roots: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        Self { roots: roots.into_iter().map(Into::into).collect() }
+    }
+}
+
+impl Tool for ListDirectory {
+    fn name(&self) -> &str {
+        "list_directory"
+    }
+
+    fn description(&self) -> &str {
+        "List the files and directories in a directory; directories end with '/'."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": { "path": { "type": "string", "description": "Directory to list; defaults to the working directory" } },
+        })
+    }
+
+    fn read_only(&self, _arguments: &Value) -> bool {
+        true
+    }
+
+    fn summary(&self, arguments: &Value) -> String {
+        string_argument(arguments, "path").unwrap_or(".").to_string()
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String, LlmError> {
+        let path = resolve_in(&self.roots, string_argument(arguments, "path").unwrap_or("."))?;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&path).map_err(LlmError::Io)? {
+            let entry = entry.map_err(LlmError::Io)?;
+            let mut name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type().map_err(LlmError::Io)?.is_dir() {
+                name.push('/');
+            }
+            entries.push(name);
+        }
+        entries.sort();
+        Ok(entries.join("\n"))
+    }
+}
+
+/// `run_command`: run an allowed program directly, without a shell
+pub struct RunCommand {
+    programs: Vec<String>,
+    dir: PathBuf,
+    timeout: Duration,
+}
+
+impl RunCommand {
+    /// Allow `programs`, matched exactly against the name the model gives, run in `dir`
+    pub fn new(programs: impl IntoIterator<Item = impl Into<String>>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            programs: programs.into_iter().map(Into::into).collect(),
+            dir: dir.into(),
+            timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Kill commands that run longer than `timeout` (one minute by default)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Tool for RunCommand {
+    fn name(&self) -> &str {
+        "run_command"
+    }
+
+    fn description(&self) -> &str {
+        "Run a program with arguments in the working directory and return its exit status and output. \
+         No shell is involved, so pipes, redirects and globs do not work."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "command": { "type": "string", "enum": self.programs },
+                "args": { "type": "array", "items": { "type": "string" } },
+            },
+            "required": ["command"],
+        })
+    }
+
+    /// The command line, with arguments containing spaces or quotes quoted
+    fn summary(&self, arguments: &Value) -> String {
+        let args = arguments["args"].as_array().into_iter().flatten().filter_map(Value::as_str);
+        let words: Vec<String> = std::iter::once(string_argument(arguments, "command").unwrap_or_default())
+            .chain(args)
+            .map(|word| if word.is_empty() || word.contains([' ', '\t', '\n', '"', '\'']) { format!("{:?}", word) } else { word.to_string() })
+            .collect();
+        words.join(" ")
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String, LlmError> {
+        let program = string_argument(arguments, "command").unwrap_or_default();
+        if !self.programs.iter().any(|allowed| allowed == program) {
+            return Err(LlmError::InvalidInput(format!("'{}' is not an allowed command", program)));
+        }
+        let args: Vec<&str> = arguments["args"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+
+        let mut child = Command::new(program)
+            .args(&args)
+            .current_dir(&self.dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| LlmError::ProcessSpawn(format!("{}: {}", program, e)))?;
+
+        // Drain both pipes while waiting, so a chatty program cannot block on a full pipe
+        let mut stdout = child.stdout.take().expect("piped");
+        let mut stderr = child.stderr.take().expect("piped");
+        let stdout = thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = stdout.read_to_end(&mut buffer);
+            buffer
+        });
+        let stderr = thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = stderr.read_to_end(&mut buffer);
+            buffer
+        });
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(LlmError::Io)? {
+                break status;
+            }
+            if started.elapsed() > self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(LlmError::ProcessExecution(format!(
+                    "'{}' was killed after {} seconds",
+                    program,
+                    self.timeout.as_secs()
+                )));
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        let stdout = String::from_utf8_lossy(&stdout.join().unwrap_or_default()).to_string();
+        let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).to_string();
+        let code = status.code().map_or("killed by a signal".to_string(), |code| code.to_string());
+        Ok(format!("exit status: {}\nstdout:\n{}\nstderr:\n{}", code, stdout.trim_end(), stderr.trim_end()))
+    }
+}
+
+/// `http_request`: HTTP to allowed ports on this machine only
+pub struct HttpLocalhost {
+    ports: Vec<u16>,
+    agent: ureq::Agent,
+}
+
+impl HttpLocalhost {
+    pub fn new(ports: impl IntoIterator<Item = u16>) -> Self {
+        Self {
+            ports: ports.into_iter().collect(),
+            agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).redirects(0).build(),
+        }
+    }
+
+    /// Check that `url` is plain HTTP to localhost on an allowed port
+    fn check_url(&self, url: &str) -> Result<(), LlmError> {
+        let rejected = || LlmError::InvalidInput(format!("'{}' is not an allowed URL", url));
+        let authority = url.strip_prefix("http://").ok_or_else(rejected)?.split(['/', '?', '#']).next().unwrap_or_default();
+        let (host, port) = authority.rsplit_once(':').ok_or_else(rejected)?;
+        let port: u16 = port.parse().map_err(|_| rejected())?;
+        if matches!(host, "localhost" | "127.0.0.1" | "[::1]") && self.ports.contains(&port) {
+            Ok(())
+        } else {
+            Err(rejected())
+        }
+    }
+}
+
+impl Tool for HttpLocalhost {
+    fn name(&self) -> &str {
+        "http_request"
+    }
+
+    fn description(&self) -> &str {
+        "Send an HTTP request to a service on this machine and return the status and body."
+    }
+
+    fn parameters(&self) -> Value {
+        let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+        json!({
+            "type": "object",
+            "properties": {
+                "method": { "type": "string", "enum": ["GET", "POST", "PUT", "PATCH", "DELETE"] },
+                "url": { "type": "string", "description": format!("http://localhost:<port>/..., where port is one of {}", ports.join(", ")) },
+                "body": { "type": "string" },
+            },
+            "required": ["url"],
+        })
+    }
+
+    fn read_only(&self, arguments: &Value) -> bool {
+        matches!(string_argument(arguments, "method").unwrap_or("GET"), "GET" | "HEAD")
+    }
+
+    fn summary(&self, arguments: &Value) -> String {
+        let method = string_argument(arguments, "method").unwrap_or("GET");
+        format!("{} {}", method, string_argument(arguments, "url").unwrap_or_default())
+    }
+
+    fn call(&self, arguments: &Value) -> Result<String, LlmError> {
+        let url = string_argument(arguments, "url").unwrap_or_default();
+        self.check_url(url)?;
+        let method = string_argument(arguments, "method").unwrap_or("GET");
+        let request = self.agent.request(method, url);
+        let result = match string_argument(arguments, "body") {
+            Some(body) => request.send_string(body),
+            None => request.call(),
+        };
+
+        // Error statuses are answers too; the model should see them
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(LlmError::Http(format!("{}: {}", url, e))),
+        };
+        let status = response.status();
+        let body = response.into_string().map_err(LlmError::Io)?;
+        Ok(format!("HTTP {}\n{}", status, body))
+    }
+}
+
+/// The built-in tools enabled by a set of allowlists; empty lists enable nothing
+pub fn builtin_tools(read_dirs: &[PathBuf], commands: &[String], workdir: &Path, http_ports: &[u16]) -> Vec<Box<dyn Tool>> {
+    let mut tools: Vec<Box<dyn Tool>> = Vec::new();
+    if !read_dirs.is_empty() {
+        tools.push(Box::new(ReadFile::new(read_dirs.to_vec())));
+        tools.push(Box::new(ListDirectory::new(read_dirs.to_vec())));
+    }
+    if !commands.is_empty() {
+        tools.push(Box::new(RunCommand::new(commands.to_vec(), workdir)));
+    }
+    if !http_ports.is_empty() {
+        tools.push(Box::new(HttpLocalhost::new(http_ports.to_vec())));
+    }
+    tools
+}
+
+/// Built-in tools an agent definition allows, under `[tools]`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolAllowlist {
+    /// Directories `read_file` and `list_directory` may look in
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read: Vec<PathBuf>,
+    /// Programs `run_command` may run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+    /// Localhost ports `http_request` may reach
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http: Vec<u16>,
+    /// Directory commands run in; defaults to the current directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<PathBuf>,
+}
+
+impl ToolAllowlist {
+    /// The enabled built-in tools. Relative paths are resolved against the current directory.
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool>>, LlmError> {
+        let workdir = match &self.workdir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir().map_err(LlmError::Io)?,
+        };
+        Ok(builtin_tools(&self.read, &self.commands, &workdir, &self.http))
+    }
+}
+
+/// An agent stored as `<name>.toml` in an agents directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Model or alias; the default model when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Sampling preset from [presets] in config.toml
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// Replaces the built-in system prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_budget: Option<usize>,
+    /// JSON Schema for the final answer, inline or as the path of a JSON file
+    /// relative to the definition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<Value>,
+    #[serde(default)]
+    pub tools: ToolAllowlist,
+    #[serde(default)]
+    pub approval: ApprovalPolicy,
+    /// Sampling parameters, overriding the model's and the preset's
+    #[serde(flatten)]
+    pub params: GenerationParams,
+    /// File the definition was loaded from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+impl AgentDefinition {
+    pub fn from_file(path: &Path) -> Result<Self, LlmError> {
+        let content = fs::read_to_string(path).map_err(LlmError::Io)?;
+        let mut definition: AgentDefinition = toml::from_str(&content)
+            .map_err(|e| LlmError::Config(format!("Invalid agent definition {}: {}", path.display(), e)))?;
+        // Anyone can commit a project definition, so it may restrict calls but never wave them through,
+        // not even reads of directories it picked itself
+        if is_project_definition(path) {
+            definition.approval.ask_instead_of_allow();
+        }
+        definition.path = Some(path.to_path_buf());
+        Ok(definition)
+    }
+
+    /// The output schema, read from its file when given as a path
+    pub fn resolve_output_schema(&self) -> Result<Option<Value>, LlmError> {
+        let schema = match &self.output_schema {
+            Some(Value::String(file)) => {
+                let base_dir = self.path.as_deref().and_then(Path::parent).unwrap_or(Path::new("."));
+                let path = base_dir.join(file);
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| LlmError::Config(format!("Cannot read output schema {}: {}", path.display(), e)))?;
+                serde_json::from_str(&content)
+                    .map_err(|e| LlmError::Config(format!("Output schema {} is not JSON: {}", path.display(), e)))?
+            }
+            Some(schema) => schema.clone(),
+            None => return Ok(None),
+        };
+        schema::check_schema(&schema)?;
+        Ok(Some(schema))
+    }
+
+    /// Open the definition's model and build the agent
+    pub fn open(&self, no_cache: bool) -> Result<Agent, LlmError> {
+        let model = match &self.model {
+            Some(model) => model.clone(),
+            None => config::default_model()?,
+        };
+        // Every step chats with the same model, so keep it loaded between steps
+        let options = OpenOptions {
+            preset: self.preset.clone(),
+            ..OpenOptions::default()
+        };
+        self.build(open_with(&model, &options.no_cache(no_cache).persistent(true))?)
+    }
+
+    /// Build the agent around an already opened model. Approval decisions are
+    /// logged to `~/.agentd/logs/approvals.jsonl`; add an approver to be asked about calls.
+    pub fn build(&self, mut llm: Box<dyn LlmInterface + Send + Sync>) -> Result<Agent, LlmError> {
+        if !self.params.is_empty() {
+            let args = [llm.config().additional_args.clone(), self.params.to_args()].concat();
+            llm = llm.with_args(args);
+        }
+
+        let mut agent = Agent::new(llm)
+            .with_approval_policy(self.approval.clone())
+            .with_approval_log(config::get_approval_log_path());
+        for tool in self.tools.tools()? {
+            agent = agent.with_boxed_tool(tool);
+        }
+        if let Some(system) = &self.system {
+            agent = agent.with_system(system.as_str());
+        }
+        if let Some(max_steps) = self.max_steps {
+            agent = agent.with_max_steps(max_steps);
+        }
+        if let Some(budget) = self.token_budget {
+            agent = agent.with_token_budget(budget);
+        }
+        if let Some(schema) = self.resolve_output_schema()? {
+            agent = agent.with_output_schema(schema);
+        }
+        Ok(agent)
+    }
+}
+
+/// Directories searched for agent definitions, highest precedence first:
+/// `.agentd/agents` in the current directory and each parent, then the user's agents directory
+pub fn definition_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::current_dir()
+        .map(|cwd| cwd.ancestors().map(|dir| dir.join(PROJECT_AGENTS_DIR)).filter(|dir| dir.is_dir()).collect())
+        .unwrap_or_default();
+    dirs.push(config::get_agents_dir());
+    dirs
+}
+
+/// Whether `path` is in a project's `.agentd/agents` rather than the user's agents directory
+fn is_project_definition(path: &Path) -> bool {
+    let Some(dir) = path.parent() else {
+        return false;
+    };
+    let user_dir = config::get_agents_dir();
+    let same_dir = match (dir.canonicalize(), user_dir.canonicalize()) {
+        (Ok(dir), Ok(user_dir)) => dir == user_dir,
+        _ => dir == user_dir,
+    };
+    dir.ends_with(PROJECT_AGENTS_DIR) && !same_dir
+}
+
+/// Path of the definition called `name`; a project definition hides a user definition of the same name
+pub fn find_definition(name: &str) -> Result<PathBuf, LlmError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(LlmError::InvalidInput(format!(
+            "Invalid agent name '{}': use letters, digits, '-', '_' or '.'",
+            name
+        )));
+    }
+    definition_dirs()
+        .into_iter()
+        .map(|dir| dir.join(format!("{}.toml", name)))
+        .find(|path| path.is_file())
+        .ok_or_else(|| LlmError::InvalidInput(format!("No agent named '{}'", name)))
+}
+
+pub fn load_definition(name: &str) -> Result<AgentDefinition, LlmError> {
+    AgentDefinition::from_file(&find_definition(name)?)
+}
+
+/// Definition files that `find_definition` can reach, sorted by name, without parsing them
+pub fn definition_files() -> BTreeMap<String, PathBuf> {
+    let mut files = BTreeMap::new();
+    for dir in definition_dirs() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if path.extension().is_some_and(|ext| ext == "toml") && !files.contains_key(name) {
+                files.insert(name.to_string(), path.clone());
+            }
+        }
+    }
+    files
+}
+
+/// Every definition that `find_definition` can reach, sorted by name
+pub fn list_definitions() -> Result<BTreeMap<String, AgentDefinition>, LlmError> {
+    definition_files()
+        .into_iter()
+        .map(|(name, path)| Ok((name, AgentDefinition::from_file(&path)?)))
+        .collect()
+}
@@ -0,0 +1,185 @@
+use crate::chat::{render_transcript, ChatMessage};
+use crate::error::LlmError;
+use crate::llm::{GenerationParams, LlmInterface, Usage};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// One line of a batch input file: either a `prompt` or chat `messages`, plus optional parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRequest {
+    /// Identifier copied to the result; defaults to `line-<n>`
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub messages: Option<Vec<ChatMessage>>,
+    #[serde(flatten)]
+    pub params: GenerationParams,
+}
+
+impl BatchRequest {
+    /// The prompt text, rendering chat messages as a transcript; usage is estimated from it
+    pub fn prompt_text(&self) -> Result<String, LlmError> {
+        match (&self.prompt, &self.messages) {
+            (Some(prompt), None) => Ok(prompt.clone()),
+            (None, Some(messages)) => Ok(render_transcript(messages)),
+            _ => Err(LlmError::InvalidInput(format!(
+                "Request '{}' must have exactly one of 'prompt' or 'messages'",
+                self.id.as_deref().unwrap_or("?")
+            ))),
+        }
+    }
+}
+
+/// One line of a batch output file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub usage: Usage,
+    pub duration_ms: u64,
+}
+
+/// Parse a JSONL batch file, assigning `line-<n>` ids to requests without one
+pub fn read_requests(path: &Path) -> Result<Vec<BatchRequest>, LlmError> {
+    let file = fs::File::open(path).map_err(LlmError::Io)?;
+    let mut requests = Vec::new();
+    let mut seen = HashSet::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(LlmError::Io)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut request: BatchRequest = serde_json::from_str(&line).map_err(|e| {
+            LlmError::InvalidInput(format!("{}:{}: {}", path.display(), index + 1, e))
+        })?;
+        let id = request.id.get_or_insert_with(|| format!("line-{}", index + 1)).clone();
+
+        if !seen.insert(id.clone()) {
+            return Err(LlmError::InvalidInput(format!(
+                "{}:{}: duplicate id '{}'",
+                path.display(),
+                index + 1,
+                id
+            )));
+        }
+        requests.push(request);
+    }
+
+    Ok(requests)
+}
+
+/// Rewrite an existing output file for `--resume`, keeping one successful result
+/// per id and dropping failures and a truncated last line, so rerun requests
+/// don't leave a second line with the same id. Returns the kept ids.
+pub fn compact_results(path: &Path) -> Result<HashSet<String>, LlmError> {
+    let mut ids = HashSet::new();
+
+    if !path.exists() {
+        return Ok(ids);
+    }
+
+    let mut kept = String::new();
+    for line in fs::read_to_string(path).map_err(LlmError::Io)?.lines() {
+        if let Ok(result) = serde_json::from_str::<BatchResult>(line) {
+            if result.error.is_none() && ids.insert(result.id) {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+    }
+
+    // Write beside the original and rename, so an interruption never loses results
+    let partial = path.with_extension("compacting");
+    fs::write(&partial, kept).map_err(LlmError::Io)?;
+    fs::rename(&partial, path).map_err(LlmError::Io)?;
+    Ok(ids)
+}
+
+/// Run requests against one backend with up to `parallelism` requests in flight.
+///
+/// Results are handed to `on_result` in completion order on the calling thread.
+/// A failed request produces a result with `error` set rather than stopping the batch;
+/// an error returned by `on_result` stops the batch once in-flight requests finish.
+pub fn run_batch<F>(
+    llm: &(dyn LlmInterface + Send + Sync),
+    requests: Vec<BatchRequest>,
+    parallelism: usize,
+    mut on_result: F,
+) -> Result<(), LlmError>
+where
+    F: FnMut(BatchResult) -> Result<(), LlmError>,
+{
+    let workers = parallelism.max(1).min(requests.len().max(1));
+    let queue = Mutex::new(requests.into_iter().collect::<VecDeque<_>>());
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let queue = &queue;
+            scope.spawn(move || loop {
+                let Some(request) = queue.lock().unwrap().pop_front() else {
+                    break;
+                };
+                if sender.send(run_one(llm, request)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        for result in receiver {
+            if let Err(e) = on_result(result) {
+                // Stop handing out new work; running requests finish and are dropped
+                queue.lock().unwrap().clear();
+                return Err(e);
+            }
+        }
+        Ok(())
+    })
+}
+
+fn run_one(llm: &(dyn LlmInterface + Send + Sync), request: BatchRequest) -> BatchResult {
+    let id = request.id.clone().unwrap_or_default();
+    let started = Instant::now();
+
+    // Messages go through `chat`, so a backend with a chat template applies it
+    let outcome = request.prompt_text().and_then(|prompt| {
+        let text = match &request.messages {
+            Some(messages) => llm.chat_with(messages, &request.params)?,
+            None => llm.generate_with(&prompt, &request.params)?,
+        };
+        Ok((prompt, text))
+    });
+
+    let duration_ms = started.elapsed().as_millis() as u64;
+    match outcome {
+        Ok((prompt, text)) => BatchResult {
+            id,
+            usage: Usage::estimate(&prompt, &text),
+            text: Some(text),
+            error: None,
+            duration_ms,
+        },
+        Err(e) => BatchResult {
+            id,
+            text: None,
+            error: Some(e.to_string()),
+            usage: Usage::default(),
+            duration_ms,
+        },
+    }
+}
@@ -0,0 +1,419 @@
+use crate::chat::ChatMessage;
+use crate::config::CacheConfig;
+use crate::error::LlmError;
+use crate::llm::{GenerationParams, LlmConfig, LlmInterface};
+use crate::tools::Tool;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Bytes read from each end of a model file when fingerprinting it
+const FINGERPRINT_SAMPLE_BYTES: u64 = 1024 * 1024;
+
+/// Hex-encoded SHA-256 of the given parts, separated so that ("ab", "c") and ("a", "bc") differ
+pub fn hash_parts(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// llama.cpp prompt-cache files keyed by model and system preamble.
+///
+/// Prompts for the same model with the same leading `System:` block (at most
+/// `prefix_chars` characters of it) share a file, and llama.cpp re-evaluates
+/// only what follows the prefix they have in common with the cached prompt.
+/// Prompts without a system block share one file per model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptCache {
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+    pub prefix_chars: usize,
+}
+
+impl PromptCache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64, prefix_chars: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+            prefix_chars,
+        }
+    }
+
+    /// Prompt cache stored under `~/.agentd/cache/prompts`
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self::new(
+            crate::config::get_cache_dir().join("prompts"),
+            config.prompt_cache_max_mb * 1024 * 1024,
+            config.prompt_prefix_chars,
+        )
+    }
+
+    pub fn key(&self, model_path: &str, prompt: &str) -> String {
+        let preamble: String = system_preamble(prompt).chars().take(self.prefix_chars).collect();
+        hash_parts(&[model_path.as_bytes(), preamble.as_bytes()])
+    }
+
+    /// Path of the cache file for this model and prompt, creating the cache directory if needed
+    pub fn path_for(&self, model_path: &str, prompt: &str) -> Result<PathBuf, LlmError> {
+        fs::create_dir_all(&self.dir).map_err(LlmError::Io)?;
+        Ok(self.dir.join(format!("{}.bin", self.key(model_path, prompt))))
+    }
+
+    /// Mark a cache file as recently used and evict the oldest files beyond the size limit
+    pub fn record_use(&self, path: &Path) -> Result<(), LlmError> {
+        touch(path)?;
+        evict_to_size(&self.dir, self.max_bytes)?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<CacheStats, LlmError> {
+        dir_stats(&self.dir)
+    }
+
+    pub fn clear(&self) -> Result<usize, LlmError> {
+        clear_dir(&self.dir)
+    }
+}
+
+/// The leading system message of a transcript rendered by `render_transcript`, or ""
+fn system_preamble(prompt: &str) -> &str {
+    match prompt.strip_prefix("System: ") {
+        Some(rest) => rest.split("\n\nUser: ").next().unwrap_or_default(),
+        None => "",
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResponse {
+    created_at: u64,
+    response: String,
+}
+
+/// Content-addressed store of responses to deterministic requests, one JSON file per key
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl,
+            max_bytes,
+        }
+    }
+
+    /// Response cache stored under `~/.agentd/cache/responses`
+    pub fn from_config(config: &CacheConfig) -> Self {
+        Self::new(
+            crate::config::get_cache_dir().join("responses"),
+            Duration::from_secs(config.response_cache_ttl_secs),
+            config.response_cache_max_mb * 1024 * 1024,
+        )
+    }
+
+    /// Cache key covering everything that can influence a deterministic response
+    pub fn key(model_fingerprint: &str, executable_path: &str, args: &[String], prompt: &str) -> String {
+        let args = args.join("\0");
+        hash_parts(&[
+            model_fingerprint.as_bytes(),
+            executable_path.as_bytes(),
+            args.as_bytes(),
+            prompt.as_bytes(),
+        ])
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let content = fs::read_to_string(self.entry_path(key)).ok()?;
+        let entry: CachedResponse = serde_json::from_str(&content).ok()?;
+
+        if unix_now().saturating_sub(entry.created_at) > self.ttl.as_secs() {
+            return None;
+        }
+        Some(entry.response)
+    }
+
+    pub fn put(&self, key: &str, response: &str) -> Result<(), LlmError> {
+        fs::create_dir_all(&self.dir).map_err(LlmError::Io)?;
+
+        let entry = CachedResponse {
+            created_at: unix_now(),
+            response: response.to_string(),
+        };
+        fs::write(self.entry_path(key), serde_json::to_string(&entry)?).map_err(LlmError::Io)?;
+
+        evict_to_size(&self.dir, self.max_bytes)?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<CacheStats, LlmError> {
+        dir_stats(&self.dir)
+    }
+
+    pub fn clear(&self) -> Result<usize, LlmError> {
+        clear_dir(&self.dir)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+/// Whether llama.cpp arguments make generation reproducible: temperature 0 or a fixed seed.
+/// Later occurrences of a flag win, matching llama.cpp's own argument parsing.
+pub fn is_deterministic(args: &[String]) -> bool {
+    let mut temperature: Option<f32> = None;
+    let mut seed: Option<i64> = None;
+
+    for pair in args.windows(2) {
+        match pair[0].as_str() {
+            "--temp" | "--temperature" => temperature = pair[1].parse().ok(),
+            "-s" | "--seed" => seed = pair[1].parse().ok(),
+            _ => {}
+        }
+    }
+
+    temperature == Some(0.0) || seed.is_some_and(|seed| seed >= 0)
+}
+
+/// Cheap identity for a model file: its size plus a hash of the first and last megabyte.
+/// Hashing a multi-gigabyte GGUF on every request would cost more than generating.
+pub fn model_fingerprint(path: &Path) -> Result<String, LlmError> {
+    let mut file = fs::File::open(path).map_err(LlmError::Io)?;
+    let size = file.metadata().map_err(LlmError::Io)?.len();
+
+    let mut head = Vec::new();
+    (&mut file).take(FINGERPRINT_SAMPLE_BYTES).read_to_end(&mut head).map_err(LlmError::Io)?;
+
+    let mut tail = Vec::new();
+    if size > FINGERPRINT_SAMPLE_BYTES {
+        file.seek(SeekFrom::End(-(FINGERPRINT_SAMPLE_BYTES as i64))).map_err(LlmError::Io)?;
+        file.read_to_end(&mut tail).map_err(LlmError::Io)?;
+    }
+
+    Ok(hash_parts(&[&size.to_le_bytes(), &head, &tail]))
+}
+
+/// Wraps a backend and answers repeated deterministic requests from a `ResponseCache`
+pub struct CachedBackend {
+    inner: Box<dyn LlmInterface + Send + Sync>,
+    cache: ResponseCache,
+    fingerprint: OnceLock<String>,
+}
+
+impl CachedBackend {
+    pub fn new(inner: Box<dyn LlmInterface + Send + Sync>, cache: ResponseCache) -> Self {
+        Self {
+            inner,
+            cache,
+            fingerprint: OnceLock::new(),
+        }
+    }
+
+    fn cache_key(&self, prompt: &str, params: &GenerationParams) -> Result<Option<String>, LlmError> {
+        let config = self.inner.config();
+        let mut args = config.additional_args.clone();
+        args.extend(params.to_args());
+        if !is_deterministic(&args) {
+            return Ok(None);
+        }
+
+        let fingerprint = match self.fingerprint.get() {
+            Some(fingerprint) => fingerprint,
+            None => {
+                let fingerprint = model_fingerprint(Path::new(&config.model_path))?;
+                self.fingerprint.get_or_init(|| fingerprint)
+            }
+        };
+
+        Ok(Some(ResponseCache::key(fingerprint, &config.executable_path, &args, prompt)))
+    }
+
+    fn cached<F>(&self, prompt: &str, params: &GenerationParams, generate: F) -> Result<String, LlmError>
+    where
+        F: FnOnce() -> Result<String, LlmError>,
+    {
+        let Some(key) = self.cache_key(prompt, params)? else {
+            return generate();
+        };
+
+        if let Some(response) = self.cache.get(&key) {
+            return Ok(response);
+        }
+
+        let response = generate()?;
+        // The response is still valid if it could not be stored
+        let _ = self.cache.put(&key, &response);
+        Ok(response)
+    }
+}
+
+impl CachedBackend {
+    /// Like `cached`, replaying a hit as a single chunk
+    fn cached_stream<F>(&self, key_input: &str, on_chunk: &mut dyn FnMut(&str) -> bool, generate: F) -> Result<String, LlmError>
+    where
+        F: FnOnce(&mut dyn FnMut(&str) -> bool) -> Result<String, LlmError>,
+    {
+        let Some(key) = self.cache_key(key_input, &GenerationParams::default())? else {
+            return generate(on_chunk);
+        };
+
+        if let Some(response) = self.cache.get(&key) {
+            on_chunk(&response);
+            return Ok(response);
+        }
+
+        let mut stopped = false;
+        let response = generate(&mut |chunk| {
+            let more = on_chunk(chunk);
+            stopped |= !more;
+            more
+        })?;
+
+        // A reply cut short by the caller is not the model's full answer
+        if !stopped {
+            let _ = self.cache.put(&key, &response);
+        }
+        Ok(response)
+    }
+}
+
+impl LlmInterface for CachedBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        self.cached(prompt, &GenerationParams::default(), || self.inner.generate(prompt))
+    }
+
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        self.cached(prompt, params, || self.inner.generate_with(prompt, params))
+    }
+
+    fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        self.cached_stream(prompt, on_chunk, |on_chunk| self.inner.generate_stream(prompt, on_chunk))
+    }
+
+    fn chat(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        // Key on the structured messages so a chat never collides with a raw prompt
+        let key_input = serde_json::to_string(messages)?;
+        self.cached(&key_input, &GenerationParams::default(), || self.inner.chat(messages))
+    }
+
+    fn chat_with(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<String, LlmError> {
+        let key_input = serde_json::to_string(messages)?;
+        self.cached(&key_input, params, || self.inner.chat_with(messages, params))
+    }
+
+    fn chat_stream(&self, messages: &[ChatMessage], on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let key_input = serde_json::to_string(messages)?;
+        self.cached_stream(&key_input, on_chunk, |on_chunk| self.inner.chat_stream(messages, on_chunk))
+    }
+
+    /// Tool calls are passed through uncached, keeping the inner backend's own tool support
+    fn chat_with_tools(&self, messages: &[ChatMessage], tools: &[Tool]) -> Result<ChatMessage, LlmError> {
+        self.inner.chat_with_tools(messages, tools)
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+        self.inner.embed(text)
+    }
+
+    fn config(&self) -> &LlmConfig {
+        self.inner.config()
+    }
+
+    fn with_args(mut self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self.inner = self.inner.with_args(args);
+        self
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn touch(path: &Path) -> Result<(), LlmError> {
+    if path.exists() {
+        fs::File::options()
+            .append(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+            .map_err(LlmError::Io)?;
+    }
+    Ok(())
+}
+
+fn cache_files(dir: &Path) -> Result<Vec<(PathBuf, u64, SystemTime)>, LlmError> {
+    let mut files = Vec::new();
+
+    if !dir.exists() {
+        return Ok(files);
+    }
+
+    for entry in fs::read_dir(dir).map_err(LlmError::Io)? {
+        let entry = entry.map_err(LlmError::Io)?;
+        let metadata = entry.metadata().map_err(LlmError::Io)?;
+        if metadata.is_file() {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((entry.path(), metadata.len(), modified));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Delete least recently used files until the directory fits in `max_bytes`
+pub(crate) fn evict_to_size(dir: &Path, max_bytes: u64) -> Result<usize, LlmError> {
+    let mut files = cache_files(dir)?;
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let mut removed = 0;
+
+    files.sort_by_key(|(_, _, modified)| *modified);
+    for (path, size, _) in files {
+        if total <= max_bytes {
+            break;
+        }
+        fs::remove_file(&path).map_err(LlmError::Io)?;
+        total -= size;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+pub(crate) fn dir_stats(dir: &Path) -> Result<CacheStats, LlmError> {
+    let files = cache_files(dir)?;
+    Ok(CacheStats {
+        entries: files.len(),
+        bytes: files.iter().map(|(_, size, _)| size).sum(),
+    })
+}
+
+pub(crate) fn clear_dir(dir: &Path) -> Result<usize, LlmError> {
+    let files = cache_files(dir)?;
+    for (path, _, _) in &files {
+        fs::remove_file(path).map_err(LlmError::Io)?;
+    }
+    Ok(files.len())
+}
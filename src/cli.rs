@@ -1,17 +1,39 @@
-use crate::{discover_models, open, LlmError, config};
+use crate::{open_with, LlmError, OpenOptions, config};
+use crate::{agent, batch, discovery, eval, models, output, prompts, schema, server};
+use crate::cache::{PromptCache, ResponseCache};
+use crate::chat::ChatMessage;
+use crate::llm::{estimate_tokens, GenerationParams};
+use crate::output::{ActionRecord, GenerationRecord, ModelRecord, ModelSource, ModelStatus, OutputFormat, Tabular};
+use crate::repl::Repl;
+use crate::session::{Session, SessionRecord, SessionStore};
 use clap::{Parser, Subcommand, Args};
-use std::io::{self, Read};
+use std::io::{self, IsTerminal, Read, Write};
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
 
 #[derive(Parser)]
 #[command(name = "agentd")]
 #[command(about = "A simple interface for local LLM interaction")]
 #[command(version = "0.1.0")]
 pub struct Cli {
+    /// Override a configuration value for this run (e.g. defaults.temperature=0.2)
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub overrides: Vec<(String, String)>,
+    /// How to print results: human-readable text, a table, JSON or JSON lines
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Plain)]
+    pub format: OutputFormat,
     #[command(subcommand)]
     pub command: Commands,
 }
 
+fn parse_key_value(raw: &str) -> Result<(String, String), String> {
+    raw.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", raw))
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Generate text using a model
@@ -22,14 +44,192 @@ pub enum Commands {
     Download(DownloadArgs),
     /// Show model information
     Info(InfoArgs),
+    /// Chat with a model, optionally in a persistent named session
+    Chat(ChatArgs),
+    /// Manage saved chat sessions
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+    /// Run every request in a JSONL file and write results as JSONL
+    Batch(BatchArgs),
+    /// Run a prompt evaluation suite and report pass rates
+    Eval(EvalArgs),
+    /// Inspect or clear the response and prompt caches
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Register, rename, alias and import models in models.toml
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+    /// Inspect, validate and edit configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Serve models over the Ollama REST API
+    Serve(ServeArgs),
+    /// Run a prompt template with variables filled in
+    Run(RunArgs),
+    /// List and show prompt templates
+    Prompts {
+        #[command(subcommand)]
+        command: PromptsCommand,
+    },
+    /// Let a model work on a task using tools
+    Agent {
+        #[command(subcommand)]
+        command: AgentCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration after merging all layers
+    Show {
+        /// Annotate each value with the layer it came from
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Check config files for unknown keys, invalid values and missing files
+    Validate,
+    /// Print the effective value of a key (e.g. defaults.temperature)
+    Get {
+        key: String,
+    },
+    /// Set a key in the user config, keeping comments and formatting
+    Set {
+        key: String,
+        value: String,
+        /// Write to the project's .agentd.toml instead
+        #[arg(long)]
+        project: bool,
+    },
+    /// Open the user config file in $VISUAL or $EDITOR, then validate it
+    Edit,
+    /// Create the default config files if they don't exist
+    Init {
+        /// Overwrite existing files, keeping a .bak copy
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ModelsCommand {
+    /// Register a model file that is already in the models directory
+    Add {
+        /// Name to register the model under
+        name: String,
+        /// GGUF file, relative to the models directory
+        #[arg(long)]
+        file: String,
+        #[arg(long)]
+        description: Option<String>,
+        #[arg(long)]
+        context_size: Option<u32>,
+    },
+    /// Unregister a model or alias
+    Remove {
+        name: String,
+        /// Also delete the model file from the models directory
+        #[arg(long)]
+        delete_file: bool,
+    },
+    /// Rename a model, updating aliases that point at it
+    Rename {
+        old: String,
+        new: String,
+    },
+    /// Point an alias at a model (e.g. `agentd models alias fast qwen2.5-3b`)
+    Alias {
+        alias: String,
+        model: String,
+    },
+    /// Bring a GGUF file into the models directory and register it
+    Import {
+        /// GGUF file to import
+        path: PathBuf,
+        /// Name to register the model under (defaults to the file name)
+        #[arg(long)]
+        name: Option<String>,
+        /// How to place the file: copy, move, hardlink or symlink
+        #[arg(long, default_value = "copy")]
+        mode: String,
+        #[arg(long)]
+        description: Option<String>,
+        #[arg(long)]
+        context_size: Option<u32>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Show cache sizes
+    Stats,
+    /// Delete all cached responses and prompt-cache files
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum PromptsCommand {
+    /// List templates from the project and user prompts directories
+    List,
+    /// Print a template's file
+    Show {
+        /// Template name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AgentCommand {
+    /// Work on a task until the model answers or a step or token budget runs out
+    Run(Box<AgentRunArgs>),
+    /// List agent definitions from the project and user agents directories
+    List,
+    /// Print an agent definition's file
+    Show {
+        /// Agent name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SessionsCommand {
+    /// List saved sessions
+    List,
+    /// Print a session's message history
+    Show {
+        /// Session name
+        name: String,
+    },
+    /// Delete a saved session
+    Delete {
+        /// Session name
+        name: String,
+    },
+    /// Copy a session's history into a new session
+    Fork {
+        /// Session to copy
+        source: String,
+        /// Name of the new session
+        target: String,
+    },
 }
 
 #[derive(Args)]
 pub struct GenerateArgs {
-    /// Model name to use
-    pub model: String,
-    /// Prompt text
-    pub prompt: String,
+    /// Model name or alias; the default model when no model is given
+    pub model: Option<String>,
+    /// Prompt text (read from stdin if omitted)
+    pub prompt: Option<String>,
+    /// Model name or alias, making the only positional argument the prompt
+    #[arg(long = "model", value_name = "MODEL", conflicts_with = "prompt")]
+    pub model_option: Option<String>,
     /// Temperature (0.0-2.0)
     #[arg(short, long)]
     pub temperature: Option<f32>,
@@ -39,6 +239,148 @@ pub struct GenerateArgs {
     /// Maximum tokens to generate
     #[arg(short, long)]
     pub max_tokens: Option<u32>,
+    /// Sampling preset from [presets] in config.toml (e.g. precise, creative)
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// Constrain the output to a JSON Schema, given inline or as a file, and validate the reply
+    #[arg(long, value_name = "JSON|FILE", conflicts_with = "grammar")]
+    pub schema: Option<String>,
+    /// Constrain the output to the GBNF grammar in this file
+    #[arg(long, value_name = "FILE")]
+    pub grammar: Option<PathBuf>,
+    /// Always run the model, ignoring cached responses
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+#[derive(Args)]
+pub struct ChatArgs {
+    /// Message to send (read from stdin if omitted; on a terminal, starts an interactive chat)
+    pub message: Option<String>,
+    /// Persist the conversation under this session name and resume it on later runs
+    #[arg(short, long)]
+    pub session: Option<String>,
+    /// Model name or alias (defaults to the session's model when resuming, else the default model)
+    #[arg(long)]
+    pub model: Option<String>,
+    /// System prompt for the conversation
+    #[arg(long)]
+    pub system: Option<String>,
+    /// Sampling preset from [presets] in config.toml (e.g. precise, creative)
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// Always run the model, ignoring cached responses
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+#[derive(Args)]
+pub struct BatchArgs {
+    /// Model name or alias to use for every request (defaults to the default model)
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Input file with one JSON request per line
+    pub input: PathBuf,
+    /// Output file for JSONL results
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Number of requests to run concurrently
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub parallel: usize,
+    /// Skip requests that already have a successful result in the output file
+    #[arg(long)]
+    pub resume: bool,
+    /// Always run the model, ignoring cached responses
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+#[derive(Args)]
+pub struct EvalArgs {
+    /// Suite file (TOML)
+    pub suite: PathBuf,
+    /// Run against these models instead of the suite's list (repeatable)
+    #[arg(long = "model")]
+    pub models: Vec<String>,
+    /// Compare against a saved baseline and fail on regressions
+    #[arg(long)]
+    pub baseline: Option<PathBuf>,
+    /// Save this run's results as a baseline
+    #[arg(long)]
+    pub save_baseline: Option<PathBuf>,
+    /// Print the output of failing cases
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Address to listen on; Ollama's default port lets existing clients connect unchanged
+    #[arg(long, default_value = server::DEFAULT_ADDR)]
+    pub addr: String,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Template name, looked up in .agentd/prompts/ and then ~/.agentd/prompts/
+    pub name: String,
+    /// Set a variable (repeatable); NAME=@FILE reads a file and NAME=@- reads stdin
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    pub vars: Vec<String>,
+    /// Model name or alias (defaults to the template's model, else the default model)
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Sampling preset from [presets] in config.toml (e.g. precise, creative)
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// Print the rendered prompt instead of running it
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Always run the model, ignoring cached responses
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+#[derive(Args)]
+pub struct AgentRunArgs {
+    /// Agent definition, looked up in .agentd/agents/ and then ~/.agentd/agents/; without one only the tools allowed by flags are offered
+    pub agent: Option<String>,
+    /// The task; @FILE reads a file and @- standard input (read from stdin if omitted)
+    #[arg(short, long, value_name = "TEXT")]
+    pub input: Option<String>,
+    /// Model name or alias (defaults to the agent's model, else the default model)
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Sampling preset from [presets] in config.toml (e.g. precise, creative)
+    #[arg(long)]
+    pub preset: Option<String>,
+    /// System prompt replacing the agent's
+    #[arg(long)]
+    pub system: Option<String>,
+    /// Let the agent read files and list directories under DIR (repeatable)
+    #[arg(long = "allow-read", value_name = "DIR")]
+    pub allow_read: Vec<PathBuf>,
+    /// Let the agent run PROGRAM, without a shell (repeatable)
+    #[arg(long = "allow-command", value_name = "PROGRAM")]
+    pub allow_command: Vec<String>,
+    /// Let the agent send HTTP requests to PORT on localhost (repeatable)
+    #[arg(long = "allow-http", value_name = "PORT")]
+    pub allow_http: Vec<u16>,
+    /// Directory commands run in (defaults to the agent's, else the current directory)
+    #[arg(long, value_name = "DIR")]
+    pub workdir: Option<PathBuf>,
+    /// Maximum model replies before giving up [default: the agent's, else 10]
+    #[arg(long)]
+    pub max_steps: Option<usize>,
+    /// Stop once this many tokens (estimated) have been used
+    #[arg(long)]
+    pub token_budget: Option<usize>,
+    /// What to do with calls that may change something and that no approval rule covers [default: the agent's, else ask]
+    #[arg(long, value_enum)]
+    pub approve: Option<agent::ApprovalAction>,
+    /// Always run the model, ignoring cached responses
+    #[arg(long)]
+    pub no_cache: bool,
 }
 
 #[derive(Args)]
@@ -55,111 +397,842 @@ pub struct InfoArgs {
 
 pub fn run_cli() -> Result<(), LlmError> {
     let cli = Cli::parse();
+    config::set_cli_overrides(cli.overrides);
+    let format = cli.format;
     
     match cli.command {
-        Commands::Generate(args) => generate_command(args),
-        Commands::List => list_command(),
-        Commands::Download(args) => download_command(args),
-        Commands::Info(args) => info_command(args),
+        Commands::Generate(args) => generate_command(args, format),
+        Commands::List => list_command(format),
+        Commands::Download(args) => download_command(args, format),
+        Commands::Info(args) => info_command(args, format),
+        Commands::Chat(args) => chat_command(args, format),
+        Commands::Sessions { command } => sessions_command(command, format),
+        Commands::Batch(args) => batch_command(args, format),
+        Commands::Eval(args) => eval_command(args, format),
+        Commands::Cache { command } => cache_command(command, format),
+        Commands::Models { command } => models_command(command, format),
+        Commands::Config { command } => config_command(command, format),
+        Commands::Serve(args) => serve_command(args, format),
+        Commands::Run(args) => run_command(args, format),
+        Commands::Prompts { command } => prompts_command(command, format),
+        Commands::Agent { command } => agent_command(command, format),
     }
 }
 
-fn generate_command(args: GenerateArgs) -> Result<(), LlmError> {
-    let mut llm = open(&args.model)?;
-    
-    let mut cli_args = Vec::new();
-
-    if let Some(temp) = args.temperature {
-        cli_args.extend_from_slice(&["--temp".to_string(), temp.to_string()]);
-    }
-    if let Some(top_p) = args.top_p {
-        cli_args.extend_from_slice(&["--top-p".to_string(), top_p.to_string()]);
-    }
-    if let Some(max_tokens) = args.max_tokens {
-        cli_args.extend_from_slice(&["--n-predict".to_string(), max_tokens.to_string()]);
-    }
+fn generate_command(args: GenerateArgs, format: OutputFormat) -> Result<(), LlmError> {
+    let options = OpenOptions {
+        preset: args.preset.clone(),
+        ..OpenOptions::default()
+    };
+    // After --model the positional argument is the prompt
+    let (model, prompt) = match (args.model_option, args.model, args.prompt) {
+        (Some(model), prompt, _) | (None, Some(model), prompt) => (model, prompt.unwrap_or_default()),
+        (None, None, _) => (config::default_model()?, String::new()),
+    };
+    let llm = open_with(&model, &options.no_cache(args.no_cache))?;
 
-    if !cli_args.is_empty() {
-        llm = llm.with_args(cli_args);
+    // Flags given on the command line take precedence over the model and preset
+    let json_schema = match &args.schema {
+        Some(schema) if schema.trim_start().starts_with('{') => Some(serde_json::from_str(schema)?),
+        Some(path) => Some(serde_json::from_str(&fs::read_to_string(path).map_err(LlmError::Io)?)?),
+        None => None,
+    };
+    if let Some(schema) = &json_schema {
+        schema::check_schema(schema)?;
     }
+    let grammar = match &args.grammar {
+        Some(path) => Some(fs::read_to_string(path).map_err(LlmError::Io)?),
+        None => None,
+    };
+    let params = GenerationParams {
+        temperature: args.temperature,
+        top_p: args.top_p,
+        max_tokens: args.max_tokens,
+        grammar,
+        json_schema,
+        ..GenerationParams::default()
+    };
 
-    let prompt = if args.prompt.is_empty() {
+    let prompt = if prompt.is_empty() {
         let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer).map_err(|e| LlmError::Io(e))?;
+        io::stdin().read_to_string(&mut buffer).map_err(LlmError::Io)?;
         buffer
     } else {
-        args.prompt.clone()
+        prompt
     };
     
-    let response = llm.generate(&prompt)?;
+    let started = Instant::now();
+    let response = llm.generate_with(&prompt, &params)?;
+    let mut record = GenerationRecord::new(&model, estimate_tokens(&prompt), &response, started.elapsed());
+    if let Some(schema) = &params.json_schema {
+        record.json = Some(schema::parse_reply(&response, schema)?);
+    }
     
-    println!("{}", response.trim());
+    output::print_record(format, &record, || println!("{}", record.text))
+}
+
+fn chat_command(args: ChatArgs, format: OutputFormat) -> Result<(), LlmError> {
+    let store = SessionStore::open_default();
+    let session_name = args.session.as_deref().unwrap_or("default");
+    let resuming = args.session.is_some() && store.exists(session_name);
+
+    let model = match (&args.model, resuming) {
+        (Some(model), _) => model.clone(),
+        (None, true) => store.load(session_name)?.model,
+        (None, false) => config::default_model()?,
+    };
+
+    // With no message on a terminal, chat interactively, keeping the model loaded between turns
+    let interactive = args.message.is_none() && format.is_plain() && io::stdin().is_terminal();
+    let options = OpenOptions {
+        preset: args.preset.clone(),
+        ..OpenOptions::default()
+    }
+    .no_cache(args.no_cache)
+    .persistent(interactive);
+    let llm = open_with(&model, &options)?;
+    let mut session = if resuming {
+        // --model switches an existing session to a different model
+        let mut record = store.load(session_name)?;
+        record.model = model.clone();
+        Session::from_record(store, record, llm)
+    } else if args.session.is_some() {
+        Session::new(store, session_name, &model, llm)?
+    } else {
+        // Not saved unless /save names it, so a saved session called "default" doesn't get in the way
+        Session::from_record(store, SessionRecord::new(session_name, &model), llm)
+    };
+
+    let entry = config::find_model_entry(&model)?;
+    let agent_config = config::load_config()?;
+    let reply_reserve = args
+        .preset
+        .as_ref()
+        .and_then(|preset| agent_config.presets.get(preset))
+        .and_then(|preset| preset.max_tokens)
+        .or(entry.params.max_tokens)
+        .unwrap_or(agent_config.defaults.max_tokens);
+    session = session
+        .with_context_size(entry.context_size)
+        .with_reply_reserve(reply_reserve);
+
+    if let Some(system) = args.system {
+        session.set_system(system);
+    }
+
+    if interactive {
+        return Repl::new(session, options, args.session.is_some()).run();
+    }
+
+    let message = match args.message {
+        Some(message) => message,
+        None => {
+            let mut buffer = String::new();
+            io::stdin().read_to_string(&mut buffer).map_err(LlmError::Io)?;
+            buffer
+        }
+    };
+
+    let started = Instant::now();
+    let (prompt_tokens, reply) = if args.session.is_some() {
+        let prompt_tokens = prompt_tokens(&session.context_window()) + estimate_tokens(&message);
+        (prompt_tokens, session.send(&message)?)
+    } else {
+        // Without --session the conversation is a single throwaway turn
+        let mut messages = session.messages().to_vec();
+        messages.push(ChatMessage::user(message));
+        (prompt_tokens(&messages), session.llm().chat(&messages)?)
+    };
+
+    let mut record = GenerationRecord::new(&model, prompt_tokens, &reply, started.elapsed());
+    record.session = args.session;
+    output::print_record(format, &record, || println!("{}", record.text))
+}
+
+/// Estimated tokens in the messages sent to the model
+fn prompt_tokens(messages: &[ChatMessage]) -> usize {
+    messages.iter().map(|message| estimate_tokens(&message.content)).sum()
+}
+
+fn run_command(args: RunArgs, format: OutputFormat) -> Result<(), LlmError> {
+    let vars = args
+        .vars
+        .iter()
+        .map(|raw| prompts::parse_var(raw))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    let rendered = prompts::render(&args.name, &vars)?;
+
+    if args.dry_run {
+        if let Some(system) = &rendered.system {
+            println!("[system]");
+            println!("{}", system.trim());
+            println!();
+            println!("[user]");
+        }
+        println!("{}", rendered.prompt.trim());
+        return Ok(());
+    }
+
+    let model = match args.model.or(rendered.model) {
+        Some(model) => model,
+        None => config::default_model()?,
+    };
+    let options = OpenOptions {
+        preset: args.preset,
+        ..OpenOptions::default()
+    };
+    let mut llm = open_with(&model, &options.no_cache(args.no_cache))?;
+    // The template's parameters take precedence over the model and preset
+    if !rendered.params.is_empty() {
+        let args = [llm.config().additional_args.clone(), rendered.params.to_args()].concat();
+        llm = llm.with_args(args);
+    }
+
+    let started = Instant::now();
+    let (prompt_tokens, reply) = match rendered.system {
+        Some(system) => {
+            let messages = [ChatMessage::system(system), ChatMessage::user(rendered.prompt)];
+            (prompt_tokens(&messages), llm.chat(&messages)?)
+        }
+        None => (estimate_tokens(&rendered.prompt), llm.generate(&rendered.prompt)?),
+    };
+
+    let record = GenerationRecord::new(&model, prompt_tokens, &reply, started.elapsed());
+    output::print_record(format, &record, || println!("{}", record.text))
+}
+
+fn agent_command(command: AgentCommand, format: OutputFormat) -> Result<(), LlmError> {
+    let args = match command {
+        AgentCommand::Run(args) => args,
+        AgentCommand::List => {
+            let agents: Vec<output::AgentRecord> = agent::list_definitions()?
+                .iter()
+                .map(|(name, definition)| output::AgentRecord::new(name, definition))
+                .collect();
+            return output::print_list(format, &agents, || {
+                if agents.is_empty() {
+                    println!("No agents. Add one as {}/<name>.toml", config::get_agents_dir().display());
+                }
+                for agent in &agents {
+                    let tools = if agent.tools.is_empty() { String::new() } else { format!(" [{}]", agent.tools.join(", ")) };
+                    println!("  {}{} - {}", agent.name, tools, agent.description.as_deref().unwrap_or("No description"));
+                }
+            });
+        }
+        AgentCommand::Show { name } => {
+            let path = agent::find_definition(&name)?;
+            let definition = agent::AgentDefinition::from_file(&path)?;
+            let record = output::AgentRecord::new(&name, &definition);
+            return output::print_record(format, &record, || {
+                println!("# {}", path.display());
+                print!("{}", fs::read_to_string(&path).unwrap_or_default());
+            });
+        }
+    };
+
+    // Flags take precedence over the definition; allowlists add to it
+    let mut definition = match &args.agent {
+        Some(name) => agent::load_definition(name)?,
+        None => agent::AgentDefinition::default(),
+    };
+    definition.model = args.model.or(definition.model);
+    definition.preset = args.preset.or(definition.preset);
+    definition.system = args.system.or(definition.system);
+    definition.max_steps = args.max_steps.or(definition.max_steps);
+    definition.token_budget = args.token_budget.or(definition.token_budget);
+    definition.tools.workdir = args.workdir.or(definition.tools.workdir);
+    definition.tools.read.extend(args.allow_read);
+    definition.tools.commands.extend(args.allow_command);
+    definition.tools.http.extend(args.allow_http);
+    if let Some(action) = args.approve {
+        definition.approval.default = action;
+    }
+    let runner = definition.open(args.no_cache)?.with_approver(agent::TerminalApprover);
+
+    let task = match args.input.as_deref().map(|input| (input, input.strip_prefix('@'))) {
+        Some((input, None)) => input.to_string(),
+        Some((_, Some(path))) if path != "-" => fs::read_to_string(path)
+            .map_err(|e| LlmError::InvalidInput(format!("Cannot read input {}: {}", path, e)))?,
+        _ => {
+            let mut buffer = String::new();
+            io::stdin().read_to_string(&mut buffer).map_err(LlmError::Io)?;
+            buffer
+        }
+    };
+
+    // Tool activity goes to stderr so stdout holds only the answer
+    let run = runner.run_with_events(&task, &mut |event| match event {
+        agent::AgentEvent::ToolCall(call) => eprintln!("-> {} {}", call.name, call.arguments),
+        agent::AgentEvent::ToolResult(result) => {
+            let first_line = result.output.lines().next().unwrap_or_default();
+            let lines = result.output.lines().count();
+            let more = if lines > 1 { format!(" (+{} lines)", lines - 1) } else { String::new() };
+            eprintln!("<- {}{}", first_line, more);
+        }
+    })?;
+
+    output::print_record(format, &run, || match (run.stop_reason, &run.output) {
+        (agent::StopReason::Answer, Some(output)) => {
+            println!("{}", serde_json::to_string_pretty(output).unwrap_or_default())
+        }
+        (agent::StopReason::Answer, None) => println!("{}", run.answer),
+        (agent::StopReason::MaxSteps, _) => eprintln!("Stopped after {} steps without an answer", run.steps),
+        (agent::StopReason::TokenBudget, _) => {
+            eprintln!("Stopped after using {} of the token budget without an answer", run.usage.total_tokens)
+        }
+    })
+}
+
+fn prompts_command(command: PromptsCommand, format: OutputFormat) -> Result<(), LlmError> {
+    match command {
+        PromptsCommand::List => {
+            let templates: Vec<output::PromptRecord> = prompts::list_templates()?
+                .iter()
+                .map(|(name, template)| output::PromptRecord::new(name, template))
+                .collect();
+            output::print_list(format, &templates, || {
+                if templates.is_empty() {
+                    println!("No prompt templates. Add one as {}/<name>.toml", config::get_prompts_dir().display());
+                }
+                for template in &templates {
+                    let vars = if template.vars.is_empty() { String::new() } else { format!(" [{}]", template.vars.join(", ")) };
+                    println!("  {}{} - {}", template.name, vars, template.description.as_deref().unwrap_or("No description"));
+                }
+            })
+        }
+        PromptsCommand::Show { name } => {
+            let path = prompts::find_template(&name)?;
+            let template = prompts::PromptTemplate::from_file(&path)?;
+            let record = output::PromptRecord::new(&name, &template);
+            output::print_record(format, &record, || {
+                println!("# {}", path.display());
+                print!("{}", fs::read_to_string(&path).unwrap_or_default());
+            })
+        }
+    }
+}
+
+fn sessions_command(command: SessionsCommand, format: OutputFormat) -> Result<(), LlmError> {
+    let store = SessionStore::open_default();
+
+    match command {
+        SessionsCommand::List => {
+            let sessions = store.list()?;
+            output::print_list(format, &sessions, || {
+                if sessions.is_empty() {
+                    println!("No saved sessions. Start one with: agentd chat --session <name> --model <model>");
+                }
+                for summary in &sessions {
+                    println!("  {} - {} ({} messages)", summary.name, summary.model, summary.message_count);
+                }
+            })
+        }
+        SessionsCommand::Show { name } => {
+            let record = store.load(&name)?;
+            output::print_record(format, &record, || {
+                println!("Session: {}", record.name);
+                println!("Model: {}", record.model);
+                for message in &record.messages {
+                    println!();
+                    println!("[{}]", message.role.as_str());
+                    println!("{}", message.content.trim());
+                }
+            })
+        }
+        SessionsCommand::Delete { name } => {
+            store.delete(&name)?;
+            output::print_action(format, ActionRecord::new("deleted", &name, format!("Deleted session: {}", name)))
+        }
+        SessionsCommand::Fork { source, target } => {
+            store.fork(&source, &target)?;
+            let message = format!("Forked session '{}' into '{}'", source, target);
+            output::print_action(format, ActionRecord::new("forked", target, message))
+        }
+    }
+}
+
+fn batch_command(args: BatchArgs, format: OutputFormat) -> Result<(), LlmError> {
+    let mut requests = batch::read_requests(&args.input)?;
+    let total = requests.len();
+
+    if args.resume {
+        let done = batch::compact_results(&args.output)?;
+        requests.retain(|r| !r.id.as_ref().is_some_and(|id| done.contains(id)));
+        eprintln!("Resuming: {} of {} requests already completed", total - requests.len(), total);
+    }
+
+    // The backend is opened once and shared by all workers; a llama.cpp model
+    // stays loaded in one server process for the whole batch
+    let model = match args.model {
+        Some(model) => model,
+        None => config::default_model()?,
+    };
+    let llm = open_with(&model, &OpenOptions::default().no_cache(args.no_cache).persistent(true))?;
+
+    let mut output = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(args.resume)
+        .truncate(!args.resume)
+        .open(&args.output)
+        .map_err(LlmError::Io)?;
+
+    let mut summary = output::BatchSummary {
+        output: args.output.clone(),
+        total,
+        skipped: total - requests.len(),
+        succeeded: 0,
+        failed: 0,
+    };
+    batch::run_batch(llm.as_ref(), requests, args.parallel, |result| {
+        if let Some(error) = &result.error {
+            summary.failed += 1;
+            eprintln!("{}: {}", result.id, error);
+        } else {
+            summary.succeeded += 1;
+        }
+        let line = serde_json::to_string(&result)?;
+        writeln!(output, "{}", line).map_err(LlmError::Io)?;
+        output.flush().map_err(LlmError::Io)
+    })?;
+
+    // Results are in the output file; plain output has nothing more to say
+    output::print_record(format, &summary, || {})?;
+    if summary.failed > 0 {
+        return Err(LlmError::ProcessExecution(format!(
+            "{} requests failed; rerun with --resume to retry them",
+            summary.failed
+        )));
+    }
+
     Ok(())
 }
 
-fn list_command() -> Result<(), LlmError> {
-    let discovered = discover_models()?;
+fn serve_command(args: ServeArgs, format: OutputFormat) -> Result<(), LlmError> {
+    let server = server::Server::bind(&args.addr)?;
+    let addr = server.local_addr().map(|addr| addr.to_string()).unwrap_or(args.addr);
+    let url = format!("http://{}", addr);
+    let message = format!("Serving the Ollama API on {}", url);
+    if format.is_plain() {
+        eprintln!("{}", message);
+    } else {
+        output::print_action(format, ActionRecord::new("serving", url, message))?;
+        io::stdout().flush().map_err(LlmError::Io)?;
+    }
+    server.run();
+    Ok(())
+}
+
+fn eval_command(args: EvalArgs, format: OutputFormat) -> Result<(), LlmError> {
+    let mut suite = eval::load_suite(&args.suite)?;
+    if !args.models.is_empty() {
+        suite.models = args.models;
+    }
+
+    // Evaluations measure the model, so cached responses are never used
+    let report = eval::run_suite(&suite, |model| open_with(model, &OpenOptions::default().no_cache(true)))?;
+    let baseline = args.baseline.as_ref().map(|path| eval::load_baseline(path)).transpose()?;
+    let regressions = baseline.as_ref().map(|baseline| report.regressions(baseline)).unwrap_or_default();
+
+    match format {
+        OutputFormat::Plain => {
+            for result in &report.results {
+                let status = if result.passed { "✓" } else { "✗" };
+                println!("  {} {} [{}] ({} ms)", status, result.case, result.model, result.duration_ms);
+                for failure in &result.failures {
+                    println!("      {}", failure);
+                }
+                if args.verbose && !result.passed {
+                    if let Some(output) = &result.output {
+                        println!("      output: {}", output.trim());
+                    }
+                }
+            }
+
+            println!();
+            for (model, (passed, total)) in report.pass_rates() {
+                println!("{}: {}/{} passed ({:.0}%)", model, passed, total, 100.0 * passed as f64 / total.max(1) as f64);
+            }
+        }
+        OutputFormat::Json => {
+            let summary = output::EvalSummary::new(&report, baseline.is_some().then_some(&regressions[..]));
+            output::print_json(format, &summary)?;
+        }
+        // One row or line per case
+        OutputFormat::Table | OutputFormat::Jsonl => output::print_list(format, &report.results, || {})?,
+    }
+
+    // Only plain output has room for status lines on stdout
+    let status = |line: String| if format.is_plain() { println!("{}", line) } else { eprintln!("{}", line) };
+
+    if let Some(path) = &args.save_baseline {
+        eval::save_baseline(&report, path)?;
+        status(format!("Saved baseline to {}", path.display()));
+    }
+
+    if let Some(path) = &args.baseline {
+        if !regressions.is_empty() {
+            status(String::new());
+            status(format!("Regressions versus {}:", path.display()));
+            for result in &regressions {
+                status(format!("  {}", result.key()));
+            }
+            return Err(LlmError::ProcessExecution(format!("{} regressions", regressions.len())));
+        }
+        status(format!("No regressions versus {}", path.display()));
+    }
+
+    Ok(())
+}
+
+fn cache_command(command: CacheCommand, format: OutputFormat) -> Result<(), LlmError> {
     let config = config::load_config()?;
-    
-    if discovered.is_empty() && config.models.is_empty() {
-        println!("No models found. Download a model with: agentd download <model-name>");
-        return Ok(());
+    let responses = ResponseCache::from_config(&config.cache);
+    let prompts = PromptCache::from_config(&config.cache);
+
+    match command {
+        CacheCommand::Stats => {
+            let caches = [
+                output::CacheRecord::new("responses", &responses.stats()?, config.cache.response_cache_max_mb, config.cache.response_cache),
+                output::CacheRecord::new("prompt_cache", &prompts.stats()?, config.cache.prompt_cache_max_mb, config.cache.prompt_cache),
+            ];
+            output::print_list(format, &caches, || {
+                let enabled = |cache: &output::CacheRecord| if cache.enabled { "enabled" } else { "disabled" };
+                let [response_stats, prompt_stats] = &caches;
+                println!(
+                    "Responses: {} entries, {} (limit {} MB, {})",
+                    response_stats.entries,
+                    format_bytes(response_stats.bytes),
+                    response_stats.limit_mb,
+                    enabled(response_stats)
+                );
+                println!(
+                    "Prompt cache: {} files, {} (limit {} MB, {})",
+                    prompt_stats.entries,
+                    format_bytes(prompt_stats.bytes),
+                    prompt_stats.limit_mb,
+                    enabled(prompt_stats)
+                );
+            })
+        }
+        CacheCommand::Clear => {
+            let removed_responses = responses.clear()?;
+            let removed_prompts = prompts.clear()?;
+            let message = format!("Removed {} cached responses and {} prompt-cache files", removed_responses, removed_prompts);
+            let actions: Vec<ActionRecord> = [
+                ("responses", removed_responses, "cached responses"),
+                ("prompt_cache", removed_prompts, "prompt-cache files"),
+            ]
+            .into_iter()
+            .map(|(cache, removed, what)| ActionRecord {
+                count: Some(removed),
+                ..ActionRecord::new("cleared", cache, format!("Removed {} {}", removed, what))
+            })
+            .collect();
+            output::print_list(format, &actions, || println!("{}", message))
+        }
     }
-    
-    println!("Available models:");
-    println!();
-    
-    // Show configured models first
-    for (name, entry) in &config.models {
-        let status = if config::resolve_model_path(name).is_ok() { "✓" } else { "✗" };
-        println!("  {} {} - {}", status, name, entry.description.as_deref().unwrap_or("No description"));
+}
+
+fn models_command(command: ModelsCommand, format: OutputFormat) -> Result<(), LlmError> {
+    let action = match command {
+        ModelsCommand::Add { name, file, description, context_size } => {
+            let entry = config::ModelEntry { file, description, context_size, ..config::ModelEntry::default() };
+            models::add_model(&name, &entry)?;
+            ActionRecord::new("registered", &name, format!("Registered '{}'", name))
+        }
+        ModelsCommand::Remove { name, delete_file } => {
+            models::remove_model(&name, delete_file)?;
+            ActionRecord::new("removed", &name, format!("Removed '{}'", name))
+        }
+        ModelsCommand::Rename { old, new } => {
+            models::rename_model(&old, &new)?;
+            ActionRecord::new("renamed", &new, format!("Renamed '{}' to '{}'", old, new))
+        }
+        ModelsCommand::Alias { alias, model } => {
+            models::set_alias(&alias, &model)?;
+            ActionRecord::new("aliased", &alias, format!("'{}' now refers to '{}'", alias, model))
+        }
+        ModelsCommand::Import { path, name, mode, description, context_size } => {
+            let mode = models::ImportMode::parse(&mode)?;
+            let (name, target) = models::import_model(&path, name.as_deref(), mode, description, context_size)?;
+            ActionRecord::new("imported", &name, format!("Imported '{}' to {}", name, target.display()))
+        }
+    };
+
+    output::print_action(format, action)
+}
+
+/// Look up a dotted key such as `defaults.temperature` in a config table
+fn config_value<'a>(values: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = values.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
     }
-    
-    // Show discovered models
-    for (name, entry) in &discovered {
-        if !config.models.contains_key(name) {
-            let status = if config::resolve_model_path(name).is_ok() { "✓" } else { "✗" };
-            println!("  {} {} - {}", status, name, entry.description.as_deref().unwrap_or("Auto-discovered"));
+    Some(value)
+}
+
+fn config_command(command: ConfigCommand, format: OutputFormat) -> Result<(), LlmError> {
+    match command {
+        ConfigCommand::Show { origin } => {
+            let layered = config::load_layered_config()?;
+            match format {
+                OutputFormat::Plain if !origin => {
+                    let rendered = toml::to_string_pretty(&layered.values)
+                        .map_err(|e| LlmError::Config(e.to_string()))?;
+                    print!("{}", rendered);
+                    return Ok(());
+                }
+                OutputFormat::Json | OutputFormat::Jsonl if !origin => return output::print_json(format, &layered.values),
+                _ => {}
+            }
+
+            let records: Vec<output::ConfigValueRecord> = layered
+                .origins
+                .iter()
+                .filter_map(|(key, source)| match config_value(&layered.values, key) {
+                    Some(toml::Value::Table(_)) | None => None,
+                    Some(value) => Some(output::ConfigValueRecord::new(key, value, source)),
+                })
+                .collect();
+            output::print_list(format, &records, || {
+                let width = records.iter().map(|record| record.key.len()).max().unwrap_or(0);
+                for record in &records {
+                    println!("{:width$} = {:<24} # {}", record.key, record.value.to_string(), record.source, width = width);
+                }
+            })?;
+        }
+        ConfigCommand::Validate => {
+            let issues = config::validate_config()?;
+            output::print_list(format, &issues, || {
+                if issues.is_empty() {
+                    println!("Configuration is valid");
+                }
+                for issue in &issues {
+                    eprintln!("✗ {}", issue);
+                }
+            })?;
+            if !issues.is_empty() {
+                return Err(LlmError::Config(format!("{} problem(s) found", issues.len())));
+            }
+        }
+        ConfigCommand::Get { key } => {
+            let layered = config::load_layered_config()?;
+            let value = match config_value(&layered.values, &key) {
+                Some(toml::Value::Table(_)) | None => return Err(LlmError::Config(format!("'{}' is not set", key))),
+                Some(value) => value,
+            };
+            let source = layered.origins.get(&key).map(ToString::to_string).unwrap_or_default();
+            let record = output::ConfigValueRecord::new(&key, value, source);
+            output::print_record(format, &record, || match value {
+                toml::Value::String(text) => println!("{}", text),
+                value => println!("{}", value),
+            })?;
+        }
+        ConfigCommand::Set { key, value, project } => {
+            let (path, is_models_file) = if project {
+                let path = config::find_project_config()
+                    .unwrap_or_else(|| PathBuf::from(config::PROJECT_CONFIG_FILE));
+                (path, false)
+            } else if key.starts_with("models.") {
+                (config::get_models_registry_path(), true)
+            } else {
+                (config::get_config_path(), false)
+            };
+            config::set_config_value(&path, &key, &value, is_models_file)?;
+            let message = format!("Set {} = {} in {}", key, value, path.display());
+            output::print_action(format, ActionRecord::new("set", key, message))?;
+        }
+        ConfigCommand::Edit => {
+            let path = config::get_config_path();
+            if !path.exists() {
+                config::init_config(&config::get_config_dir(), false)?;
+            }
+
+            let editor = std::env::var("VISUAL")
+                .or_else(|_| std::env::var("EDITOR"))
+                .unwrap_or_else(|_| "vi".to_string());
+            // The editor setting may carry arguments, e.g. "code --wait"
+            let mut parts = editor.split_whitespace();
+            let program = parts.next().unwrap_or("vi");
+            let status = std::process::Command::new(program)
+                .args(parts)
+                .arg(&path)
+                .status()
+                .map_err(|e| LlmError::ProcessSpawn(format!("Failed to start editor '{}': {}", editor, e)))?;
+            if !status.success() {
+                return Err(LlmError::ProcessExecution(format!("Editor exited with {}", status)));
+            }
+
+            let issues = config::validate_config()?;
+            output::print_list(format, &issues, || {
+                for issue in &issues {
+                    eprintln!("✗ {}", issue);
+                }
+            })?;
+            if !issues.is_empty() {
+                return Err(LlmError::Config(format!("{} problem(s) found; run `agentd config edit` to fix", issues.len())));
+            }
+        }
+        ConfigCommand::Init { force } => {
+            let actions: Vec<ActionRecord> = config::init_config(&config::get_config_dir(), force)?
+                .into_iter()
+                .map(|(path, written)| {
+                    let target = path.display().to_string();
+                    if written {
+                        ActionRecord::new("created", &target, format!("Created {}", target))
+                    } else {
+                        ActionRecord::new("kept", &target, format!("Kept existing {}", target))
+                    }
+                })
+                .collect();
+            output::print_list(format, &actions, || {
+                for action in &actions {
+                    println!("{}", action.message);
+                }
+            })?;
         }
     }
-    
+
     Ok(())
 }
 
-fn download_command(args: DownloadArgs) -> Result<(), LlmError> {
+fn format_bytes(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= MB {
+        format!("{:.1} MB", bytes as f64 / MB)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+fn list_command(format: OutputFormat) -> Result<(), LlmError> {
+    let config = config::load_config()?;
+    let scan = discovery::scan(&config.discovery)?;
+    for warning in &scan.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    let records = models::list_models(&config, &scan.models);
+
+    output::print_list(format, &records, || {
+        if records.is_empty() {
+            println!("No models found. Download a model with: agentd download <model-name>");
+            return;
+        }
+
+        println!("Available models:");
+        println!();
+        for record in &records {
+            let status = if record.status == ModelStatus::Missing { "✗" } else { "✓" };
+            let marker = if record.default { " (default)" } else { "" };
+            let fallback = match record.source {
+                ModelSource::Registered => "No description",
+                ModelSource::Discovered => "Auto-discovered",
+            };
+            println!("  {} {}{} - {}", status, record.name, marker, record.description.as_deref().unwrap_or(fallback));
+        }
+
+        if !config.aliases.is_empty() {
+            println!();
+            println!("Aliases:");
+            for (alias, model) in &config.aliases {
+                println!("  {} -> {}", alias, model);
+            }
+        }
+    })
+}
+
+/// A model that `agentd download` knows how to fetch
+#[derive(serde::Serialize)]
+struct DownloadRecord<'a> {
+    model: &'a str,
+    repo: &'a str,
+    filename: &'a str,
+    url: &'a str,
+    /// Command that downloads the file into the models directory
+    command: String,
+}
+
+impl Tabular for DownloadRecord<'_> {
+    fn headers() -> &'static [&'static str] {
+        &["MODEL", "REPO", "FILENAME", "URL", "COMMAND"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.model.to_string(),
+            self.repo.to_string(),
+            self.filename.to_string(),
+            self.url.to_string(),
+            self.command.clone(),
+        ]
+    }
+}
+
+fn download_command(args: DownloadArgs, format: OutputFormat) -> Result<(), LlmError> {
     // Predefined model download URLs
     let model_urls = get_model_urls();
     
-    if let Some(url_info) = model_urls.get(&args.model) {
-        println!("Downloading model: {}", args.model);
-        println!("This would download from: {}", url_info.url);
-        println!("File: {}", url_info.filename);
-        // Placeholder for actual download logic
-        // For example, using huggingface-cli
-        println!("Run this command to download:");
-        println!("huggingface-cli download {} {} --local-dir ~/.agentd/models/",
-                 url_info.repo, url_info.filename);
-    } else {
+    let Some(url_info) = model_urls.get(&args.model) else {
         return Err(LlmError::InvalidModelPath(format!("Unknown model: {}", args.model)));
-    }
-    
-    Ok(())
+    };
+
+    // Placeholder for actual download logic
+    // For example, using huggingface-cli
+    let record = DownloadRecord {
+        model: &args.model,
+        repo: url_info.repo,
+        filename: url_info.filename,
+        url: url_info.url,
+        command: format!(
+            "huggingface-cli download {} {} --local-dir {}",
+            url_info.repo,
+            url_info.filename,
+            config::get_models_dir().display()
+        ),
+    };
+    output::print_record(format, &record, || {
+        println!("Downloading model: {}", record.model);
+        println!("This would download from: {}", record.url);
+        println!("File: {}", record.filename);
+        println!("Run this command to download:");
+        println!("{}", record.command);
+    })
 }
 
-fn info_command(args: InfoArgs) -> Result<(), LlmError> {
+fn info_command(args: InfoArgs, format: OutputFormat) -> Result<(), LlmError> {
     let config = config::load_config()?;
-    let discovered = discover_models()?;
-    
-    let model_entry = config.models.get(&args.model)
-        .or_else(|| discovered.get(&args.model))
-        .ok_or_else(|| LlmError::InvalidModelPath(format!("Model '{}' not found", args.model)))?;
-    
-    let model_path = config::resolve_model_path(&args.model)?;
-    
+    let name = config::resolve_alias(&config, &args.model);
+    let model_entry = config::find_model_entry(name)?;
+    let source = if config.models.contains_key(name) { ModelSource::Registered } else { ModelSource::Discovered };
+    let mut record = ModelRecord::new(name, &model_entry, source);
+    record.default = config::default_model().ok().as_deref().map(|model| config::resolve_alias(&config, model)) == Some(name);
+    record.aliases = config.aliases.iter().filter(|(_, model)| *model == name).map(|(alias, _)| alias.clone()).collect();
+
+    if !format.is_plain() {
+        return output::print_record(format, &record, || {});
+    }
+
     println!("Model: {}", args.model);
-    println!("File: {}", model_entry.file);
-    println!("Path: {}", model_path.display());
+    if let Some(base_url) = &model_entry.base_url {
+        println!("URL: {}", base_url);
+        println!("Remote model: {}", model_entry.remote_model.as_deref().unwrap_or(&args.model));
+    } else {
+        let model_path = config::resolve_model_path(&args.model)?;
+        println!("File: {}", model_entry.file);
+        println!("Path: {}", model_path.display());
+    }
     println!("Description: {}", model_entry.description.as_deref().unwrap_or("N/A"));
+    if !model_entry.params.is_empty() {
+        println!("Parameters: {}", model_entry.params.to_args().join(" "));
+    }
+    if !model_entry.args.is_empty() {
+        println!("Extra args: {}", model_entry.args.join(" "));
+    }
+    if let Some(mmproj) = &model_entry.mmproj {
+        println!("Projector: {}", config::get_models_dir().join(mmproj).display());
+    }
     
     Ok(())
 }
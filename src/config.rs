@@ -1,29 +1,122 @@
 use crate::error::LlmError;
+use crate::llm::GenerationParams;
+use crate::recording::CassetteMode;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
     pub runtime: RuntimeConfig,
     pub models: HashMap<String, ModelEntry>,
     pub defaults: DefaultParams,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// Alternative names for models, e.g. `fast = "qwen2.5-3b-instruct-q4_k_m"`
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Named sampling parameter sets, selected with `--preset` or `OpenOptions::preset`
+    #[serde(default)]
+    pub presets: BTreeMap<String, GenerationParams>,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuntimeConfig {
     pub default_backend: String,
+    /// Model or alias used when a command or `agentd.open()` doesn't name one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_model: Option<String>,
     pub llama_executable: String,
     pub use_gpu: bool,
     pub gpu_layers: Option<u32>,
+    /// Record to or replay from this cassette file (see `agentd::recording`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cassette: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cassette_mode: Option<CassetteMode>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModelEntry {
+    /// GGUF file, relative to the models directory; unused by remote models
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub file: String,
     pub description: Option<String>,
+    /// Context window in tokens, passed to llama.cpp as `--ctx-size`
+    #[serde(alias = "ctx_size")]
     pub context_size: Option<u32>,
+    /// CPU threads, passed to llama.cpp as `--threads`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+    /// Sampling defaults for this model, overriding `[defaults]`
+    #[serde(flatten)]
+    pub params: GenerationParams,
+    /// Extra llama.cpp arguments for this model
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Multimodal projector file, passed to llama.cpp as `--mmproj`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmproj: Option<String>,
+    /// Backend for this model, overriding `runtime.default_backend`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Base URL of an OpenAI-compatible API, e.g. `http://gpu-box:8000/v1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Environment variable holding the API key for `base_url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// Model name sent to the remote API, if it differs from the entry's name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_model: Option<String>,
+}
+
+impl ModelEntry {
+    /// The backend that serves this model: its own `backend`, `openai` when it
+    /// has a `base_url`, otherwise `runtime.default_backend`
+    pub fn backend_name<'a>(&'a self, runtime: &'a RuntimeConfig) -> &'a str {
+        match (&self.backend, &self.base_url) {
+            (Some(backend), _) => backend,
+            (None, Some(_)) => "openai",
+            (None, None) => &runtime.default_backend,
+        }
+    }
+
+    /// Served over HTTP rather than from a local file
+    pub fn is_remote(&self) -> bool {
+        self.base_url.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// Directories searched for GGUF models after the models directory, e.g. a shared /opt/models
+    pub roots: Vec<PathBuf>,
+    /// Also search subdirectories
+    pub recursive: bool,
+    /// Index models pulled with Ollama (`$OLLAMA_MODELS` or ~/.ollama/models)
+    pub ollama: bool,
+    /// Index models downloaded with LM Studio
+    pub lm_studio: bool,
+    /// Index GGUF files in the Hugging Face hub cache
+    pub huggingface: bool,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            recursive: true,
+            ollama: false,
+            lm_studio: false,
+            huggingface: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +127,36 @@ pub struct DefaultParams {
     pub max_tokens: u32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Reuse llama.cpp prompt state across prompts that share a prefix
+    pub prompt_cache: bool,
+    /// Size limit for ~/.agentd/cache/prompts before least recently used files are evicted
+    pub prompt_cache_max_mb: u64,
+    /// Characters of the system prompt that tell cache files apart
+    pub prompt_prefix_chars: usize,
+    /// Reuse responses for temperature-0 or fixed-seed requests
+    pub response_cache: bool,
+    /// Age after which a cached response is ignored and regenerated
+    pub response_cache_ttl_secs: u64,
+    /// Size limit for ~/.agentd/cache/responses before the oldest entries are evicted
+    pub response_cache_max_mb: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            prompt_cache: false,
+            prompt_cache_max_mb: 2048,
+            prompt_prefix_chars: 8192,
+            response_cache: true,
+            response_cache_ttl_secs: 7 * 24 * 60 * 60,
+            response_cache_max_mb: 256,
+        }
+    }
+}
+
 impl Default for AgentConfig {
     fn default() -> Self {
         let (gpu_support, gpu_layers) = detect_gpu_support();
@@ -41,9 +164,12 @@ impl Default for AgentConfig {
         Self {
             runtime: RuntimeConfig {
                 default_backend: "llama.cpp".to_string(),
+                default_model: None,
                 llama_executable: "llama-cli".to_string(),
                 use_gpu: gpu_support,
                 gpu_layers,
+                cassette: None,
+                cassette_mode: None,
             },
             models: HashMap::new(),
             defaults: DefaultParams {
@@ -52,51 +178,904 @@ impl Default for AgentConfig {
                 repeat_penalty: 1.1,
                 max_tokens: 256,
             },
+            cache: CacheConfig::default(),
+            aliases: BTreeMap::new(),
+            discovery: DiscoveryConfig::default(),
+            presets: BTreeMap::from([
+                (
+                    "precise".to_string(),
+                    GenerationParams {
+                        temperature: Some(0.1),
+                        top_p: Some(0.5),
+                        ..GenerationParams::default()
+                    },
+                ),
+                (
+                    "creative".to_string(),
+                    GenerationParams {
+                        temperature: Some(1.0),
+                        top_p: Some(0.95),
+                        ..GenerationParams::default()
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+/// Where a configuration value came from, lowest precedence first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigOrigin {
+    Default,
+    System(PathBuf),
+    User(PathBuf),
+    Project(PathBuf),
+    Env(String),
+    Cli,
+}
+
+impl std::fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigOrigin::Default => write!(f, "default"),
+            ConfigOrigin::System(path) => write!(f, "system ({})", path.display()),
+            ConfigOrigin::User(path) => write!(f, "user ({})", path.display()),
+            ConfigOrigin::Project(path) => write!(f, "project ({})", path.display()),
+            ConfigOrigin::Env(var) => write!(f, "env ({})", var),
+            ConfigOrigin::Cli => write!(f, "command line"),
         }
     }
 }
 
+/// The merged configuration plus the origin of every value, keyed by dotted path
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    pub config: AgentConfig,
+    pub values: toml::Table,
+    pub origins: BTreeMap<String, ConfigOrigin>,
+}
+
+/// Name of the project-local config file looked up from the current directory upwards
+pub const PROJECT_CONFIG_FILE: &str = ".agentd.toml";
+
+/// Sections that can be set through `AGENTD_<SECTION>_<KEY>` environment variables
+const ENV_SECTIONS: &[&str] = &["runtime", "defaults", "cache"];
+
+static CLI_OVERRIDES: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+fn env_path(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// `$<var>/agentd` unless AGENTD_HOME is set or `legacy` (the matching directory of an
+/// older ~/.agentd install) exists. Each variable is decided on its own, so creating
+/// ~/.agentd for data never moves the config or cache directories.
+fn xdg_dir(var: &str, legacy: &str) -> Option<PathBuf> {
+    if env_path("AGENTD_HOME").is_some() {
+        return None;
+    }
+    if dirs::home_dir().is_some_and(|home| home.join(".agentd").join(legacy).exists()) {
+        return None;
+    }
+    env_path(var).map(|dir| dir.join("agentd"))
+}
+
+/// Root of agentd's data: `AGENTD_HOME`, else `~/.agentd`, else `$XDG_DATA_HOME/agentd`.
+/// Without a home directory it falls back to a directory under the system temp dir.
 pub fn get_agentd_home() -> PathBuf {
-    dirs::home_dir()
-        .expect("Could not find home directory")
-        .join(".agentd")
+    if let Some(home) = env_path("AGENTD_HOME") {
+        return home;
+    }
+    if let Some(dir) = xdg_dir("XDG_DATA_HOME", "") {
+        return dir;
+    }
+    match dirs::home_dir() {
+        Some(home) => home.join(".agentd"),
+        None => std::env::temp_dir().join("agentd"),
+    }
 }
 
+/// Directory holding config.toml and models.toml: the directory of `AGENTD_CONFIG`,
+/// else `$XDG_CONFIG_HOME/agentd`, else `<agentd home>/config`
 pub fn get_config_dir() -> PathBuf {
+    if let Some(path) = env_path("AGENTD_CONFIG") {
+        if path.is_dir() {
+            return path;
+        }
+        return path.parent().map(PathBuf::from).unwrap_or_default();
+    }
+    if let Some(dir) = xdg_dir("XDG_CONFIG_HOME", "config") {
+        return dir;
+    }
     get_agentd_home().join("config")
 }
 
+/// The user config file; `AGENTD_CONFIG` may name it directly
+pub fn get_config_path() -> PathBuf {
+    match env_path("AGENTD_CONFIG") {
+        Some(path) if !path.is_dir() => path,
+        _ => get_config_dir().join("config.toml"),
+    }
+}
+
+pub fn get_models_registry_path() -> PathBuf {
+    get_config_dir().join("models.toml")
+}
+
+/// System-wide configuration directory, normally /etc/agentd
+pub fn get_system_config_dir() -> PathBuf {
+    env_path("AGENTD_SYSTEM_CONFIG_DIR").unwrap_or_else(|| PathBuf::from("/etc/agentd"))
+}
+
 pub fn get_models_dir() -> PathBuf {
-    get_agentd_home().join("models")
+    env_path("AGENTD_MODELS_DIR").unwrap_or_else(|| get_agentd_home().join("models"))
+}
+
+pub fn get_cache_dir() -> PathBuf {
+    if let Some(dir) = xdg_dir("XDG_CACHE_HOME", "cache") {
+        return dir;
+    }
+    get_agentd_home().join("cache")
+}
+
+pub fn get_sessions_dir() -> PathBuf {
+    get_agentd_home().join("sessions")
+}
+
+/// User prompt templates; see `agentd::prompts`
+pub fn get_prompts_dir() -> PathBuf {
+    get_agentd_home().join("prompts")
+}
+
+/// User agent definitions; see `agentd::agent::AgentDefinition`
+pub fn get_agents_dir() -> PathBuf {
+    get_agentd_home().join("agents")
+}
+
+/// Every tool-call approval decision made by agents, one JSON object per line
+pub fn get_approval_log_path() -> PathBuf {
+    get_agentd_home().join("logs").join("approvals.jsonl")
+}
+
+/// Nearest `.agentd.toml` in the current directory or one of its parents
+pub fn find_project_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Register `section.key=value` overrides from the command line; they apply to
+/// every later `load_config` call in this process
+pub fn set_cli_overrides(overrides: Vec<(String, String)>) {
+    let _ = CLI_OVERRIDES.set(overrides);
 }
 
 pub fn load_config() -> Result<AgentConfig, LlmError> {
-    let config_path = get_config_dir().join("config.toml");
-    let models_path = get_config_dir().join("models.toml");
-    
-    let mut config = if config_path.exists() {
-        let content = fs::read_to_string(&config_path)
-            .map_err(|e| LlmError::Io(e))?;
-        
-        toml::from_str(&content)
-            .map_err(|e| LlmError::ProcessExecution(format!("Failed to parse config: {}", e)))?
+    Ok(load_layered_config()?.config)
+}
+
+/// Merge every configuration layer, lowest precedence first: built-in defaults,
+/// /etc/agentd, the user config directory, the nearest `.agentd.toml`,
+/// `AGENTD_<SECTION>_<KEY>` environment variables, then command-line overrides
+pub fn load_layered_config() -> Result<LayeredConfig, LlmError> {
+    let mut defaults = toml::Table::try_from(AgentConfig::default())
+        .map_err(|e| LlmError::Config(format!("Failed to serialize defaults: {}", e)))?;
+    tidy_floats(&mut defaults);
+
+    let mut values = toml::Table::new();
+    let mut origins = BTreeMap::new();
+    merge_layer(&mut values, &defaults, "", &ConfigOrigin::Default, &mut origins);
+
+    let system_dir = get_system_config_dir();
+    let user_dir = get_config_dir();
+    let file_layers = [
+        (system_dir.join("config.toml"), false, ConfigOrigin::System(system_dir.join("config.toml"))),
+        (system_dir.join("models.toml"), true, ConfigOrigin::System(system_dir.join("models.toml"))),
+        (get_config_path(), false, ConfigOrigin::User(get_config_path())),
+        (user_dir.join("models.toml"), true, ConfigOrigin::User(user_dir.join("models.toml"))),
+    ];
+
+    for (path, is_models_file, origin) in &file_layers {
+        if let Some(layer) = read_layer(path, *is_models_file)? {
+            merge_layer(&mut values, &layer, "", origin, &mut origins);
+        }
+    }
+
+    if let Some(path) = find_project_config() {
+        if let Some(layer) = read_layer(&path, false)? {
+            merge_layer(&mut values, &layer, "", &ConfigOrigin::Project(path.clone()), &mut origins);
+        }
+    }
+
+    let mut env_vars: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("AGENTD_"))
+        .collect();
+    env_vars.sort();
+    for (name, raw) in env_vars {
+        let suffix = name["AGENTD_".len()..].to_lowercase();
+        let Some(section) = ENV_SECTIONS
+            .iter()
+            .find(|section| suffix.starts_with(&format!("{}_", section)))
+        else {
+            continue;
+        };
+        let key = format!("{}.{}", section, &suffix[section.len() + 1..]);
+        set_value(&mut values, &key, &raw, &defaults, &ConfigOrigin::Env(name.clone()), &mut origins)?;
+    }
+
+    for (key, raw) in CLI_OVERRIDES.get().into_iter().flatten() {
+        set_value(&mut values, key, raw, &defaults, &ConfigOrigin::Cli, &mut origins)?;
+    }
+
+    let config = toml::Value::Table(values.clone())
+        .try_into()
+        .map_err(|e| LlmError::Config(format!("Invalid configuration: {}", e)))?;
+
+    Ok(LayeredConfig { config, values, origins })
+}
+
+fn read_layer(path: &Path, is_models_file: bool) -> Result<Option<toml::Table>, LlmError> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(path).map_err(LlmError::Io)?;
+    let table: toml::Table = toml::from_str(&content)
+        .map_err(|e| LlmError::Config(format!("Failed to parse {}: {}", path.display(), e)))?;
+
+    if is_models_file {
+        // models.toml holds model entries at the top level
+        let mut wrapped = toml::Table::new();
+        wrapped.insert("models".to_string(), toml::Value::Table(table));
+        return Ok(Some(wrapped));
+    }
+    Ok(Some(table))
+}
+
+fn merge_layer(
+    target: &mut toml::Table,
+    layer: &toml::Table,
+    prefix: &str,
+    origin: &ConfigOrigin,
+    origins: &mut BTreeMap<String, ConfigOrigin>,
+) {
+    for (key, value) in layer {
+        let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+
+        match (target.get_mut(key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(incoming)) => {
+                merge_layer(existing, incoming, &path, origin, origins);
+            }
+            _ => {
+                if let toml::Value::Table(incoming) = value {
+                    // A new table: record the origin of each value inside it
+                    let mut fresh = toml::Table::new();
+                    merge_layer(&mut fresh, incoming, &path, origin, origins);
+                    target.insert(key.clone(), toml::Value::Table(fresh));
+                } else {
+                    target.insert(key.clone(), value.clone());
+                    origins.insert(path, origin.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Set a dotted key from a string, typed like the built-in default when there is one
+fn set_value(
+    values: &mut toml::Table,
+    key: &str,
+    raw: &str,
+    defaults: &toml::Table,
+    origin: &ConfigOrigin,
+    origins: &mut BTreeMap<String, ConfigOrigin>,
+) -> Result<(), LlmError> {
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.len() < 2 || parts.iter().any(|part| part.is_empty()) {
+        return Err(LlmError::Config(format!("Invalid config key '{}': expected section.key", key)));
+    }
+
+    let value = parse_value(raw, lookup(defaults, &parts));
+
+    let mut table = values;
+    for part in &parts[..parts.len() - 1] {
+        table = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| LlmError::Config(format!("Config key '{}' is not a table", part)))?;
+    }
+    table.insert(parts[parts.len() - 1].to_string(), value);
+    origins.insert(key.to_string(), origin.clone());
+    Ok(())
+}
+
+/// Defaults are stored as f32; widen them so 0.7 shows as 0.7 rather than 0.699999988079071
+fn tidy_floats(table: &mut toml::Table) {
+    for (_, value) in table.iter_mut() {
+        match value {
+            toml::Value::Float(number) => {
+                *number = (*number as f32).to_string().parse().unwrap_or(*number);
+            }
+            toml::Value::Table(inner) => tidy_floats(inner),
+            _ => {}
+        }
+    }
+}
+
+fn lookup<'a>(table: &'a toml::Table, parts: &[&str]) -> Option<&'a toml::Value> {
+    let (last, sections) = parts.split_last()?;
+    let mut table = table;
+    for part in sections {
+        table = table.get(*part)?.as_table()?;
+    }
+    table.get(*last)
+}
+
+/// Interpret a string as the same type as `like`, or guess bool/integer/float/string
+fn parse_value(raw: &str, like: Option<&toml::Value>) -> toml::Value {
+    match like {
+        Some(toml::Value::String(_)) => return toml::Value::String(raw.to_string()),
+        Some(toml::Value::Float(_)) => {
+            if let Ok(number) = raw.parse::<f64>() {
+                return toml::Value::Float(number);
+            }
+        }
+        _ => {}
+    }
+
+    // Arrays and inline tables are written as TOML, e.g. ["/opt/models"]
+    if raw.trim_start().starts_with(['[', '{']) {
+        if let Ok(mut table) = toml::from_str::<toml::Table>(&format!("value = {}", raw)) {
+            if let Some(value) = table.remove("value") {
+                return value;
+            }
+        }
+    }
+
+    if let Ok(flag) = raw.parse::<bool>() {
+        toml::Value::Boolean(flag)
+    } else if let Ok(number) = raw.parse::<i64>() {
+        toml::Value::Integer(number)
+    } else if let Ok(number) = raw.parse::<f64>() {
+        toml::Value::Float(number)
+    } else {
+        toml::Value::String(raw.to_string())
+    }
+}
+
+/// Keys accepted in each config section; anything else is reported by `validate_config`
+const SECTION_KEYS: &[(&str, &[&str])] = &[
+    (
+        "runtime",
+        &["default_backend", "default_model", "llama_executable", "use_gpu", "gpu_layers", "cassette", "cassette_mode"],
+    ),
+    ("defaults", &["temperature", "top_p", "repeat_penalty", "max_tokens"]),
+    ("discovery", &["roots", "recursive", "ollama", "lm_studio", "huggingface"]),
+    (
+        "cache",
+        &[
+            "prompt_cache",
+            "prompt_cache_max_mb",
+            "prompt_prefix_chars",
+            "response_cache",
+            "response_cache_ttl_secs",
+            "response_cache_max_mb",
+        ],
+    ),
+];
+
+/// Sampling keys accepted in a preset, and in a model entry
+const PARAM_KEYS: &[&str] = &["temperature", "top_p", "repeat_penalty", "max_tokens", "seed", "grammar", "json_schema"];
+
+/// Keys accepted in a model entry besides the sampling keys
+const MODEL_KEYS: &[&str] = &[
+    "file",
+    "description",
+    "context_size",
+    "ctx_size",
+    "threads",
+    "args",
+    "mmproj",
+    "backend",
+    "base_url",
+    "api_key_env",
+    "remote_model",
+];
+
+/// Top-level keys of an agent definition, besides the sampling parameters
+const AGENT_KEYS: &[&str] = &["description", "model", "preset", "system", "max_steps", "token_budget", "output_schema", "tools", "approval"];
+
+/// Keys of an agent definition's `[tools]` table
+const AGENT_TOOL_KEYS: &[&str] = &["read", "commands", "http", "workdir"];
+
+/// Keys of an `[[approval.rules]]` entry
+const APPROVAL_RULE_KEYS: &[&str] = &["tool", "arguments", "action"];
+
+/// Template written by `agentd config init`
+pub const DEFAULT_CONFIG_TEMPLATE: &str = r#"[runtime]
+default_backend = "llama.cpp"
+# Model or alias used when none is given
+# default_model = "my-model"
+llama_executable = "llama-cli"
+use_gpu = false
+gpu_layers = 0
+
+[models]
+# Models will be auto-discovered in ~/.agentd/models/
+# You can also add custom model definitions here
+
+[defaults]
+temperature = 0.7
+top_p = 0.9
+repeat_penalty = 1.1
+max_tokens = 256
+"#;
+
+/// Template written by `agentd config init`
+pub const DEFAULT_MODELS_TEMPLATE: &str = r#"# Model Registry
+# Models are automatically discovered, but you can override settings here
+# Add your model configurations below following this format:
+#
+# [your-model-name]
+# file = "your-model-file.gguf"
+# description = "Description of your model"
+# context_size = 4096
+"#;
+
+/// A problem found by `validate_config`, attributed to the file or layer it came from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigIssue {
+    pub source: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.source, self.message)
+    }
+}
+
+/// Whether `section.key` (or `models.<name>.key`) is a recognised setting
+pub fn is_known_key(key: &str) -> bool {
+    let parts: Vec<&str> = key.split('.').collect();
+    match parts.as_slice() {
+        ["models", _, field] => MODEL_KEYS.contains(field) || PARAM_KEYS.contains(field),
+        ["presets", _, field] => PARAM_KEYS.contains(field),
+        ["aliases", _] => true,
+        [section, field] => SECTION_KEYS
+            .iter()
+            .any(|(name, keys)| name == section && keys.contains(field)),
+        _ => false,
+    }
+}
+
+/// Strictly check every config file and the effective configuration: parse
+/// errors, unknown keys, wrongly typed or out-of-range values, a missing
+/// llama.cpp executable and models whose files don't exist
+pub fn validate_config() -> Result<Vec<ConfigIssue>, LlmError> {
+    let system_dir = get_system_config_dir();
+    let user_dir = get_config_dir();
+    let mut files = vec![
+        (system_dir.join("config.toml"), false),
+        (system_dir.join("models.toml"), true),
+        (get_config_path(), false),
+        (user_dir.join("models.toml"), true),
+    ];
+    if let Some(path) = find_project_config() {
+        files.push((path, false));
+    }
+
+    let mut issues = Vec::new();
+    for (path, is_models_file) in &files {
+        if !path.is_file() {
+            continue;
+        }
+        let content = fs::read_to_string(path).map_err(LlmError::Io)?;
+        for message in check_file_content(&content, *is_models_file) {
+            issues.push(ConfigIssue {
+                source: path.display().to_string(),
+                message,
+            });
+        }
+    }
+
+    let agent_files = crate::agent::definition_files();
+    for path in agent_files.values() {
+        let content = fs::read_to_string(path).map_err(LlmError::Io)?;
+        for message in check_agent_content(&content) {
+            issues.push(ConfigIssue {
+                source: path.display().to_string(),
+                message,
+            });
+        }
+    }
+
+    // Files that don't parse would only repeat the same errors below
+    if !issues.is_empty() {
+        return Ok(issues);
+    }
+
+    let layered = load_layered_config()?;
+    let source_of = |key: &str| {
+        layered
+            .origins
+            .get(key)
+            .map(ToString::to_string)
+            .unwrap_or_else(|| "config".to_string())
+    };
+
+    for (key, message) in range_issues(&layered.config) {
+        issues.push(ConfigIssue { source: source_of(&key), message });
+    }
+
+    let runtime = &layered.config.runtime;
+    if matches!(runtime.default_backend.as_str(), "llama.cpp" | "llamacpp")
+        && find_executable(&runtime.llama_executable).is_none()
+    {
+        issues.push(ConfigIssue {
+            source: source_of("runtime.llama_executable"),
+            message: format!("llama_executable '{}' was not found", runtime.llama_executable),
+        });
+    }
+
+    if let Some(model) = &runtime.default_model {
+        if find_model_entry(model).is_err() {
+            issues.push(ConfigIssue {
+                source: source_of("runtime.default_model"),
+                message: format!("default_model '{}' is not a known model or alias", model),
+            });
+        }
+    }
+    for (alias, model) in &layered.config.aliases {
+        if !layered.config.models.contains_key(model) && find_model_entry(model).is_err() {
+            issues.push(ConfigIssue {
+                source: source_of(&format!("aliases.{}", alias)),
+                message: format!("alias '{}' points at unknown model '{}'", alias, model),
+            });
+        }
+    }
+
+    let models_dir = get_models_dir();
+    let mut names: Vec<&String> = layered.config.models.keys().collect();
+    names.sort();
+    for name in names {
+        let entry = &layered.config.models[name];
+        if entry.backend_name(runtime) == "openai" {
+            if entry.base_url.is_none() {
+                issues.push(ConfigIssue {
+                    source: source_of(&format!("models.{}.backend", name)),
+                    message: format!("model '{}' uses the openai backend but has no base_url", name),
+                });
+            }
+            if let Some(var) = entry.api_key_env.as_ref().filter(|var| std::env::var_os(var).is_none()) {
+                issues.push(ConfigIssue {
+                    source: source_of(&format!("models.{}.api_key_env", name)),
+                    message: format!("model '{}' reads its API key from {}, which is not set", name, var),
+                });
+            }
+            continue;
+        }
+
+        let path = models_dir.join(&entry.file);
+        if !path.exists() {
+            issues.push(ConfigIssue {
+                source: source_of(&format!("models.{}.file", name)),
+                message: format!("model '{}' points at missing file {}", name, path.display()),
+            });
+        }
+    }
+
+    for path in agent_files.values() {
+        let definition = crate::agent::AgentDefinition::from_file(path)?;
+        let mut messages = Vec::new();
+        if let Some(model) = definition.model.as_ref().filter(|model| find_model_entry(model).is_err()) {
+            messages.push(format!("model '{}' is not a known model or alias", model));
+        }
+        if let Some(preset) = definition.preset.as_ref().filter(|preset| !layered.config.presets.contains_key(*preset)) {
+            messages.push(format!("preset '{}' is not defined in [presets]", preset));
+        }
+        if let Err(e) = definition.resolve_output_schema() {
+            messages.push(e.to_string());
+        }
+        issues.extend(messages.into_iter().map(|message| ConfigIssue {
+            source: path.display().to_string(),
+            message,
+        }));
+    }
+
+    Ok(issues)
+}
+
+/// Parse errors, unknown keys and out-of-range values in an agent definition
+fn check_agent_content(content: &str) -> Vec<String> {
+    let table: toml::Table = match toml::from_str(content) {
+        Ok(table) => table,
+        Err(e) => return vec![format!("parse error: {}", e.message())],
+    };
+
+    let mut messages = Vec::new();
+    for (key, value) in &table {
+        if key == "tools" {
+            let Some(tools) = value.as_table() else {
+                messages.push("'tools' should be a [tools] section".to_string());
+                continue;
+            };
+            for field in tools.keys().filter(|field| !AGENT_TOOL_KEYS.contains(&field.as_str())) {
+                messages.push(format!("unknown key 'tools.{}'", field));
+            }
+        } else if key == "approval" {
+            let Some(approval) = value.as_table() else {
+                messages.push("'approval' should be an [approval] section".to_string());
+                continue;
+            };
+            for field in approval.keys().filter(|field| *field != "default" && *field != "rules") {
+                messages.push(format!("unknown key 'approval.{}'", field));
+            }
+            let rules = approval.get("rules").and_then(toml::Value::as_array).into_iter().flatten();
+            for field in rules.filter_map(toml::Value::as_table).flat_map(|rule| rule.keys()) {
+                if !APPROVAL_RULE_KEYS.contains(&field.as_str()) {
+                    messages.push(format!("unknown key '{}' in an approval rule", field));
+                }
+            }
+        } else if !AGENT_KEYS.contains(&key.as_str()) && !PARAM_KEYS.contains(&key.as_str()) {
+            messages.push(format!("unknown key '{}'", key));
+        }
+    }
+
+    match toml::Value::Table(table).try_into::<crate::agent::AgentDefinition>() {
+        Ok(definition) => {
+            messages.extend(param_issues("", &definition.params).into_iter().map(|(_, message)| message));
+            if definition.max_steps == Some(0) {
+                messages.push("max_steps must be greater than 0".to_string());
+            }
+            if definition.output_schema.as_ref().is_some_and(|schema| !schema.is_object() && !schema.is_string()) {
+                messages.push("output_schema should be a table or the path of a JSON file".to_string());
+            }
+        }
+        Err(e) => messages.push(format!("invalid value: {}", e.message())),
+    }
+    messages
+}
+
+/// Parse errors, unknown keys, type errors and out-of-range values in one file's content
+fn check_file_content(content: &str, is_models_file: bool) -> Vec<String> {
+    let mut table: toml::Table = match toml::from_str(content) {
+        Ok(table) => table,
+        Err(e) => return vec![format!("parse error: {}", e.message())],
+    };
+    if is_models_file {
+        let mut wrapped = toml::Table::new();
+        wrapped.insert("models".to_string(), toml::Value::Table(table));
+        table = wrapped;
+    }
+
+    let mut messages = Vec::new();
+    for (section, value) in &table {
+        let Some(inner) = value.as_table() else {
+            messages.push(format!("'{}' should be a [{}] section", section, section));
+            continue;
+        };
+        if section == "models" || section == "presets" {
+            let kind = if section == "models" { "model" } else { "preset" };
+            for (name, entry) in inner {
+                for field in entry.as_table().into_iter().flat_map(|entry| entry.keys()) {
+                    if !is_known_key(&format!("{}.{}.{}", section, name, field)) {
+                        messages.push(format!("unknown key '{}' in {} '{}'", field, kind, name));
+                    }
+                }
+            }
+        } else if section == "aliases" {
+            // Any alias name is allowed; the values are type-checked below
+        } else if SECTION_KEYS.iter().any(|(name, _)| name == section) {
+            for field in inner.keys() {
+                if !is_known_key(&format!("{}.{}", section, field)) {
+                    messages.push(format!("unknown key '{}.{}'", section, field));
+                }
+            }
+        } else {
+            messages.push(format!("unknown section [{}]", section));
+        }
+    }
+
+    let Ok(mut merged) = toml::Table::try_from(AgentConfig::default()) else {
+        return messages;
+    };
+    let mut origins = BTreeMap::new();
+    merge_layer(&mut merged, &table, "", &ConfigOrigin::Default, &mut origins);
+    match toml::Value::Table(merged).try_into::<AgentConfig>() {
+        Ok(config) => messages.extend(range_issues(&config).into_iter().map(|(_, message)| message)),
+        Err(e) => messages.push(format!("invalid value: {}", e.message())),
+    }
+
+    messages
+}
+
+/// Values that parse but make no sense, keyed by the setting they belong to
+fn range_issues(config: &AgentConfig) -> Vec<(String, String)> {
+    let defaults = &config.defaults;
+    let mut issues = param_issues(
+        "defaults",
+        &GenerationParams {
+            temperature: Some(defaults.temperature),
+            top_p: Some(defaults.top_p),
+            repeat_penalty: Some(defaults.repeat_penalty),
+            max_tokens: Some(defaults.max_tokens),
+            ..GenerationParams::default()
+        },
+    );
+
+    let mut models: Vec<_> = config.models.iter().collect();
+    models.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, entry) in models {
+        issues.extend(param_issues(&format!("models.{}", name), &entry.params));
+    }
+    for (name, preset) in &config.presets {
+        issues.extend(param_issues(&format!("presets.{}", name), preset));
+    }
+    issues
+}
+
+fn param_issues(prefix: &str, params: &GenerationParams) -> Vec<(String, String)> {
+    let mut issues = Vec::new();
+    if let Some(temperature) = params.temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+        issues.push((format!("{}.temperature", prefix), format!("temperature {} is outside 0.0-2.0", temperature)));
+    }
+    if let Some(top_p) = params.top_p.filter(|p| !(0.0..=1.0).contains(p)) {
+        issues.push((format!("{}.top_p", prefix), format!("top_p {} is outside 0.0-1.0", top_p)));
+    }
+    if let Some(penalty) = params.repeat_penalty.filter(|p| *p <= 0.0) {
+        issues.push((format!("{}.repeat_penalty", prefix), format!("repeat_penalty {} must be positive", penalty)));
+    }
+    if params.max_tokens == Some(0) {
+        issues.push((format!("{}.max_tokens", prefix), "max_tokens must be greater than 0".to_string()));
+    }
+    issues
+}
+
+/// Resolve an executable name against PATH, or check a path directly
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Set a dotted key in a config file, keeping its comments and formatting.
+/// In a models.toml file, `models.<name>.<key>` is written as `[<name>]`.
+/// The edit is refused if it would leave the file invalid.
+pub fn set_config_value(path: &Path, key: &str, raw: &str, is_models_file: bool) -> Result<(), LlmError> {
+    if !is_known_key(key) {
+        return Err(LlmError::Config(format!("Unknown config key '{}'", key)));
+    }
+
+    let defaults = toml::Table::try_from(AgentConfig::default())
+        .map_err(|e| LlmError::Config(format!("Failed to serialize defaults: {}", e)))?;
+    let parts: Vec<&str> = key.split('.').collect();
+    let value: toml_edit::Value = parse_value(raw, lookup(&defaults, &parts))
+        .to_string()
+        .parse()
+        .map_err(|e| LlmError::Config(format!("Invalid value '{}': {}", raw, e)))?;
+
+    let mut doc = read_document(path)?;
+    let file_parts = if is_models_file {
+        parts.strip_prefix(&["models"][..]).unwrap_or(&parts)
     } else {
-        AgentConfig::default()
+        &parts[..]
     };
-    
-    // Load models from models.toml if it exists
-    if models_path.exists() {
-        let models_content = fs::read_to_string(&models_path)
-            .map_err(|e| LlmError::Io(e))?;
-        
-        let models: HashMap<String, ModelEntry> = toml::from_str(&models_content)
-            .map_err(|e| LlmError::ProcessExecution(format!("Failed to parse models.toml: {}", e)))?;
-        
-        // Merge models into config
-        config.models.extend(models);
+    let (last, sections) = file_parts
+        .split_last()
+        .ok_or_else(|| LlmError::Config(format!("Invalid config key '{}'", key)))?;
+
+    set_document_value(table_at(&mut doc, sections)?, last, value);
+    write_document(path, &doc, is_models_file)
+}
+
+/// Parse a config file for editing; a missing file is an empty document
+pub(crate) fn read_document(path: &Path) -> Result<toml_edit::DocumentMut, LlmError> {
+    let content = if path.is_file() {
+        fs::read_to_string(path).map_err(LlmError::Io)?
+    } else {
+        String::new()
+    };
+    content
+        .parse()
+        .map_err(|e| LlmError::Config(format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+/// The table at `sections` in `doc`, creating any that are missing
+pub(crate) fn table_at<'a>(
+    doc: &'a mut toml_edit::DocumentMut,
+    sections: &[&str],
+) -> Result<&'a mut dyn toml_edit::TableLike, LlmError> {
+    let has_content = !doc.to_string().trim().is_empty();
+    // A file of only comments keeps them as trailing text; move them in front of
+    // the first section so they stay at the top
+    let mut preamble = if doc.as_table().is_empty() {
+        let trailing = doc.trailing().as_str().unwrap_or("").to_string();
+        doc.set_trailing("");
+        trailing
+    } else {
+        String::new()
+    };
+
+    let mut table: &mut dyn toml_edit::TableLike = doc.as_table_mut();
+    for (depth, section) in sections.iter().enumerate() {
+        if !table.contains_key(section) {
+            let mut created = toml_edit::Table::new();
+            // Only the innermost table needs its own header
+            created.set_implicit(depth + 1 < sections.len());
+            if !preamble.is_empty() {
+                created.decor_mut().set_prefix(format!("{}\n", std::mem::take(&mut preamble)));
+            } else if has_content {
+                created.decor_mut().set_prefix("\n");
+            }
+            table.insert(section, toml_edit::Item::Table(created));
+        }
+        table = table
+            .get_mut(section)
+            .and_then(toml_edit::Item::as_table_like_mut)
+            .ok_or_else(|| LlmError::Config(format!("Config key '{}' is not a table", section)))?;
     }
-    
-    Ok(config)
+    Ok(table)
+}
+
+/// Set `key` in `table`, keeping any comment on the line being replaced
+pub(crate) fn set_document_value(table: &mut dyn toml_edit::TableLike, key: &str, value: toml_edit::Value) {
+    match table.get_mut(key).and_then(toml_edit::Item::as_value_mut) {
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table.insert(key, toml_edit::Item::Value(value));
+        }
+    }
+}
+
+/// Write an edited config file, refusing edits that would leave it invalid
+pub(crate) fn write_document(path: &Path, doc: &toml_edit::DocumentMut, is_models_file: bool) -> Result<(), LlmError> {
+    let updated = doc.to_string();
+    let problems = check_file_content(&updated, is_models_file);
+    if !problems.is_empty() {
+        return Err(LlmError::Config(problems.join("; ")));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(LlmError::Io)?;
+    }
+    fs::write(path, updated).map_err(LlmError::Io)
+}
+
+/// Write the default config.toml and models.toml into `dir`. Existing files
+/// are left alone unless `force` is set, in which case they are first copied
+/// to `<name>.bak`. Returns each file with whether it was written.
+pub fn init_config(dir: &Path, force: bool) -> Result<Vec<(PathBuf, bool)>, LlmError> {
+    fs::create_dir_all(dir).map_err(LlmError::Io)?;
+
+    let mut written = Vec::new();
+    for (name, template) in [("config.toml", DEFAULT_CONFIG_TEMPLATE), ("models.toml", DEFAULT_MODELS_TEMPLATE)] {
+        let path = dir.join(name);
+        if path.exists() {
+            if !force {
+                written.push((path, false));
+                continue;
+            }
+            fs::copy(&path, dir.join(format!("{}.bak", name))).map_err(LlmError::Io)?;
+        }
+        fs::write(&path, template).map_err(LlmError::Io)?;
+        written.push((path, true));
+    }
+
+    Ok(written)
 }
 
 /// Detect if GPU acceleration is available on this system
@@ -142,45 +1121,48 @@ pub fn detect_gpu_support() -> (bool, Option<u32>) {
     */
 }
 
+/// Models found by scanning the models directory, `[discovery] roots` and any enabled caches, see `discovery::scan`
 pub fn discover_models() -> Result<HashMap<String, ModelEntry>, LlmError> {
-    let models_dir = get_models_dir();
-    let mut models = HashMap::new();
-    
-    if !models_dir.exists() {
-        return Ok(models);
+    Ok(crate::discovery::scan(&load_config()?.discovery)?.models)
+}
+
+/// The model to use when none is named: `runtime.default_model`, else the first
+/// registered model by name, else the first discovered model by name
+pub fn default_model() -> Result<String, LlmError> {
+    let config = load_config()?;
+    if let Some(model) = config.runtime.default_model {
+        return Ok(model);
     }
-    
-    for entry in fs::read_dir(&models_dir).map_err(LlmError::Io)? {
-        let entry = entry.map_err(LlmError::Io)?;
-        let path = entry.path();
-        
-        if let Some(extension) = path.extension() {
-            if extension == "gguf" {
-                if let Some(file_name) = path.file_name() {
-                    if let Some(name) = file_name.to_str() {
-                        let model_name = name.trim_end_matches(".gguf");
-                        models.insert(model_name.to_string(), ModelEntry {
-                            file: name.to_string(),
-                            description: Some(format!("Auto-discovered model: {}", name)),
-                            context_size: Some(4096),
-                        });
-                    }
-                }
-            }
-        }
+    if let Some(model) = config.models.keys().min() {
+        return Ok(model.clone());
     }
-    
-    Ok(models)
+
+    discover_models()?
+        .into_keys()
+        .min()
+        .ok_or_else(|| LlmError::InvalidModelPath("No models available. Please download a model first.".to_string()))
 }
 
-pub fn resolve_model_path(model_name: &str) -> Result<PathBuf, LlmError> {
+/// The model an alias points at, or `name` itself when it isn't an alias
+pub fn resolve_alias<'a>(config: &'a AgentConfig, name: &'a str) -> &'a str {
+    config.aliases.get(name).map(String::as_str).unwrap_or(name)
+}
+
+/// Look up a model entry by name or alias, checking models.toml first, then discovered models
+pub fn find_model_entry(model_name: &str) -> Result<ModelEntry, LlmError> {
     let config = load_config()?;
-    let discovered_models = discover_models()?;
-    
-    // Check config first, then discovered models
-    let model_entry = config.models.get(model_name)
-        .or_else(|| discovered_models.get(model_name))
-        .ok_or_else(|| LlmError::InvalidModelPath(format!("Model '{}' not found", model_name)))?;
+    let model_name = resolve_alias(&config, model_name);
+    if let Some(entry) = config.models.get(model_name) {
+        return Ok(entry.clone());
+    }
+
+    discover_models()?
+        .remove(model_name)
+        .ok_or_else(|| LlmError::InvalidModelPath(format!("Model '{}' not found", model_name)))
+}
+
+pub fn resolve_model_path(model_name: &str) -> Result<PathBuf, LlmError> {
+    let model_entry = find_model_entry(model_name)?;
     
     let models_dir = get_models_dir();
     let model_path = models_dir.join(&model_entry.file);
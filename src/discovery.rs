@@ -0,0 +1,407 @@
+use crate::config::{self, DiscoveryConfig, ModelEntry};
+use crate::error::LlmError;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Models found on disk, plus anything odd noticed while scanning
+#[derive(Debug, Clone, Default)]
+pub struct Discovery {
+    pub models: HashMap<String, ModelEntry>,
+    /// Name collisions, incomplete split models and directories that could not be read
+    pub warnings: Vec<String>,
+}
+
+/// Scan the models directory and every `[discovery] roots` entry for GGUF models,
+/// then the LM Studio, Hugging Face and Ollama stores when enabled. Files in
+/// those stores are used in place, never copied.
+///
+/// Each model is named after its file without `.gguf`; split models
+/// (`name-00001-of-00003.gguf`) are named after the shared prefix and load
+/// from their first shard. `mmproj` files are attached to the models in the
+/// same directory rather than listed. When a name is found more than once,
+/// the first source wins, and within a source the shallowest path.
+pub fn scan(settings: &DiscoveryConfig) -> Result<Discovery, LlmError> {
+    let models_dir = config::get_models_dir();
+    let mut roots = vec![models_dir.clone()];
+    roots.extend(settings.roots.iter().map(|root| expand_home(root)));
+    if settings.lm_studio {
+        // LM Studio stores plain GGUF files under publisher/repository directories
+        roots.extend(lm_studio_dirs());
+    }
+
+    let mut discovery = Discovery::default();
+    let mut found_at: HashMap<String, PathBuf> = HashMap::new();
+    let mut visited = HashSet::new();
+
+    for root in &roots {
+        let mut files = Vec::new();
+        collect_gguf_files(root, settings.recursive, &mut visited, &mut files, &mut discovery.warnings);
+        files.sort();
+        let grouped = group_models(&files, &mut discovery.warnings);
+        add_models(&mut discovery, &mut found_at, grouped, &models_dir, None);
+    }
+
+    if settings.huggingface {
+        for (repo, snapshot) in huggingface_snapshots() {
+            let mut files = Vec::new();
+            collect_gguf_files(&snapshot, true, &mut visited, &mut files, &mut discovery.warnings);
+            files.sort();
+            let grouped = group_models(&files, &mut discovery.warnings);
+            let description = format!("Hugging Face: {}", repo);
+            add_models(&mut discovery, &mut found_at, grouped, &models_dir, Some(&description));
+        }
+    }
+
+    if settings.ollama {
+        let grouped = ollama_models(&mut discovery.warnings);
+        add_models(&mut discovery, &mut found_at, grouped, &models_dir, Some("Ollama"));
+    }
+
+    Ok(discovery)
+}
+
+/// Record grouped models, keeping the first of any duplicate names
+fn add_models(
+    discovery: &mut Discovery,
+    found_at: &mut HashMap<String, PathBuf>,
+    mut grouped: Vec<(String, PathBuf, Option<PathBuf>, Option<u32>)>,
+    models_dir: &Path,
+    source: Option<&str>,
+) {
+    grouped.sort_by_key(|(_, path, _, _)| (path.components().count(), path.clone()));
+
+    for (name, path, mmproj, parts) in grouped {
+        if let Some(existing) = found_at.get(&name) {
+            discovery.warnings.push(format!(
+                "Model name '{}' found at {} and {}; using the first",
+                name,
+                existing.display(),
+                path.display()
+            ));
+            continue;
+        }
+
+        let description = match (source, parts) {
+            (Some(source), _) => format!("{} model: {}", source, name),
+            (None, Some(count)) => format!("Auto-discovered model: {} ({} parts)", name, count),
+            (None, None) => format!("Auto-discovered model: {}", file_name(&path)),
+        };
+        discovery.models.insert(
+            name.clone(),
+            ModelEntry {
+                file: relative_to(&path, models_dir),
+                description: Some(description),
+                context_size: Some(4096),
+                mmproj: mmproj.map(|mmproj| relative_to(&mmproj, models_dir)),
+                ..ModelEntry::default()
+            },
+        );
+        found_at.insert(name, path);
+    }
+}
+
+/// Gather GGUF files under `dir`. A directory that cannot be read is skipped
+/// with a warning, so one locked folder doesn't hide every other model.
+fn collect_gguf_files(
+    dir: &Path,
+    recursive: bool,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    warnings: &mut Vec<String>,
+) {
+    if !dir.is_dir() {
+        return;
+    }
+    // Symlinked directories can form cycles or point into another root
+    let entries = match dir.canonicalize() {
+        Ok(canonical) => {
+            if !visited.insert(canonical) {
+                return;
+            }
+            fs::read_dir(dir)
+        }
+        Err(e) => Err(e),
+    };
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => {
+            warnings.push(format!("Skipping {}: {}", dir.display(), e));
+            return;
+        }
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                warnings.push(format!("Skipping an entry in {}: {}", dir.display(), e));
+                continue;
+            }
+        };
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if hidden {
+            continue;
+        }
+
+        if path.is_dir() {
+            if recursive {
+                collect_gguf_files(&path, recursive, visited, files, warnings);
+            }
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gguf"))
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// Turn GGUF files into (name, path to load, mmproj companion, shard count) tuples
+fn group_models(
+    files: &[PathBuf],
+    warnings: &mut Vec<String>,
+) -> Vec<(String, PathBuf, Option<PathBuf>, Option<u32>)> {
+    let shard_pattern = Regex::new(r"(?i)^(.+)-(\d{5})-of-(\d{5})\.gguf$").unwrap();
+
+    let mut models = Vec::new();
+    // (directory, prefix) -> (expected part count, shard number -> path)
+    let mut splits: BTreeMap<(PathBuf, String), (u32, BTreeMap<u32, PathBuf>)> = BTreeMap::new();
+
+    for path in files.iter().filter(|path| !is_mmproj(path)) {
+        let name = file_name(path);
+        match shard_pattern.captures(&name) {
+            Some(captures) => {
+                let prefix = captures[1].to_string();
+                let part: u32 = captures[2].parse().unwrap_or(0);
+                let total: u32 = captures[3].parse().unwrap_or(0);
+                let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+                let split = splits.entry((dir, prefix)).or_insert_with(|| (total, BTreeMap::new()));
+                split.1.insert(part, path.clone());
+            }
+            None => {
+                let stem = name[..name.len() - ".gguf".len()].to_string();
+                models.push((stem, path.clone(), None, None));
+            }
+        }
+    }
+
+    for ((dir, prefix), (total, parts)) in splits {
+        let complete = total > 0 && (1..=total).all(|part| parts.contains_key(&part));
+        if !complete {
+            warnings.push(format!(
+                "Skipping split model '{}' in {}: found {} of {} parts",
+                prefix,
+                dir.display(),
+                parts.len(),
+                total
+            ));
+            continue;
+        }
+        models.push((prefix, parts[&1].clone(), None, Some(total)));
+    }
+
+    let mmprojs: Vec<&PathBuf> = files.iter().filter(|path| is_mmproj(path)).collect();
+    let dir_of = |path: &Path| path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let mut models_per_dir: HashMap<PathBuf, usize> = HashMap::new();
+    for (_, path, _, _) in &models {
+        *models_per_dir.entry(dir_of(path)).or_default() += 1;
+    }
+    for (name, path, mmproj, _) in &mut models {
+        let dir = dir_of(path);
+        let candidates: Vec<&PathBuf> = mmprojs.iter().copied().filter(|mmproj| dir_of(mmproj) == dir).collect();
+        *mmproj = mmproj_for(name, &candidates)
+            .or_else(|| (candidates.len() == 1 && models_per_dir[&dir] == 1).then(|| candidates[0]))
+            .cloned();
+    }
+
+    models
+}
+
+/// The mmproj named after the model, e.g. `mmproj-llava-v1.6-f16.gguf` for
+/// `llava-v1.6-Q4_K_M`; the longest such name wins
+fn mmproj_for<'a>(model: &str, candidates: &[&'a PathBuf]) -> Option<&'a PathBuf> {
+    let precision = Regex::new(r"[-._](f16|f32|bf16|q8_0)$").unwrap();
+    let marker = Regex::new(r"[-._]*mmproj[-._]*").unwrap();
+    let model = model.to_lowercase();
+    candidates
+        .iter()
+        .copied()
+        .filter_map(|path| {
+            let name = file_name(path).to_lowercase();
+            let stem = precision.replace(name.strip_suffix(".gguf").unwrap_or(&name), "").to_string();
+            let stem = marker.replace(&stem, "-").trim_matches('-').to_string();
+            (!stem.is_empty() && model.starts_with(&stem)).then_some((stem.len(), path))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, path)| path)
+}
+
+fn is_mmproj(path: &Path) -> bool {
+    file_name(path).to_lowercase().contains("mmproj")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Paths inside the models directory are stored relative to it, like hand-written entries
+fn relative_to(path: &Path, models_dir: &Path) -> String {
+    path.strip_prefix(models_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn lm_studio_dirs() -> Vec<PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    vec![home.join(".lmstudio").join("models"), home.join(".cache").join("lm-studio").join("models")]
+}
+
+/// Hugging Face hub cache: `$HF_HUB_CACHE`, else `$HF_HOME/hub`, else ~/.cache/huggingface/hub
+fn huggingface_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("HF_HUB_CACHE").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = std::env::var_os("HF_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("hub"));
+    }
+    dirs::home_dir().map(|home| home.join(".cache").join("huggingface").join("hub"))
+}
+
+/// One snapshot directory per cached repository (`org/name`): the revision `refs/main`
+/// points at, else the most recently modified snapshot
+fn huggingface_snapshots() -> Vec<(String, PathBuf)> {
+    let Some(cache) = huggingface_cache_dir() else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(&cache) else {
+        return Vec::new();
+    };
+
+    let mut snapshots = Vec::new();
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let Some(repo) = file_name(&dir).strip_prefix("models--").map(|repo| repo.replacen("--", "/", 1)) else {
+            continue;
+        };
+
+        let main = fs::read_to_string(dir.join("refs").join("main"))
+            .ok()
+            .map(|revision| dir.join("snapshots").join(revision.trim()))
+            .filter(|snapshot| snapshot.is_dir());
+        let newest = || {
+            fs::read_dir(dir.join("snapshots"))
+                .ok()?
+                .flatten()
+                .filter_map(|snapshot| {
+                    let modified = snapshot.metadata().and_then(|meta| meta.modified()).ok()?;
+                    Some((modified, snapshot.path()))
+                })
+                .max()
+                .map(|(_, path)| path)
+        };
+
+        if let Some(snapshot) = main.or_else(newest) {
+            snapshots.push((repo, snapshot));
+        }
+    }
+    snapshots.sort();
+    snapshots
+}
+
+/// Ollama's store: `$OLLAMA_MODELS`, else ~/.ollama/models
+fn ollama_dir() -> Option<PathBuf> {
+    match std::env::var_os("OLLAMA_MODELS").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => dirs::home_dir().map(|home| home.join(".ollama").join("models")),
+    }
+}
+
+/// Resolve Ollama manifests (`manifests/<host>/<namespace>/<model>/<tag>`) to
+/// their content-addressed model and projector blobs. Models from the default
+/// library are named `model:tag` as in `ollama run`.
+fn ollama_models(warnings: &mut Vec<String>) -> Vec<(String, PathBuf, Option<PathBuf>, Option<u32>)> {
+    let Some(store) = ollama_dir() else {
+        return Vec::new();
+    };
+    let manifests = store.join("manifests");
+    let mut files = Vec::new();
+    collect_files(&manifests, &mut files);
+    files.sort();
+
+    let mut models = Vec::new();
+    for manifest_path in files {
+        let Ok(relative) = manifest_path.strip_prefix(&manifests) else {
+            continue;
+        };
+        let parts: Vec<String> = relative.iter().map(|part| part.to_string_lossy().to_string()).collect();
+        let [host, namespace, model, tag] = parts.as_slice() else {
+            continue;
+        };
+        let name = match (host.as_str(), namespace.as_str()) {
+            ("registry.ollama.ai", "library") => format!("{}:{}", model, tag),
+            ("registry.ollama.ai", _) => format!("{}/{}:{}", namespace, model, tag),
+            _ => format!("{}/{}/{}:{}", host, namespace, model, tag),
+        };
+
+        let manifest: serde_json::Value = match fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+        {
+            Some(manifest) => manifest,
+            None => {
+                warnings.push(format!("Skipping Ollama model '{}': unreadable manifest", name));
+                continue;
+            }
+        };
+        let blob = |media_type: &str| {
+            manifest["layers"]
+                .as_array()?
+                .iter()
+                .find(|layer| layer["mediaType"] == media_type)
+                .and_then(|layer| layer["digest"].as_str())
+                .map(|digest| store.join("blobs").join(digest.replace(':', "-")))
+        };
+
+        match blob("application/vnd.ollama.image.model") {
+            Some(path) if path.is_file() => {
+                let projector = blob("application/vnd.ollama.image.projector").filter(|path| path.is_file());
+                models.push((name, path, projector, None));
+            }
+            Some(path) => warnings.push(format!("Skipping Ollama model '{}': missing blob {}", name, path.display())),
+            None => {}
+        }
+    }
+    models
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
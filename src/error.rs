@@ -19,4 +19,25 @@ pub enum LlmError {
     
     #[error("Empty response from LLM")]
     EmptyResponse,
+    
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    
+    #[error("Session error: {0}")]
+    Session(String),
+    
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    
+    #[error("Cassette error: {0}")]
+    Cassette(String),
+    
+    #[error("Configuration error: {0}")]
+    Config(String),
+    
+    #[error("HTTP error: {0}")]
+    Http(String),
+    
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
 }
\ No newline at end of file
@@ -0,0 +1,308 @@
+use crate::error::LlmError;
+use crate::llm::{GenerationParams, LlmInterface};
+use crate::schema;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+/// A set of prompts and assertions loaded from a suite TOML file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalSuite {
+    /// Models every case is run against
+    pub models: Vec<String>,
+    /// Model used for `llm_judge` assertions that don't name one; defaults to the model under test
+    #[serde(default)]
+    pub judge_model: Option<String>,
+    /// Parameters applied to every case; evaluations default to temperature 0
+    #[serde(default = "default_suite_params")]
+    pub params: GenerationParams,
+    pub cases: Vec<EvalCase>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalCase {
+    pub name: String,
+    pub prompt: String,
+    /// Per-case overrides of the suite parameters
+    #[serde(default)]
+    pub params: GenerationParams,
+    #[serde(default, rename = "assert")]
+    pub assertions: Vec<Assertion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Assertion {
+    /// Output equals `value` after trimming whitespace
+    Exact { value: String },
+    /// Output contains `value`, ignoring case unless `case_sensitive` is set
+    Contains {
+        value: String,
+        #[serde(default)]
+        case_sensitive: bool,
+    },
+    /// Output matches the regular expression `pattern`
+    Regex { pattern: String },
+    /// Output parses as JSON that satisfies `schema`
+    JsonSchema { schema: serde_json::Value },
+    /// The first number in the output is within `tolerance` of `expected`
+    Numeric {
+        expected: f64,
+        #[serde(default)]
+        tolerance: f64,
+    },
+    /// A judge model answers PASS for the output against `criteria`
+    LlmJudge {
+        criteria: String,
+        #[serde(default)]
+        model: Option<String>,
+    },
+}
+
+/// Outcome of one case on one model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaseResult {
+    pub model: String,
+    pub case: String,
+    pub passed: bool,
+    pub output: Option<String>,
+    pub failures: Vec<String>,
+    pub duration_ms: u64,
+}
+
+impl CaseResult {
+    /// Key used in baseline files
+    pub fn key(&self) -> String {
+        format!("{}::{}", self.model, self.case)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EvalReport {
+    pub results: Vec<CaseResult>,
+}
+
+/// Saved pass/fail state per `model::case`, compared against later runs
+pub type Baseline = BTreeMap<String, bool>;
+
+impl EvalReport {
+    /// (passed, total) per model, sorted by model name
+    pub fn pass_rates(&self) -> BTreeMap<String, (usize, usize)> {
+        let mut rates = BTreeMap::new();
+        for result in &self.results {
+            let entry = rates.entry(result.model.clone()).or_insert((0, 0));
+            if result.passed {
+                entry.0 += 1;
+            }
+            entry.1 += 1;
+        }
+        rates
+    }
+
+    pub fn to_baseline(&self) -> Baseline {
+        self.results.iter().map(|r| (r.key(), r.passed)).collect()
+    }
+
+    /// Results that passed in the baseline but fail now
+    pub fn regressions<'a>(&'a self, baseline: &Baseline) -> Vec<&'a CaseResult> {
+        self.results
+            .iter()
+            .filter(|r| !r.passed && baseline.get(&r.key()) == Some(&true))
+            .collect()
+    }
+}
+
+fn default_suite_params() -> GenerationParams {
+    GenerationParams {
+        temperature: Some(0.0),
+        ..GenerationParams::default()
+    }
+}
+
+pub fn load_suite(path: &Path) -> Result<EvalSuite, LlmError> {
+    let content = fs::read_to_string(path).map_err(LlmError::Io)?;
+    let suite: EvalSuite = toml::from_str(&content)
+        .map_err(|e| LlmError::InvalidInput(format!("Failed to parse {}: {}", path.display(), e)))?;
+
+    // Catch bad patterns before spending time on generation
+    for case in &suite.cases {
+        for assertion in &case.assertions {
+            if let Assertion::Regex { pattern } = assertion {
+                Regex::new(pattern).map_err(|e| {
+                    LlmError::InvalidInput(format!("Case '{}': invalid regex: {}", case.name, e))
+                })?;
+            }
+        }
+    }
+
+    Ok(suite)
+}
+
+pub fn load_baseline(path: &Path) -> Result<Baseline, LlmError> {
+    let content = fs::read_to_string(path).map_err(LlmError::Io)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+pub fn save_baseline(report: &EvalReport, path: &Path) -> Result<(), LlmError> {
+    let content = serde_json::to_string_pretty(&report.to_baseline())?;
+    fs::write(path, content).map_err(LlmError::Io)
+}
+
+/// Run every case against every model in the suite.
+///
+/// `open` builds a backend for a model name; models are opened once and
+/// reused for both generation and judging. A model that fails to open marks
+/// all its cases as failed instead of aborting the run.
+pub fn run_suite<F>(suite: &EvalSuite, mut open: F) -> Result<EvalReport, LlmError>
+where
+    F: FnMut(&str) -> Result<Box<dyn LlmInterface + Send + Sync>, LlmError>,
+{
+    let mut backends: HashMap<String, Box<dyn LlmInterface + Send + Sync>> = HashMap::new();
+    let mut report = EvalReport::default();
+
+    for model in &suite.models {
+        if !backends.contains_key(model) {
+            match open(model) {
+                Ok(llm) => {
+                    backends.insert(model.clone(), llm);
+                }
+                Err(e) => {
+                    for case in &suite.cases {
+                        report.results.push(CaseResult {
+                            model: model.clone(),
+                            case: case.name.clone(),
+                            passed: false,
+                            output: None,
+                            failures: vec![format!("failed to open model: {}", e)],
+                            duration_ms: 0,
+                        });
+                    }
+                    continue;
+                }
+            }
+        }
+
+        for case in &suite.cases {
+            let params = suite.params.merged_with(&case.params);
+            let started = Instant::now();
+            let output = backends[model].generate_with(&case.prompt, &params);
+            let duration_ms = started.elapsed().as_millis() as u64;
+
+            let (output, failures) = match output {
+                Ok(output) => {
+                    let mut failures = Vec::new();
+                    for assertion in &case.assertions {
+                        let judge_name = match assertion {
+                            Assertion::LlmJudge { model: judge, .. } => judge
+                                .clone()
+                                .or_else(|| suite.judge_model.clone())
+                                .unwrap_or_else(|| model.clone()),
+                            _ => model.clone(),
+                        };
+                        if !backends.contains_key(&judge_name) {
+                            match open(&judge_name) {
+                                Ok(judge) => {
+                                    backends.insert(judge_name.clone(), judge);
+                                }
+                                Err(e) => {
+                                    failures.push(format!("failed to open judge model '{}': {}", judge_name, e));
+                                    continue;
+                                }
+                            }
+                        }
+
+                        if let Err(failure) = check(assertion, &output, backends[&judge_name].as_ref()) {
+                            failures.push(failure);
+                        }
+                    }
+                    (Some(output), failures)
+                }
+                Err(e) => (None, vec![format!("generation failed: {}", e)]),
+            };
+
+            report.results.push(CaseResult {
+                model: model.clone(),
+                case: case.name.clone(),
+                passed: failures.is_empty(),
+                output,
+                failures,
+                duration_ms,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+/// Check one assertion, returning a description of the failure
+pub fn check(assertion: &Assertion, output: &str, judge: &(dyn LlmInterface + Send + Sync)) -> Result<(), String> {
+    match assertion {
+        Assertion::Exact { value } => {
+            if output.trim() == value.trim() {
+                Ok(())
+            } else {
+                Err(format!("expected exactly {:?}", value))
+            }
+        }
+        Assertion::Contains { value, case_sensitive } => {
+            let found = if *case_sensitive {
+                output.contains(value.as_str())
+            } else {
+                output.to_lowercase().contains(&value.to_lowercase())
+            };
+            if found {
+                Ok(())
+            } else {
+                Err(format!("expected output to contain {:?}", value))
+            }
+        }
+        Assertion::Regex { pattern } => {
+            let regex = Regex::new(pattern).map_err(|e| format!("invalid regex: {}", e))?;
+            if regex.is_match(output) {
+                Ok(())
+            } else {
+                Err(format!("expected output to match /{}/", pattern))
+            }
+        }
+        Assertion::JsonSchema { schema } => {
+            let value = schema::extract_json(output).map_err(|e| format!("output is not JSON: {}", e))?;
+            schema::validate(&value, schema).map_err(|errors| errors.join("; "))
+        }
+        Assertion::Numeric { expected, tolerance } => {
+            let number = Regex::new(r"-?\d+(?:\.\d+)?")
+                .unwrap()
+                .find(&output.replace(',', ""))
+                .and_then(|m| m.as_str().parse::<f64>().ok())
+                .ok_or_else(|| "no number found in output".to_string())?;
+            if (number - expected).abs() <= *tolerance {
+                Ok(())
+            } else {
+                Err(format!("expected {} ± {}, got {}", expected, tolerance, number))
+            }
+        }
+        Assertion::LlmJudge { criteria, .. } => {
+            let prompt = format!(
+                "You are grading a response against criteria.\n\nCriteria:\n{}\n\nResponse:\n{}\n\n\
+                 Answer PASS or FAIL on the first line, followed by a one-sentence reason.",
+                criteria, output
+            );
+            let params = GenerationParams {
+                temperature: Some(0.0),
+                ..GenerationParams::default()
+            };
+            let verdict = judge
+                .generate_with(&prompt, &params)
+                .map_err(|e| format!("judge failed: {}", e))?;
+            let first_line = verdict.trim().lines().next().unwrap_or("").to_uppercase();
+
+            if first_line.contains("PASS") && !first_line.contains("FAIL") {
+                Ok(())
+            } else {
+                Err(format!("judge: {}", verdict.trim()))
+            }
+        }
+    }
+}
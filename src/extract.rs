@@ -0,0 +1,308 @@
+use crate::error::LlmError;
+use crate::llm::LlmInterface;
+pub use agentd_derive::LlmExtract;
+pub use serde_json::{Map, Value};
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+/// Times `Extract::extract` asks again after a reply fails validation
+pub const DEFAULT_EXTRACT_RETRIES: u32 = 2;
+
+const DEFAULT_INSTRUCTIONS: &str = "Extract the requested information from the text below.";
+
+/// A type that can be extracted from free text by a model.
+///
+/// Usually derived with `#[derive(LlmExtract)]`, which supports structs with
+/// named fields and enums whose variants have no fields. Doc comments become
+/// schema descriptions; `#[llm(rename = "...")]` changes a field or variant
+/// name, `#[llm(description = "...")]` replaces its doc comment and
+/// `#[llm(instructions = "...")]` on the type replaces the task description.
+/// Structs may contain themselves, e.g. `children: Vec<Node>`; their schema
+/// then uses `$defs` and `$ref`.
+pub trait LlmExtract: Sized {
+    /// JSON Schema the model's reply must satisfy
+    fn json_schema() -> Value;
+
+    /// Build the value from JSON, describing the first mismatch on failure
+    fn from_json(value: &Value) -> Result<Self, String>;
+
+    /// Task description placed before the schema in the prompt
+    fn instructions() -> String {
+        DEFAULT_INSTRUCTIONS.to_string()
+    }
+
+    /// The full prompt asking for this type to be extracted from `text`
+    fn prompt(text: &str) -> String {
+        let schema = serde_json::to_string_pretty(&Self::json_schema()).unwrap_or_default();
+        format!(
+            "{}\nReply with only a JSON value matching this JSON Schema:\n{}\n\nText:\n{}",
+            Self::instructions(),
+            schema,
+            text
+        )
+    }
+}
+
+/// Typed extraction for every backend: `llm.extract::<Invoice>(text)?`
+pub trait Extract {
+    /// Extract a `T` from `text`, asking again up to `DEFAULT_EXTRACT_RETRIES`
+    /// times when the reply does not validate
+    fn extract<T: LlmExtract>(&self, text: &str) -> Result<T, LlmError> {
+        self.extract_with_retries(text, DEFAULT_EXTRACT_RETRIES)
+    }
+
+    /// Like `extract`, with an explicit number of retries. The last
+    /// `LlmError::InvalidResponse` is returned once they run out; other
+    /// errors are returned immediately.
+    fn extract_with_retries<T: LlmExtract>(&self, text: &str, retries: u32) -> Result<T, LlmError>;
+}
+
+impl<L: LlmInterface + ?Sized> Extract for L {
+    fn extract_with_retries<T: LlmExtract>(&self, text: &str, retries: u32) -> Result<T, LlmError> {
+        let schema = T::json_schema();
+        let base_prompt = T::prompt(text);
+        let mut prompt = base_prompt.clone();
+        let mut attempt = 0;
+
+        loop {
+            let result = self
+                .generate_json(&prompt, &schema)
+                .and_then(|value| T::from_json(&value).map_err(LlmError::InvalidResponse));
+            match result {
+                Err(LlmError::InvalidResponse(problem)) if attempt < retries => {
+                    attempt += 1;
+                    prompt = format!(
+                        "{}\n\nYour previous reply was rejected: {}. Answer again with JSON that matches the schema.",
+                        base_prompt, problem
+                    );
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Types whose schemas are being built on this thread, so a type that contains
+/// itself can refer to its definition instead of expanding forever
+#[derive(Default)]
+struct SchemaBuild {
+    building: Vec<Option<String>>,
+    recursive: HashSet<String>,
+    defs: Map<String, Value>,
+}
+
+thread_local! {
+    static SCHEMA_BUILD: RefCell<SchemaBuild> = RefCell::new(SchemaBuild::default());
+}
+
+/// Schema for a derived struct `T`, built by `build`. A struct that contains
+/// itself, e.g. through `Vec<Box<Node>>`, is described once under the root
+/// schema's `$defs` and referred to with `$ref`.
+pub fn named_schema<T: ?Sized>(build: impl FnOnce() -> Value) -> Value {
+    let name: String = std::any::type_name::<T>()
+        .rsplit("::")
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    nested_schema(Some(name), build)
+}
+
+/// Build a schema that contains other types' schemas, collecting the
+/// definitions of recursive types into `$defs` once the outermost one is done
+fn nested_schema(name: Option<String>, build: impl FnOnce() -> Value) -> Value {
+    if let Some(name) = &name {
+        let reference = json!({"$ref": format!("#/$defs/{}", name)});
+        let seen = SCHEMA_BUILD.with(|state| {
+            let mut state = state.borrow_mut();
+            let seen = state.building.contains(&Some(name.clone()));
+            if seen {
+                state.recursive.insert(name.clone());
+            }
+            seen
+        });
+        if seen {
+            return reference;
+        }
+    }
+
+    SCHEMA_BUILD.with(|state| state.borrow_mut().building.push(name.clone()));
+    let schema = build();
+    SCHEMA_BUILD.with(|state| {
+        let mut state = state.borrow_mut();
+        state.building.pop();
+        let mut schema = match name {
+            Some(name) if state.recursive.contains(&name) => {
+                state.defs.insert(name.clone(), schema.clone());
+                if !state.building.is_empty() {
+                    return json!({"$ref": format!("#/$defs/{}", name)});
+                }
+                schema
+            }
+            _ => schema,
+        };
+        if state.building.is_empty() && !state.defs.is_empty() {
+            schema["$defs"] = Value::Object(std::mem::take(&mut state.defs));
+            state.recursive.clear();
+        }
+        schema
+    })
+}
+
+/// Schema for a derived struct. Every property is required, as strict
+/// structured-output APIs demand; optional fields are nullable instead.
+pub fn object_schema(description: Option<&str>, properties: Vec<(&str, Option<&str>, Value)>) -> Value {
+    let required: Vec<&str> = properties.iter().map(|(name, _, _)| *name).collect();
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(name, description, schema)| (name.to_string(), describe(schema, description)))
+        .collect();
+    let schema = json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    });
+    describe(schema, description)
+}
+
+/// Schema for a derived enum: one of the variant names
+pub fn enum_schema(description: Option<&str>, variants: &[&str]) -> Value {
+    describe(json!({"type": "string", "enum": variants}), description)
+}
+
+/// Read the property `name` of a JSON object; a missing property reads as `null`
+pub fn field<T: LlmExtract>(value: &Value, name: &str) -> Result<T, String> {
+    let object = value.as_object().ok_or_else(|| format!("expected an object, got {}", value))?;
+    T::from_json(object.get(name).unwrap_or(&Value::Null)).map_err(|e| format!("{}: {}", name, e))
+}
+
+fn describe(mut schema: Value, description: Option<&str>) -> Value {
+    if let (Some(object), Some(description)) = (schema.as_object_mut(), description) {
+        object.insert("description".to_string(), json!(description));
+    }
+    schema
+}
+
+impl LlmExtract for String {
+    fn json_schema() -> Value {
+        json!({"type": "string"})
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        value.as_str().map(str::to_string).ok_or_else(|| format!("expected a string, got {}", value))
+    }
+}
+
+impl LlmExtract for bool {
+    fn json_schema() -> Value {
+        json!({"type": "boolean"})
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        value.as_bool().ok_or_else(|| format!("expected a boolean, got {}", value))
+    }
+}
+
+macro_rules! extract_integer {
+    ($($ty:ty),*) => {$(
+        impl LlmExtract for $ty {
+            fn json_schema() -> Value {
+                integer_schema(<$ty>::MIN as i128, <$ty>::MAX as i128)
+            }
+
+            fn from_json(value: &Value) -> Result<Self, String> {
+                value
+                    .as_i64()
+                    .and_then(|n| <$ty>::try_from(n).ok())
+                    .or_else(|| value.as_u64().and_then(|n| <$ty>::try_from(n).ok()))
+                    .ok_or_else(|| format!("expected an integer between {} and {}, got {}", <$ty>::MIN, <$ty>::MAX, value))
+            }
+        }
+    )*};
+}
+
+extract_integer!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+/// Bounds beyond what a JSON number can hold are left out
+fn integer_schema(minimum: i128, maximum: i128) -> Value {
+    let mut schema = json!({"type": "integer"});
+    if minimum > i64::MIN as i128 {
+        schema["minimum"] = json!(minimum as i64);
+    }
+    if maximum < i64::MAX as i128 {
+        schema["maximum"] = json!(maximum as i64);
+    }
+    schema
+}
+
+impl LlmExtract for f64 {
+    fn json_schema() -> Value {
+        json!({"type": "number"})
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        value.as_f64().ok_or_else(|| format!("expected a number, got {}", value))
+    }
+}
+
+impl LlmExtract for f32 {
+    fn json_schema() -> Value {
+        f64::json_schema()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        f64::from_json(value).map(|n| n as f32)
+    }
+}
+
+/// Any JSON value
+impl LlmExtract for Value {
+    fn json_schema() -> Value {
+        json!({})
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: LlmExtract> LlmExtract for Option<T> {
+    fn json_schema() -> Value {
+        nested_schema(None, || json!({"anyOf": [T::json_schema(), {"type": "null"}]}))
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_json(value).map(Some),
+        }
+    }
+}
+
+impl<T: LlmExtract> LlmExtract for Vec<T> {
+    fn json_schema() -> Value {
+        nested_schema(None, || json!({"type": "array", "items": T::json_schema()}))
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        let items = value.as_array().ok_or_else(|| format!("expected an array, got {}", value))?;
+        items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| T::from_json(item).map_err(|e| format!("[{}]: {}", index, e)))
+            .collect()
+    }
+}
+
+impl<T: LlmExtract> LlmExtract for Box<T> {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, String> {
+        T::from_json(value).map(Box::new)
+    }
+}
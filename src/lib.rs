@@ -2,10 +2,32 @@ pub mod llm;
 pub mod error;
 pub mod config;
 pub mod cli;
+pub mod chat;
+pub mod session;
+pub mod cache;
+pub mod batch;
+pub mod schema;
+pub mod eval;
+pub mod testing;
+pub mod recording;
+pub mod models;
+pub mod discovery;
+pub mod server;
+pub mod remote;
+pub mod repl;
+pub mod output;
+pub mod prompts;
+pub mod extract;
+pub mod tools;
+pub mod agent;
 
-pub use llm::{LlmInterface, open};
+pub use llm::{LlmInterface, OpenOptions, open, open_with};
 pub use error::LlmError;
 pub use config::{AgentConfig, load_config, resolve_model_path, discover_models};
+pub use chat::{ChatMessage, Role};
+pub use tools::{Tool, ToolCall};
+pub use session::{Session, SessionStore};
+pub use extract::{Extract, LlmExtract};
 
 // Python bindings module
 mod pybindings;
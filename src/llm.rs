@@ -1,4 +1,10 @@
+use crate::cache::{CachedBackend, PromptCache, ResponseCache};
+use crate::chat::{render_transcript, ChatMessage};
 use crate::error::LlmError;
+use crate::recording::{self, CassetteMode, RecordingBackend, ReplayBackend};
+use crate::remote::{LlamaServerBackend, OpenAiBackend};
+use crate::tools::{Tool, ToolFormat};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
@@ -7,6 +13,8 @@ pub struct LlmConfig {
     pub executable_path: String,
     pub model_path: String,
     pub additional_args: Vec<String>,
+    #[serde(default)]
+    pub prompt_cache: Option<PromptCache>,
 }
 
 impl LlmConfig {
@@ -15,19 +23,34 @@ impl LlmConfig {
             executable_path: executable_path.into(),
             model_path: model_path.into(),
             additional_args: Vec::new(),
+            prompt_cache: None,
         }
     }
 
     pub fn from_model_name(model_name: &str) -> Result<Self, LlmError> {
+        Self::from_model_name_with(model_name, None)
+    }
+
+    /// Build the llama.cpp configuration for a model, layering sampling parameters
+    /// from `[defaults]`, then the model's entry, then the named preset
+    pub fn from_model_name_with(model_name: &str, preset: Option<&str>) -> Result<Self, LlmError> {
         let config = crate::config::load_config()?;
+        let entry = crate::config::find_model_entry(model_name)?;
         let model_path = crate::config::resolve_model_path(model_name)?;
-        
-        let mut args = vec![
-            "--temp".to_string(), config.defaults.temperature.to_string(),
-            "--top-p".to_string(), config.defaults.top_p.to_string(),
-            "--repeat-penalty".to_string(), config.defaults.repeat_penalty.to_string(),
-            "--n-predict".to_string(), config.defaults.max_tokens.to_string(),
-        ];
+
+        let mut args = layered_params(&config, &entry, preset)?.to_args();
+        if let Some(context_size) = entry.context_size {
+            args.push("--ctx-size".to_string());
+            args.push(context_size.to_string());
+        }
+        if let Some(threads) = entry.threads {
+            args.push("--threads".to_string());
+            args.push(threads.to_string());
+        }
+        if let Some(mmproj) = &entry.mmproj {
+            args.push("--mmproj".to_string());
+            args.push(crate::config::get_models_dir().join(mmproj).to_string_lossy().to_string());
+        }
         
         // Add GPU support if configured
         if config.runtime.use_gpu {
@@ -36,40 +59,350 @@ impl LlmConfig {
                 args.push(gpu_layers.to_string());
             }
         }
+        args.extend(entry.args.iter().cloned());
+        
+        let prompt_cache = if config.cache.prompt_cache {
+            Some(PromptCache::from_config(&config.cache))
+        } else {
+            None
+        };
         
         Ok(Self {
             executable_path: config.runtime.llama_executable,
             model_path: model_path.to_string_lossy().to_string(),
             additional_args: args,
+            prompt_cache,
         })
     }
 
+    pub fn with_prompt_cache(mut self, prompt_cache: Option<PromptCache>) -> Self {
+        self.prompt_cache = prompt_cache;
+        self
+    }
+
     pub fn with_args(mut self, args: Vec<String>) -> Self {
         self.additional_args = args;
         self
     }
 }
 
+/// Per-request sampling overrides, applied on top of a backend's configured arguments
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// GBNF grammar the output must follow, passed to llama.cpp as `--grammar`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+    /// JSON Schema the output must satisfy, passed to llama.cpp as `--json-schema`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<serde_json::Value>,
+}
+
+impl GenerationParams {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// These parameters with any that are set in `overrides` replaced
+    pub fn merged_with(&self, overrides: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            repeat_penalty: overrides.repeat_penalty.or(self.repeat_penalty),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            seed: overrides.seed.or(self.seed),
+            grammar: overrides.grammar.clone().or_else(|| self.grammar.clone()),
+            json_schema: overrides.json_schema.clone().or_else(|| self.json_schema.clone()),
+        }
+    }
+
+    /// Parameters set by llama.cpp flags in `args`, the inverse of `to_args`.
+    /// Later flags win and unrelated arguments are ignored.
+    pub fn from_args(args: &[String]) -> GenerationParams {
+        let mut params = GenerationParams::default();
+        for pair in args.windows(2) {
+            let value = pair[1].as_str();
+            match pair[0].as_str() {
+                "--temp" => params.temperature = value.parse().ok().or(params.temperature),
+                "--top-p" => params.top_p = value.parse().ok().or(params.top_p),
+                "--repeat-penalty" => params.repeat_penalty = value.parse().ok().or(params.repeat_penalty),
+                "--n-predict" => params.max_tokens = value.parse().ok().or(params.max_tokens),
+                "--seed" => params.seed = value.parse().ok().or(params.seed),
+                "--grammar" => params.grammar = Some(value.to_string()),
+                "--json-schema" => params.json_schema = serde_json::from_str(value).ok().or(params.json_schema.take()),
+                _ => {}
+            }
+        }
+        params
+    }
+
+    /// llama.cpp command-line flags for the parameters that are set
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(temperature) = self.temperature {
+            args.extend(["--temp".to_string(), temperature.to_string()]);
+        }
+        if let Some(top_p) = self.top_p {
+            args.extend(["--top-p".to_string(), top_p.to_string()]);
+        }
+        if let Some(repeat_penalty) = self.repeat_penalty {
+            args.extend(["--repeat-penalty".to_string(), repeat_penalty.to_string()]);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            args.extend(["--n-predict".to_string(), max_tokens.to_string()]);
+        }
+        if let Some(seed) = self.seed {
+            args.extend(["--seed".to_string(), seed.to_string()]);
+        }
+        if let Some(grammar) = &self.grammar {
+            args.extend(["--grammar".to_string(), grammar.clone()]);
+        }
+        if let Some(schema) = &self.json_schema {
+            args.extend(["--json-schema".to_string(), schema.to_string()]);
+        }
+        args
+    }
+}
+
+/// Sampling parameters for a model: `[defaults]`, then the model's entry, then the named preset
+fn layered_params(
+    config: &crate::config::AgentConfig,
+    entry: &crate::config::ModelEntry,
+    preset: Option<&str>,
+) -> Result<GenerationParams, LlmError> {
+    let defaults = GenerationParams {
+        temperature: Some(config.defaults.temperature),
+        top_p: Some(config.defaults.top_p),
+        repeat_penalty: Some(config.defaults.repeat_penalty),
+        max_tokens: Some(config.defaults.max_tokens),
+        ..GenerationParams::default()
+    };
+    let mut params = defaults.merged_with(&entry.params);
+    if let Some(preset) = preset {
+        let overrides = config.presets.get(preset).ok_or_else(|| {
+            LlmError::InvalidInput(format!("Unknown preset '{}'", preset))
+        })?;
+        params = params.merged_with(overrides);
+    }
+    Ok(params)
+}
+
+/// Generate JSON that satisfies `schema` and deserialize it into `T`
+pub fn generate_typed<T: DeserializeOwned>(
+    llm: &(impl LlmInterface + ?Sized),
+    prompt: &str,
+    schema: &serde_json::Value,
+) -> Result<T, LlmError> {
+    let value = llm.generate_json(prompt, schema)?;
+    serde_json::from_value(value).map_err(|e| LlmError::InvalidResponse(format!("JSON does not fit the requested type: {}", e)))
+}
+
+/// Approximate token counts for a request, see `estimate_tokens`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl Usage {
+    pub fn estimate(prompt: &str, completion: &str) -> Self {
+        let prompt_tokens = estimate_tokens(prompt);
+        let completion_tokens = estimate_tokens(completion);
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// Options controlling how `open_with` builds a backend
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    /// Bypass the response cache even when it is enabled in config.toml
+    pub no_cache: bool,
+    /// Apply the sampling parameters of this `[presets.<name>]` entry
+    pub preset: Option<String>,
+    /// Keep a llama.cpp model loaded in a `llama-server` process (see
+    /// `agentd::remote::LlamaServerBackend`) instead of starting llama.cpp per request
+    pub persistent: bool,
+}
+
+impl OpenOptions {
+    pub fn no_cache(mut self, no_cache: bool) -> Self {
+        self.no_cache = no_cache;
+        self
+    }
+
+    pub fn preset(mut self, preset: impl Into<String>) -> Self {
+        self.preset = Some(preset.into());
+        self
+    }
+
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        self.persistent = persistent;
+        self
+    }
+}
+
 pub fn open(model_name: &str) -> Result<Box<dyn LlmInterface + Send + Sync>, LlmError> {
-    let config = LlmConfig::from_model_name(model_name)?;
-    let backend = llamacpp::LlamaCppBackend::new(config)?;
-    Ok(Box::new(backend))
+    open_with(model_name, &OpenOptions::default())
+}
+
+/// Open a model with the backend named by its entry's `backend`, or else by
+/// `runtime.default_backend`: `llama.cpp` (the default), `openai` (see
+/// `agentd::remote::OpenAiBackend`) or `mock` (see `agentd::testing::MockBackend`).
+///
+/// When a cassette is configured (`AGENTD_CASSETTE` or `runtime.cassette`), the
+/// backend is wrapped to record to it, or replaced by a replay of it.
+pub fn open_with(model_name: &str, options: &OpenOptions) -> Result<Box<dyn LlmInterface + Send + Sync>, LlmError> {
+    let agent_config = crate::config::load_config()?;
+
+    match recording::cassette_settings(&agent_config.runtime)? {
+        Some((path, CassetteMode::Replay)) => Ok(Box::new(ReplayBackend::load(&path, model_name)?)),
+        Some((path, _)) => {
+            let backend = open_backend(model_name, options, &agent_config)?;
+            Ok(Box::new(RecordingBackend::new(backend, model_name, path)))
+        }
+        None => open_backend(model_name, options, &agent_config),
+    }
+}
+
+fn open_backend(
+    model_name: &str,
+    options: &OpenOptions,
+    agent_config: &crate::config::AgentConfig,
+) -> Result<Box<dyn LlmInterface + Send + Sync>, LlmError> {
+    let entry = crate::config::find_model_entry(model_name).ok();
+    let backend_name = match &entry {
+        Some(entry) => entry.backend_name(&agent_config.runtime),
+        None => agent_config.runtime.default_backend.as_str(),
+    };
+
+    match backend_name {
+        "llama.cpp" | "llamacpp" => {
+            let config = LlmConfig::from_model_name_with(model_name, options.preset.as_deref())?;
+            let backend: Box<dyn LlmInterface + Send + Sync> = if options.persistent {
+                Box::new(LlamaServerBackend::start(config)?)
+            } else {
+                Box::new(llamacpp::LlamaCppBackend::new(config)?)
+            };
+
+            if agent_config.cache.response_cache && !options.no_cache {
+                let cache = ResponseCache::from_config(&agent_config.cache);
+                return Ok(Box::new(CachedBackend::new(backend, cache)));
+            }
+
+            Ok(backend)
+        }
+        "openai" => {
+            let entry = entry.ok_or_else(|| LlmError::InvalidModelPath(format!("Model '{}' not found", model_name)))?;
+            let params = layered_params(agent_config, &entry, options.preset.as_deref())?;
+            // Remote responses are not cached: there is no local file to fingerprint
+            Ok(Box::new(OpenAiBackend::from_entry(model_name, &entry, &params)?))
+        }
+        "mock" => Ok(Box::new(crate::testing::MockBackend::new().with_model_name(model_name))),
+        other => Err(LlmError::InvalidInput(format!(
+            "Unknown backend '{}': expected 'llama.cpp', 'openai' or 'mock'",
+            other
+        ))),
+    }
+}
+
+/// Rough token count for budgeting prompts against a model's context size.
+/// Uses the common ~4 characters per token heuristic for English text.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
 }
 
 pub trait LlmInterface: Send + Sync {
     fn generate(&self, prompt: &str) -> Result<String, LlmError>;
+    /// Generate with per-request parameter overrides.
+    /// Backends that cannot honour per-request parameters ignore them.
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        let _ = params;
+        self.generate(prompt)
+    }
+    /// Generate JSON that satisfies `schema`. The backend is constrained to the
+    /// schema where it supports that, and the reply is always validated, so the
+    /// result is either a conforming value or `LlmError::InvalidResponse`.
+    /// A schema `agentd::schema` cannot check is `LlmError::InvalidInput`.
+    fn generate_json(&self, prompt: &str, schema: &serde_json::Value) -> Result<serde_json::Value, LlmError> {
+        crate::schema::check_schema(schema)?;
+        let params = GenerationParams {
+            json_schema: Some(schema.clone()),
+            ..GenerationParams::default()
+        };
+        crate::schema::parse_reply(&self.generate_with(prompt, &params)?, schema)
+    }
+    /// Generate while passing text to `on_chunk` as it becomes available.
+    /// Returning `false` from `on_chunk` stops generation early. The returned
+    /// string is everything that was produced. Backends without incremental
+    /// output deliver the whole response as one chunk.
+    fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let text = self.generate(prompt)?;
+        on_chunk(&text);
+        Ok(text)
+    }
+    /// Generate the next assistant reply for a conversation.
+    /// Backends without a native chat API get the messages rendered as a transcript.
+    fn chat(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        self.generate(&render_transcript(messages))
+    }
+    /// `chat` with per-request parameter overrides, like `generate_with`.
+    /// By default the transcript that `chat` would generate from gets them.
+    fn chat_with(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<String, LlmError> {
+        if params.is_empty() {
+            return self.chat(messages);
+        }
+        self.generate_with(&render_transcript(messages), params)
+    }
+    /// Stream the next assistant reply for a conversation, like `generate_stream`.
+    /// By default the transcript that `chat` would generate from is streamed.
+    fn chat_stream(&self, messages: &[ChatMessage], on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        self.generate_stream(&render_transcript(messages), on_chunk)
+    }
+    /// Generate the next assistant message, which may ask to call some of `tools`.
+    /// By default the tools are described in the prompt using the convention
+    /// of the model's family (see `ToolFormat::detect`) and calls are parsed
+    /// from the reply.
+    fn chat_with_tools(&self, messages: &[ChatMessage], tools: &[Tool]) -> Result<ChatMessage, LlmError> {
+        let format = ToolFormat::detect(&self.config().model_path);
+        crate::tools::chat_with_format(self, format, messages, tools)
+    }
+    /// Embed `text` as a vector. Backends without embedding support return
+    /// `LlmError::InvalidInput`.
+    fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+        let _ = text;
+        Err(LlmError::InvalidInput("This backend does not support embeddings".to_string()))
+    }
     fn config(&self) -> &LlmConfig;
     fn with_args(self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync>;
 }
 
 pub mod backends {
     pub use super::llamacpp::LlamaCppBackend;
+    pub use crate::cache::CachedBackend;
+    pub use crate::testing::MockBackend;
+    pub use crate::recording::{RecordingBackend, ReplayBackend};
+    pub use crate::remote::{LlamaServerBackend, OpenAiBackend};
 }
 
 mod llamacpp {
     use super::*;
-    use std::process::{Command, Stdio};
-    use std::io::Write;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
 
     #[derive(Debug)]
     pub struct LlamaCppBackend {
@@ -130,17 +463,31 @@ mod llamacpp {
             
             cleaned
         }
-    }
 
-    impl LlmInterface for LlamaCppBackend {
-        fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        /// The llama.cpp command for `prompt`, and the prompt cache file it uses.
+        /// `extra_args` come after the configured arguments so they take precedence.
+        fn command(&self, prompt: &str, extra_args: &[String]) -> Result<(Command, Option<PathBuf>), LlmError> {
             let mut cmd = Command::new(&self.config.executable_path);
-            cmd.args(&["--model", &self.config.model_path])
+            cmd.args(["--model", &self.config.model_path])
                .args(&self.config.additional_args)
+               .args(extra_args)
                .stdin(Stdio::piped())
                .stdout(Stdio::piped())
                .stderr(Stdio::piped());
 
+            let prompt_cache_file = match &self.config.prompt_cache {
+                Some(cache) => {
+                    let path = cache.path_for(&self.config.model_path, prompt)?;
+                    cmd.arg("--prompt-cache").arg(&path);
+                    Some(path)
+                }
+                None => None,
+            };
+
+            Ok((cmd, prompt_cache_file))
+        }
+
+        fn spawn(&self, mut cmd: Command, prompt: &str) -> Result<Child, LlmError> {
             let mut child = cmd.spawn()
                 .map_err(|e| LlmError::ProcessSpawn(format!("Failed to spawn {}: {}", self.config.executable_path, e)))?;
 
@@ -149,9 +496,26 @@ mod llamacpp {
                     .map_err(LlmError::Io)?;
             }
 
+            Ok(child)
+        }
+
+        fn record_prompt_cache_use(&self, prompt_cache_file: Option<PathBuf>) {
+            if let (Some(cache), Some(path)) = (&self.config.prompt_cache, prompt_cache_file) {
+                // A failed eviction should never fail the generation itself
+                let _ = cache.record_use(&path);
+            }
+        }
+
+        /// Run llama.cpp once; `extra_args` come after the configured arguments so they take precedence
+        fn run(&self, prompt: &str, extra_args: &[String]) -> Result<String, LlmError> {
+            let (cmd, prompt_cache_file) = self.command(prompt, extra_args)?;
+            let child = self.spawn(cmd, prompt)?;
+
             let output = child.wait_with_output()
                 .map_err(LlmError::Io)?;
 
+            self.record_prompt_cache_use(prompt_cache_file);
+
             if !output.status.success() {
                 let stderr = String::from_utf8_lossy(&output.stderr);
                 return Err(LlmError::ProcessExecution(format!("Process failed with status {}: {}", output.status, stderr)));
@@ -168,6 +532,124 @@ mod llamacpp {
             Ok(cleaned_response)
         }
 
+        /// Like `run`, passing output to `on_chunk` as llama.cpp prints it. The
+        /// echoed prompt is held back, and the process is killed if `on_chunk`
+        /// returns `false`.
+        fn run_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+            let (mut cmd, prompt_cache_file) = self.command(prompt, &[])?;
+            // Keep llama.cpp out of the terminal's process group: Ctrl-C should
+            // reach the caller, which stops the reply through `on_chunk`
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+            let mut child = self.spawn(cmd, prompt)?;
+
+            // Drain stderr alongside stdout so a chatty llama.cpp never blocks on a full pipe
+            let mut stderr = child.stderr.take();
+            let stderr_reader = std::thread::spawn(move || {
+                let mut output = String::new();
+                if let Some(stderr) = stderr.as_mut() {
+                    let _ = stderr.read_to_string(&mut output);
+                }
+                output
+            });
+
+            let mut stdout = child.stdout.take().ok_or(LlmError::EmptyResponse)?;
+            let mut raw = Vec::new();
+            let mut buffer = [0u8; 4096];
+            let mut reply_start = None;
+            let mut emitted = 0;
+            let mut stopped = false;
+
+            loop {
+                let read = stdout.read(&mut buffer).map_err(LlmError::Io)?;
+                if read == 0 {
+                    break;
+                }
+                raw.extend_from_slice(&buffer[..read]);
+
+                // Only pass on whole UTF-8 characters
+                let valid = match std::str::from_utf8(&raw) {
+                    Ok(text) => text.len(),
+                    Err(e) => e.valid_up_to(),
+                };
+                let text = String::from_utf8_lossy(&raw[..valid]);
+
+                if reply_start.is_none() {
+                    if text.len() < prompt.len() && prompt.starts_with(text.as_ref()) {
+                        continue;
+                    }
+                    let start = if text.starts_with(prompt) { prompt.len() } else { 0 };
+                    reply_start = Some(start);
+                    emitted = start;
+                }
+
+                if valid > emitted {
+                    if !on_chunk(&text[emitted..valid]) {
+                        stopped = true;
+                        break;
+                    }
+                    emitted = valid;
+                }
+            }
+
+            if stopped {
+                let _ = child.kill();
+            }
+            let status = child.wait().map_err(LlmError::Io)?;
+            let stderr = stderr_reader.join().unwrap_or_default();
+            self.record_prompt_cache_use(prompt_cache_file);
+
+            if stopped {
+                let produced = String::from_utf8_lossy(&raw[reply_start.unwrap_or(0)..emitted]);
+                return Ok(produced.trim().to_string());
+            }
+            if !status.success() {
+                return Err(LlmError::ProcessExecution(format!("Process failed with status {}: {}", status, stderr)));
+            }
+
+            let response = String::from_utf8(raw)?;
+            if response.trim().is_empty() {
+                return Err(LlmError::EmptyResponse);
+            }
+            Ok(Self::clean_response(&response, prompt))
+        }
+    }
+
+    impl LlmInterface for LlamaCppBackend {
+        fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+            self.run(prompt, &[])
+        }
+
+        fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+            self.run(prompt, &params.to_args())
+        }
+
+        fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+            self.run_stream(prompt, on_chunk)
+        }
+
+        /// Runs `llama-embedding` from the same directory as the configured executable
+        fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+            let executable = Path::new(&self.config.executable_path).with_file_name("llama-embedding");
+            let output = Command::new(&executable)
+                .args(["--model", &self.config.model_path])
+                .args(["--embd-output-format", "json", "--prompt", text])
+                .stdin(Stdio::null())
+                .output()
+                .map_err(|e| LlmError::ProcessSpawn(format!("Failed to spawn {}: {}", executable.display(), e)))?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(LlmError::ProcessExecution(format!("Process failed with status {}: {}", output.status, stderr)));
+            }
+
+            let response: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+            response["data"][0]["embedding"]
+                .as_array()
+                .map(|values| values.iter().filter_map(|value| value.as_f64()).map(|value| value as f32).collect())
+                .ok_or(LlmError::EmptyResponse)
+        }
+
         fn config(&self) -> &LlmConfig {
             &self.config
         }
@@ -0,0 +1,364 @@
+use crate::config::{self, AgentConfig, ModelEntry};
+use crate::error::LlmError;
+use crate::output::{ModelRecord, ModelSource};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How `import_model` places a file into the models directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    Copy,
+    Move,
+    Hardlink,
+    Symlink,
+}
+
+impl ImportMode {
+    pub fn parse(value: &str) -> Result<Self, LlmError> {
+        match value {
+            "copy" => Ok(ImportMode::Copy),
+            "move" => Ok(ImportMode::Move),
+            "hardlink" => Ok(ImportMode::Hardlink),
+            "symlink" => Ok(ImportMode::Symlink),
+            other => Err(LlmError::InvalidInput(format!(
+                "Unknown import mode '{}': expected copy, move, hardlink or symlink",
+                other
+            ))),
+        }
+    }
+}
+
+/// Every usable model name: registered models sorted by name, then discovered
+/// models not registered under another name, also sorted. Aliases are attached
+/// to the model they refer to.
+pub fn list_models(config: &AgentConfig, discovered: &HashMap<String, ModelEntry>) -> Vec<ModelRecord> {
+    let mut registered: Vec<_> = config.models.iter().collect();
+    registered.sort_by(|a, b| a.0.cmp(b.0));
+    let mut found: Vec<_> = discovered
+        .iter()
+        .filter(|(name, entry)| {
+            !config.models.contains_key(*name) && config.models.values().all(|configured| configured.file != entry.file)
+        })
+        .collect();
+    found.sort_by(|a, b| a.0.cmp(b.0));
+
+    // The same choice as `config::default_model`, without scanning again
+    let default_model = config
+        .runtime
+        .default_model
+        .as_ref()
+        .or_else(|| config.models.keys().min())
+        .or_else(|| discovered.keys().min());
+    let default_target = default_model.map(|name| config::resolve_alias(config, name));
+
+    let mut records: Vec<ModelRecord> = registered
+        .into_iter()
+        .map(|(name, entry)| ModelRecord::new(name, entry, ModelSource::Registered))
+        .chain(found.into_iter().map(|(name, entry)| ModelRecord::new(name, entry, ModelSource::Discovered)))
+        .collect();
+    for record in &mut records {
+        record.default = Some(record.name.as_str()) == default_target;
+        record.aliases = config
+            .aliases
+            .iter()
+            .filter(|(_, model)| **model == record.name)
+            .map(|(alias, _)| alias.clone())
+            .collect();
+    }
+    records
+}
+
+/// Register a model in models.toml. The file is looked up in the models
+/// directory unless it is an absolute path.
+pub fn add_model(name: &str, entry: &ModelEntry) -> Result<(), LlmError> {
+    validate_name(name)?;
+    let config = config::load_config()?;
+    if config.models.contains_key(name) {
+        return Err(LlmError::InvalidInput(format!("Model '{}' is already registered", name)));
+    }
+    if config.aliases.contains_key(name) {
+        return Err(LlmError::InvalidInput(format!("'{}' is already an alias", name)));
+    }
+
+    let path = config::get_models_dir().join(&entry.file);
+    if !path.is_file() {
+        return Err(LlmError::InvalidModelPath(format!(
+            "Model file not found: {} (use `agentd models import` to bring a file in)",
+            path.display()
+        )));
+    }
+
+    let registry = config::get_models_registry_path();
+    let mut doc = config::read_document(&registry)?;
+    let table = config::table_at(&mut doc, &[name])?;
+    for (key, value) in entry_values(entry)? {
+        config::set_document_value(table, &key, value);
+    }
+    config::write_document(&registry, &doc, true)
+}
+
+/// Unregister a model or alias. Aliases pointing at a removed model are removed too, as is a
+/// `runtime.default_model` naming either, and with `delete_file` the model's file is deleted
+/// from the models directory.
+pub fn remove_model(name: &str, delete_file: bool) -> Result<(), LlmError> {
+    let config = config::load_config()?;
+    let config_path = config::get_config_path();
+
+    if config.aliases.contains_key(name) {
+        let mut doc = config::read_document(&config_path)?;
+        remove_aliases(&mut doc, |alias, _| alias == name);
+        replace_default_model(&mut doc, &[name], None);
+        return config::write_document(&config_path, &doc, false);
+    }
+
+    let file_path = if delete_file { Some(config::resolve_model_path(name)?) } else { None };
+    // Only ever delete files agentd manages
+    if let Some(path) = file_path.as_ref().filter(|path| !path.starts_with(config::get_models_dir())) {
+        return Err(LlmError::InvalidInput(format!(
+            "Not deleting {} because it is outside the models directory",
+            path.display()
+        )));
+    }
+
+    let registry = config::get_models_registry_path();
+    let mut registry_doc = config::read_document(&registry)?;
+    let mut config_doc = config::read_document(&config_path)?;
+    let (registry_before, config_before) = (registry_doc.to_string(), config_doc.to_string());
+    let in_registry = registry_doc.as_table_mut().remove(name).is_some();
+    let in_config = config_doc
+        .get_mut("models")
+        .and_then(toml_edit::Item::as_table_like_mut)
+        .and_then(|models| models.remove(name))
+        .is_some();
+
+    if !in_registry && !in_config && file_path.is_none() {
+        return Err(LlmError::InvalidInput(format!(
+            "Model '{}' is not registered; pass --delete-file to delete an auto-discovered model",
+            name
+        )));
+    }
+
+    // A default that named the model, directly or through an alias, would no longer resolve
+    let mut removed: Vec<&str> = config.aliases.iter().filter(|(_, target)| *target == name).map(|(alias, _)| alias.as_str()).collect();
+    removed.push(name);
+    remove_aliases(&mut config_doc, |_, target| target == name);
+    replace_default_model(&mut config_doc, &removed, None);
+    save_if_changed(&registry, &registry_doc, &registry_before, true)?;
+    save_if_changed(&config_path, &config_doc, &config_before, false)?;
+
+    if let Some(path) = file_path {
+        fs::remove_file(&path).map_err(LlmError::Io)?;
+    }
+
+    Ok(())
+}
+
+/// Rename a registered model, keeping its position and comments in models.toml
+/// and repointing aliases and `runtime.default_model`. An auto-discovered model is registered under the new name.
+pub fn rename_model(old: &str, new: &str) -> Result<(), LlmError> {
+    validate_name(new)?;
+    let config = config::load_config()?;
+    if config.models.contains_key(new) || config.aliases.contains_key(new) {
+        return Err(LlmError::InvalidInput(format!("'{}' is already in use", new)));
+    }
+
+    let registry = config::get_models_registry_path();
+    let config_path = config::get_config_path();
+    let mut registry_doc = config::read_document(&registry)?;
+    let mut config_doc = config::read_document(&config_path)?;
+    let (registry_before, config_before) = (registry_doc.to_string(), config_doc.to_string());
+
+    if let Some(item) = registry_doc.as_table_mut().remove(old) {
+        registry_doc.as_table_mut().insert(new, item);
+    } else if let Some(item) = config_doc
+        .get_mut("models")
+        .and_then(toml_edit::Item::as_table_like_mut)
+        .and_then(|models| models.remove(old))
+    {
+        config::table_at(&mut config_doc, &["models"])?.insert(new, item);
+    } else {
+        let entry = config::find_model_entry(old)?;
+        let table = config::table_at(&mut registry_doc, &[new])?;
+        for (key, value) in entry_values(&entry)? {
+            config::set_document_value(table, &key, value);
+        }
+    }
+
+    if let Some(aliases) = config_doc.get_mut("aliases").and_then(toml_edit::Item::as_table_like_mut) {
+        for (_, target) in aliases.iter_mut() {
+            if target.as_str() == Some(old) {
+                set_keeping_decor(target, new);
+            }
+        }
+    }
+    replace_default_model(&mut config_doc, &[old], Some(new));
+
+    save_if_changed(&registry, &registry_doc, &registry_before, true)?;
+    save_if_changed(&config_path, &config_doc, &config_before, false)
+}
+
+/// Point `alias` at an existing model, replacing any previous target
+pub fn set_alias(alias: &str, model: &str) -> Result<(), LlmError> {
+    validate_name(alias)?;
+    let config = config::load_config()?;
+    if config.models.contains_key(alias) {
+        return Err(LlmError::InvalidInput(format!("'{}' is already a model name", alias)));
+    }
+    if config.aliases.contains_key(model) {
+        return Err(LlmError::InvalidInput(format!("'{}' is an alias; point at a model instead", model)));
+    }
+    config::find_model_entry(model)?;
+
+    let config_path = config::get_config_path();
+    let mut doc = config::read_document(&config_path)?;
+    config::set_document_value(config::table_at(&mut doc, &["aliases"])?, alias, model.into());
+    config::write_document(&config_path, &doc, false)
+}
+
+/// Bring a GGUF file into the models directory and register it, named after
+/// the file unless `name` is given. Returns the registered name and new path.
+pub fn import_model(
+    source: &Path,
+    name: Option<&str>,
+    mode: ImportMode,
+    description: Option<String>,
+    context_size: Option<u32>,
+) -> Result<(String, PathBuf), LlmError> {
+    if !source.is_file() {
+        return Err(LlmError::InvalidModelPath(format!("Not a file: {}", source.display())));
+    }
+    let file_name = source
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| LlmError::InvalidModelPath(format!("Invalid file name: {}", source.display())))?
+        .to_string();
+    let name = name
+        .map(str::to_string)
+        .unwrap_or_else(|| file_name.trim_end_matches(".gguf").to_string());
+
+    validate_name(&name)?;
+    let config = config::load_config()?;
+    if config.models.contains_key(&name) || config.aliases.contains_key(&name) {
+        return Err(LlmError::InvalidInput(format!("'{}' is already in use", name)));
+    }
+
+    let models_dir = config::get_models_dir();
+    let target = models_dir.join(&file_name);
+    if target.exists() {
+        return Err(LlmError::InvalidInput(format!("{} already exists", target.display())));
+    }
+    fs::create_dir_all(&models_dir).map_err(LlmError::Io)?;
+    place_file(source, &target, mode)?;
+
+    let entry = ModelEntry {
+        file: file_name,
+        description,
+        context_size,
+        ..ModelEntry::default()
+    };
+    if let Err(e) = add_model(&name, &entry) {
+        // Leave the source untouched if registration fails
+        if mode == ImportMode::Move {
+            let _ = place_file(&target, source, ImportMode::Move);
+        } else {
+            let _ = fs::remove_file(&target);
+        }
+        return Err(e);
+    }
+
+    Ok((name, target))
+}
+
+fn place_file(source: &Path, target: &Path, mode: ImportMode) -> Result<(), LlmError> {
+    match mode {
+        ImportMode::Copy => fs::copy(source, target).map(|_| ()),
+        ImportMode::Move => fs::rename(source, target).or_else(|_| {
+            // Renaming fails across filesystems
+            fs::copy(source, target)?;
+            fs::remove_file(source)
+        }),
+        ImportMode::Hardlink => fs::hard_link(source, target),
+        ImportMode::Symlink => {
+            let source = source.canonicalize().map_err(LlmError::Io)?;
+            #[cfg(unix)]
+            let linked = std::os::unix::fs::symlink(&source, target);
+            #[cfg(windows)]
+            let linked = std::os::windows::fs::symlink_file(&source, target);
+            linked
+        }
+    }
+    .map_err(LlmError::Io)
+}
+
+/// The entry's fields in declaration order, ready to insert into a document
+fn entry_values(entry: &ModelEntry) -> Result<Vec<(String, toml_edit::Value)>, LlmError> {
+    let rendered = toml::to_string(entry)
+        .map_err(|e| LlmError::Config(format!("Failed to serialize model entry: {}", e)))?;
+    let doc: toml_edit::DocumentMut = rendered
+        .parse()
+        .map_err(|e| LlmError::Config(format!("Failed to serialize model entry: {}", e)))?;
+    Ok(doc
+        .iter()
+        .filter_map(|(key, item)| Some((key.to_string(), item.as_value()?.clone())))
+        .collect())
+}
+
+/// Avoid creating or rewriting files the operation didn't touch
+fn save_if_changed(path: &Path, doc: &toml_edit::DocumentMut, before: &str, is_models_file: bool) -> Result<(), LlmError> {
+    if doc.to_string() == before {
+        return Ok(());
+    }
+    config::write_document(path, doc, is_models_file)
+}
+
+fn remove_aliases(doc: &mut toml_edit::DocumentMut, matches: impl Fn(&str, &str) -> bool) {
+    if let Some(aliases) = doc.get_mut("aliases").and_then(toml_edit::Item::as_table_like_mut) {
+        let doomed: Vec<String> = aliases
+            .iter()
+            .filter(|(alias, target)| matches(alias, target.as_str().unwrap_or("")))
+            .map(|(alias, _)| alias.to_string())
+            .collect();
+        for alias in doomed {
+            aliases.remove(&alias);
+        }
+        if aliases.is_empty() {
+            doc.remove("aliases");
+        }
+    }
+}
+
+/// Point `runtime.default_model` at `new` if it is one of `names`, or drop it when `new` is `None`
+fn replace_default_model(doc: &mut toml_edit::DocumentMut, names: &[&str], new: Option<&str>) {
+    let Some(runtime) = doc.get_mut("runtime").and_then(toml_edit::Item::as_table_like_mut) else {
+        return;
+    };
+    let named = runtime.get("default_model").and_then(toml_edit::Item::as_str).is_some_and(|model| names.contains(&model));
+    match new {
+        _ if !named => {}
+        Some(new) => {
+            if let Some(item) = runtime.get_mut("default_model") {
+                set_keeping_decor(item, new);
+            }
+        }
+        None => {
+            runtime.remove("default_model");
+        }
+    }
+}
+
+/// Replace a string value without losing the comment next to it
+fn set_keeping_decor(item: &mut toml_edit::Item, new: &str) {
+    if let Some(value) = item.as_value_mut() {
+        let decor = value.decor().clone();
+        *value = new.into();
+        *value.decor_mut() = decor;
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), LlmError> {
+    if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c == '/' || c == '\\') {
+        return Err(LlmError::InvalidInput(format!("Invalid model name '{}'", name)));
+    }
+    Ok(())
+}
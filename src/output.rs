@@ -0,0 +1,634 @@
+use crate::agent::{AgentDefinition, AgentRun};
+use crate::cache::CacheStats;
+use crate::config::{self, ConfigIssue, ModelEntry};
+use crate::error::LlmError;
+use crate::eval::{CaseResult, EvalReport};
+use crate::llm::{estimate_tokens, Usage};
+use crate::prompts::PromptTemplate;
+use crate::session::{SessionRecord, SessionSummary};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How commands print their results, chosen with the global `--format` flag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Plain,
+    /// Aligned columns under a header row
+    Table,
+    /// A single JSON document
+    Json,
+    /// One compact JSON object per line
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub fn is_plain(self) -> bool {
+        self == OutputFormat::Plain
+    }
+}
+
+/// A record that `--format table` can show as a row
+pub trait Tabular: Serialize {
+    fn headers() -> &'static [&'static str];
+    fn row(&self) -> Vec<String>;
+}
+
+/// Render `records` in a machine-readable format: a JSON array, one object per
+/// line, or a table. Returns `None` for `plain`, which each command renders itself.
+pub fn render_list<T: Tabular>(format: OutputFormat, records: &[T]) -> Result<Option<String>, LlmError> {
+    let rendered = match format {
+        OutputFormat::Plain => return Ok(None),
+        OutputFormat::Table => render_table(T::headers(), records.iter().map(T::row)),
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(records)?),
+        OutputFormat::Jsonl => records
+            .iter()
+            .map(|record| Ok(format!("{}\n", serde_json::to_string(record)?)))
+            .collect::<Result<String, LlmError>>()?,
+    };
+    Ok(Some(rendered))
+}
+
+/// Render one record; tables show it as one field per line
+pub fn render_record<T: Tabular>(format: OutputFormat, record: &T) -> Result<Option<String>, LlmError> {
+    let rendered = match format {
+        OutputFormat::Plain => return Ok(None),
+        OutputFormat::Table => render_table(
+            &["FIELD", "VALUE"],
+            T::headers().iter().zip(record.row()).map(|(header, value)| vec![header.to_string(), value]),
+        ),
+        OutputFormat::Json => format!("{}\n", serde_json::to_string_pretty(record)?),
+        OutputFormat::Jsonl => format!("{}\n", serde_json::to_string(record)?),
+    };
+    Ok(Some(rendered))
+}
+
+/// Print `records` in `format`, calling `plain` for human-readable output
+pub fn print_list<T: Tabular>(format: OutputFormat, records: &[T], plain: impl FnOnce()) -> Result<(), LlmError> {
+    match render_list(format, records)? {
+        Some(rendered) => print!("{}", rendered),
+        None => plain(),
+    }
+    Ok(())
+}
+
+/// Print one record in `format`, calling `plain` for human-readable output
+pub fn print_record<T: Tabular>(format: OutputFormat, record: &T, plain: impl FnOnce()) -> Result<(), LlmError> {
+    match render_record(format, record)? {
+        Some(rendered) => print!("{}", rendered),
+        None => plain(),
+    }
+    Ok(())
+}
+
+/// Print any serializable value as JSON: pretty for `json`, on one line otherwise
+pub fn print_json<T: Serialize + ?Sized>(format: OutputFormat, value: &T) -> Result<(), LlmError> {
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        println!("{}", serde_json::to_string(value)?);
+    }
+    Ok(())
+}
+
+/// Columns padded to their widest cell; line breaks inside cells become spaces
+pub fn render_table(headers: &[&str], rows: impl IntoIterator<Item = Vec<String>>) -> String {
+    let mut lines: Vec<Vec<String>> = vec![headers.iter().map(|header| header.to_string()).collect()];
+    lines.extend(rows.into_iter().map(|row| row.iter().map(|cell| cell.replace(['\r', '\n'], " ")).collect()));
+
+    let mut widths = vec![0; headers.len()];
+    for line in &lines {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut rendered = String::new();
+    for line in &lines {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        rendered.push_str(cells.join("  ").trim_end());
+        rendered.push('\n');
+    }
+    rendered
+}
+
+/// Whether a listed model can be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelStatus {
+    /// The model file exists
+    Available,
+    /// The model file is not in the models directory
+    Missing,
+    /// Served by a remote API; there is no local file
+    Remote,
+}
+
+impl ModelStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ModelStatus::Available => "available",
+            ModelStatus::Missing => "missing",
+            ModelStatus::Remote => "remote",
+        }
+    }
+}
+
+/// Where a listed model was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelSource {
+    /// Registered in models.toml
+    Registered,
+    /// Found by scanning the discovery roots and caches
+    Discovered,
+}
+
+impl ModelSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ModelSource::Registered => "registered",
+            ModelSource::Discovered => "discovered",
+        }
+    }
+}
+
+/// A model as shown by `agentd list` and `agentd info`
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelRecord {
+    pub name: String,
+    /// Resolved model file; `null` for remote models
+    pub path: Option<PathBuf>,
+    pub status: ModelStatus,
+    /// Size of the model file in bytes; `null` unless the file exists
+    pub size: Option<u64>,
+    pub source: ModelSource,
+    /// Whether this is the model used when none is named
+    pub default: bool,
+    /// Aliases that refer to this model
+    pub aliases: Vec<String>,
+    pub description: Option<String>,
+    /// The model's settings, with the same keys as in models.toml
+    pub metadata: ModelEntry,
+}
+
+impl ModelRecord {
+    pub fn new(name: &str, entry: &ModelEntry, source: ModelSource) -> Self {
+        let (path, status, size) = if entry.is_remote() {
+            (None, ModelStatus::Remote, None)
+        } else {
+            let path = config::get_models_dir().join(&entry.file);
+            match std::fs::metadata(&path) {
+                Ok(metadata) => (Some(path), ModelStatus::Available, Some(metadata.len())),
+                Err(_) => (Some(path), ModelStatus::Missing, None),
+            }
+        };
+
+        Self {
+            name: name.to_string(),
+            path,
+            status,
+            size,
+            source,
+            default: false,
+            aliases: Vec::new(),
+            description: entry.description.clone(),
+            metadata: entry.clone(),
+        }
+    }
+}
+
+impl Tabular for ModelRecord {
+    fn headers() -> &'static [&'static str] {
+        &["NAME", "STATUS", "SIZE", "SOURCE", "DEFAULT", "PATH", "DESCRIPTION"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let path = match &self.path {
+            Some(path) => path.display().to_string(),
+            None => self.metadata.base_url.clone().unwrap_or_default(),
+        };
+        vec![
+            self.name.clone(),
+            self.status.as_str().to_string(),
+            self.size.map(|size| size.to_string()).unwrap_or_default(),
+            self.source.as_str().to_string(),
+            if self.default { "yes".to_string() } else { String::new() },
+            path,
+            self.description.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Timings {
+    /// Wall-clock time spent generating the reply
+    pub total_ms: u64,
+}
+
+/// A reply from `agentd generate` or `agentd chat`
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationRecord {
+    pub model: String,
+    /// Session the exchange was saved to; omitted for one-off turns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// The reply, with surrounding whitespace trimmed
+    pub text: String,
+    /// The reply parsed as JSON, when it was constrained to a schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<serde_json::Value>,
+    pub usage: Usage,
+    pub timings: Timings,
+}
+
+impl GenerationRecord {
+    pub fn new(model: &str, prompt_tokens: usize, text: &str, elapsed: Duration) -> Self {
+        let text = text.trim().to_string();
+        let completion_tokens = estimate_tokens(&text);
+        Self {
+            model: model.to_string(),
+            session: None,
+            text,
+            json: None,
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            },
+            timings: Timings {
+                total_ms: elapsed.as_millis() as u64,
+            },
+        }
+    }
+}
+
+impl Tabular for GenerationRecord {
+    fn headers() -> &'static [&'static str] {
+        &["MODEL", "SESSION", "PROMPT_TOKENS", "COMPLETION_TOKENS", "TOTAL_MS", "TEXT"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.model.clone(),
+            self.session.clone().unwrap_or_default(),
+            self.usage.prompt_tokens.to_string(),
+            self.usage.completion_tokens.to_string(),
+            self.timings.total_ms.to_string(),
+            self.text.clone(),
+        ]
+    }
+}
+
+/// Outcome of a command that changes something, e.g. `sessions delete`
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionRecord {
+    /// What was done, e.g. `deleted`, `renamed`, `created`
+    pub action: String,
+    /// The session, model, key or file acted on
+    pub target: String,
+    /// Number of items affected, for commands that remove several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    /// The same message plain output prints
+    pub message: String,
+}
+
+impl ActionRecord {
+    pub fn new(action: &str, target: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            action: action.to_string(),
+            target: target.into(),
+            count: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl Tabular for ActionRecord {
+    fn headers() -> &'static [&'static str] {
+        &["ACTION", "TARGET", "COUNT", "MESSAGE"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.action.clone(),
+            self.target.clone(),
+            self.count.map(|count| count.to_string()).unwrap_or_default(),
+            self.message.clone(),
+        ]
+    }
+}
+
+/// Print an action's message, or the action as a record in machine-readable formats
+pub fn print_action(format: OutputFormat, action: ActionRecord) -> Result<(), LlmError> {
+    print_record(format, &action, || println!("{}", action.message))
+}
+
+/// Totals from `agentd batch`; the results themselves are in the output file
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchSummary {
+    pub output: PathBuf,
+    /// Requests in the input file
+    pub total: usize,
+    /// Requests already completed in the output file, with `--resume`
+    pub skipped: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl Tabular for BatchSummary {
+    fn headers() -> &'static [&'static str] {
+        &["OUTPUT", "TOTAL", "SKIPPED", "SUCCEEDED", "FAILED"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.output.display().to_string(),
+            self.total.to_string(),
+            self.skipped.to_string(),
+            self.succeeded.to_string(),
+            self.failed.to_string(),
+        ]
+    }
+}
+
+/// An agent definition as listed by `agent list`
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentRecord {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub description: Option<String>,
+    pub model: Option<String>,
+    /// Built-in tools the definition enables
+    pub tools: Vec<String>,
+}
+
+impl AgentRecord {
+    pub fn new(name: &str, definition: &AgentDefinition) -> Self {
+        let tools = definition
+            .tools
+            .tools()
+            .map(|tools| tools.iter().map(|tool| tool.name().to_string()).collect())
+            .unwrap_or_default();
+        Self {
+            name: name.to_string(),
+            path: definition.path.clone(),
+            description: definition.description.clone(),
+            model: definition.model.clone(),
+            tools,
+        }
+    }
+}
+
+impl Tabular for AgentRecord {
+    fn headers() -> &'static [&'static str] {
+        &["NAME", "MODEL", "TOOLS", "DESCRIPTION"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.model.clone().unwrap_or_default(),
+            self.tools.join(", "),
+            self.description.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl Tabular for AgentRun {
+    fn headers() -> &'static [&'static str] {
+        &["STOP_REASON", "STEPS", "TOOL_CALLS", "TOTAL_TOKENS", "ANSWER"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.stop_reason.as_str().to_string(),
+            self.steps.to_string(),
+            self.tool_calls.len().to_string(),
+            self.usage.total_tokens.to_string(),
+            self.answer.clone(),
+        ]
+    }
+}
+
+impl Tabular for CaseResult {
+    fn headers() -> &'static [&'static str] {
+        &["MODEL", "CASE", "PASSED", "DURATION_MS", "FAILURES"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.model.clone(),
+            self.case.clone(),
+            self.passed.to_string(),
+            self.duration_ms.to_string(),
+            self.failures.join("; "),
+        ]
+    }
+}
+
+/// Passed and total cases for one model
+#[derive(Debug, Clone, Serialize)]
+pub struct PassRate {
+    pub model: String,
+    pub passed: usize,
+    pub total: usize,
+}
+
+/// The whole run printed by `agentd eval --format json`
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalSummary<'a> {
+    pub results: &'a [CaseResult],
+    /// Sorted by model name
+    pub pass_rates: Vec<PassRate>,
+    /// `model::case` keys that passed in the baseline but fail now; `null` without `--baseline`
+    pub regressions: Option<Vec<String>>,
+}
+
+impl<'a> EvalSummary<'a> {
+    pub fn new(report: &'a EvalReport, regressions: Option<&[&CaseResult]>) -> Self {
+        Self {
+            results: &report.results,
+            pass_rates: report
+                .pass_rates()
+                .into_iter()
+                .map(|(model, (passed, total))| PassRate { model, passed, total })
+                .collect(),
+            regressions: regressions.map(|results| results.iter().map(|result| result.key()).collect()),
+        }
+    }
+}
+
+/// Size and settings of one cache, from `agentd cache stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheRecord {
+    /// `responses` or `prompt_cache`
+    pub cache: String,
+    /// Cached responses or prompt-cache files
+    pub entries: usize,
+    pub bytes: u64,
+    pub limit_mb: u64,
+    pub enabled: bool,
+}
+
+impl CacheRecord {
+    pub fn new(cache: &str, stats: &CacheStats, limit_mb: u64, enabled: bool) -> Self {
+        Self {
+            cache: cache.to_string(),
+            entries: stats.entries,
+            bytes: stats.bytes,
+            limit_mb,
+            enabled,
+        }
+    }
+}
+
+impl Tabular for CacheRecord {
+    fn headers() -> &'static [&'static str] {
+        &["CACHE", "ENTRIES", "BYTES", "LIMIT_MB", "ENABLED"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.cache.clone(),
+            self.entries.to_string(),
+            self.bytes.to_string(),
+            self.limit_mb.to_string(),
+            self.enabled.to_string(),
+        ]
+    }
+}
+
+/// A configuration value and the layer it came from
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigValueRecord {
+    /// Dotted key, e.g. `defaults.temperature`
+    pub key: String,
+    pub value: toml::Value,
+    /// `default`, `user (<path>)`, `env (<var>)`, ...
+    pub source: String,
+}
+
+impl ConfigValueRecord {
+    pub fn new(key: &str, value: &toml::Value, source: impl ToString) -> Self {
+        Self {
+            key: key.to_string(),
+            value: value.clone(),
+            source: source.to_string(),
+        }
+    }
+}
+
+impl Tabular for ConfigValueRecord {
+    fn headers() -> &'static [&'static str] {
+        &["KEY", "VALUE", "SOURCE"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let value = match &self.value {
+            toml::Value::String(text) => text.clone(),
+            value => value.to_string(),
+        };
+        vec![self.key.clone(), value, self.source.clone()]
+    }
+}
+
+impl Tabular for ConfigIssue {
+    fn headers() -> &'static [&'static str] {
+        &["SOURCE", "MESSAGE"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![self.source.clone(), self.message.clone()]
+    }
+}
+
+impl Tabular for SessionSummary {
+    fn headers() -> &'static [&'static str] {
+        &["NAME", "MODEL", "MESSAGES", "UPDATED_AT"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.model.clone(),
+            self.message_count.to_string(),
+            self.updated_at.to_string(),
+        ]
+    }
+}
+
+impl Tabular for SessionRecord {
+    fn headers() -> &'static [&'static str] {
+        &["NAME", "MODEL", "CONTEXT_SIZE", "MESSAGES", "CREATED_AT", "UPDATED_AT"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.model.clone(),
+            self.context_size.map(|size| size.to_string()).unwrap_or_default(),
+            self.messages.len().to_string(),
+            self.created_at.to_string(),
+            self.updated_at.to_string(),
+        ]
+    }
+}
+
+/// A prompt template, from `agentd prompts list` and `agentd prompts show`
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptRecord {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub description: Option<String>,
+    /// Preferred model, if the template names one
+    pub model: Option<String>,
+    /// Variables the template uses or declares
+    pub vars: Vec<String>,
+    /// Variables without a default, which must be given with `--var`
+    pub required: Vec<String>,
+}
+
+impl PromptRecord {
+    pub fn new(name: &str, template: &PromptTemplate) -> Self {
+        let mut vars = template.placeholders();
+        vars.extend(template.vars.keys().filter(|name| !vars.contains(name)).cloned().collect::<Vec<_>>());
+        let required = vars
+            .iter()
+            .filter(|name| template.vars.get(*name).and_then(|spec| spec.default.as_ref()).is_none())
+            .cloned()
+            .collect();
+        Self {
+            name: name.to_string(),
+            path: template.path.clone(),
+            description: template.description.clone(),
+            model: template.model.clone(),
+            vars,
+            required,
+        }
+    }
+}
+
+impl Tabular for PromptRecord {
+    fn headers() -> &'static [&'static str] {
+        &["NAME", "MODEL", "VARS", "REQUIRED", "DESCRIPTION"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.model.clone().unwrap_or_default(),
+            self.vars.join(","),
+            self.required.join(","),
+            self.description.clone().unwrap_or_default(),
+        ]
+    }
+}
@@ -0,0 +1,230 @@
+use crate::config;
+use crate::error::LlmError;
+use crate::llm::GenerationParams;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Project-local template directory, looked up from the current directory upwards
+pub const PROJECT_PROMPTS_DIR: &str = ".agentd/prompts";
+
+/// A reusable prompt, stored as `<name>.toml` in a prompts directory.
+///
+/// `template` and `system` may contain `{{name}}` for a variable, `{{@path}}`
+/// for the contents of a file (relative to the template's directory) and
+/// `{{@-}}` for standard input. Included text loses its trailing line breaks
+/// and is not expanded again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Model or alias to use unless one is given when running the template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub template: String,
+    /// Sampling parameters, overriding the model's own
+    #[serde(flatten)]
+    pub params: GenerationParams,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, VarSpec>,
+    /// File the template was loaded from; `{{@path}}` includes are relative to it
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// A declared template variable. Variables without a default are required.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VarSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A template with its variables filled in
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedPrompt {
+    pub prompt: String,
+    pub system: Option<String>,
+    pub model: Option<String>,
+    pub params: GenerationParams,
+}
+
+impl PromptTemplate {
+    pub fn from_file(path: &Path) -> Result<Self, LlmError> {
+        let content = fs::read_to_string(path).map_err(LlmError::Io)?;
+        let mut template: PromptTemplate = toml::from_str(&content)
+            .map_err(|e| LlmError::Config(format!("Invalid prompt template {}: {}", path.display(), e)))?;
+        template.path = Some(path.to_path_buf());
+        Ok(template)
+    }
+
+    /// Variables used in `template` or `system`, in order of first use
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let text = [self.system.as_deref().unwrap_or_default(), self.template.as_str()];
+        for captures in text.iter().flat_map(|text| placeholder_pattern().captures_iter(text)) {
+            let name = captures[1].to_string();
+            if !name.starts_with('@') && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Fill in variables from `vars`, falling back to declared defaults. Every
+    /// variable the template uses must have a value, and every value given
+    /// must belong to a variable.
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<RenderedPrompt, LlmError> {
+        let used = self.placeholders();
+        if let Some(unknown) = vars.keys().find(|name| !used.contains(name) && !self.vars.contains_key(*name)) {
+            return Err(LlmError::InvalidInput(format!("Template has no variable '{}'", unknown)));
+        }
+
+        let mut values = HashMap::new();
+        for name in &used {
+            let value = vars
+                .get(name)
+                .or_else(|| self.vars.get(name).and_then(|spec| spec.default.as_ref()))
+                .ok_or_else(|| LlmError::InvalidInput(format!("Missing value for variable '{}'; pass --var {}=...", name, name)))?;
+            values.insert(name.as_str(), value.as_str());
+        }
+
+        let base_dir = self.path.as_deref().and_then(Path::parent).unwrap_or(Path::new("."));
+        let mut stdin = None;
+        let mut expand = |text: &str| -> Result<String, LlmError> {
+            let mut failure = None;
+            let expanded = placeholder_pattern().replace_all(text, |captures: &Captures| {
+                let name = &captures[1];
+                let included = match name.strip_prefix('@') {
+                    None => return values[name].to_string(),
+                    Some("-") => read_stdin(&mut stdin),
+                    Some(path) => fs::read_to_string(base_dir.join(path)).map_err(|e| {
+                        LlmError::InvalidInput(format!("Cannot include {}: {}", base_dir.join(path).display(), e))
+                    }),
+                };
+                match included {
+                    Ok(text) => text.trim_end_matches(['\r', '\n']).to_string(),
+                    Err(e) => {
+                        failure.get_or_insert(e);
+                        String::new()
+                    }
+                }
+            });
+            match failure {
+                Some(e) => Err(e),
+                None => Ok(expanded.into_owned()),
+            }
+        };
+
+        Ok(RenderedPrompt {
+            prompt: expand(&self.template)?,
+            system: self.system.as_deref().map(&mut expand).transpose()?,
+            model: self.model.clone(),
+            params: self.params.clone(),
+        })
+    }
+}
+
+/// `{{name}}`, `{{@path}}` or `{{@-}}`
+fn placeholder_pattern() -> &'static Regex {
+    static PATTERN: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"\{\{\s*([^{}\s]+)\s*\}\}").unwrap())
+}
+
+/// Standard input, read on first use so a template can include it more than once
+fn read_stdin(cache: &mut Option<String>) -> Result<String, LlmError> {
+    if cache.is_none() {
+        let mut buffer = String::new();
+        std::io::stdin().read_to_string(&mut buffer).map_err(LlmError::Io)?;
+        *cache = Some(buffer);
+    }
+    Ok(cache.clone().unwrap_or_default())
+}
+
+/// Directories searched for templates, highest precedence first: `.agentd/prompts`
+/// in the current directory and each parent, then the user's prompts directory
+pub fn template_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::current_dir()
+        .map(|cwd| cwd.ancestors().map(|dir| dir.join(PROJECT_PROMPTS_DIR)).filter(|dir| dir.is_dir()).collect())
+        .unwrap_or_default();
+    dirs.push(config::get_prompts_dir());
+    dirs
+}
+
+/// Path of the template called `name`; a project template hides a user template of the same name
+pub fn find_template(name: &str) -> Result<PathBuf, LlmError> {
+    validate_template_name(name)?;
+    template_dirs()
+        .into_iter()
+        .map(|dir| dir.join(format!("{}.toml", name)))
+        .find(|path| path.is_file())
+        .ok_or_else(|| LlmError::InvalidInput(format!("No prompt template named '{}'", name)))
+}
+
+pub fn load_template(name: &str) -> Result<PromptTemplate, LlmError> {
+    PromptTemplate::from_file(&find_template(name)?)
+}
+
+/// Every template that `find_template` can reach, sorted by name
+pub fn list_templates() -> Result<BTreeMap<String, PromptTemplate>, LlmError> {
+    let mut templates = BTreeMap::new();
+    for dir in template_dirs() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry.map_err(LlmError::Io)?.path();
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if path.extension().is_some_and(|ext| ext == "toml") && !templates.contains_key(name) {
+                templates.insert(name.to_string(), PromptTemplate::from_file(&path)?);
+            }
+        }
+    }
+    Ok(templates)
+}
+
+/// Load the template called `name` and fill in `vars`
+pub fn render(name: &str, vars: &HashMap<String, String>) -> Result<RenderedPrompt, LlmError> {
+    load_template(name)?.render(vars)
+}
+
+/// Parse a `name=value` variable from the command line. A value of `@path`
+/// is replaced by the file's contents and `@-` by standard input, without
+/// trailing line breaks.
+pub fn parse_var(raw: &str) -> Result<(String, String), LlmError> {
+    let (name, value) = raw
+        .split_once('=')
+        .ok_or_else(|| LlmError::InvalidInput(format!("Expected NAME=VALUE, got '{}'", raw)))?;
+    let value = match value.strip_prefix('@') {
+        Some("-") => read_stdin(&mut None)?,
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| LlmError::InvalidInput(format!("Cannot read {} for variable '{}': {}", path, name, e)))?,
+        None => return Ok((name.trim().to_string(), value.to_string())),
+    };
+    Ok((name.trim().to_string(), value.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn validate_template_name(name: &str) -> Result<(), LlmError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(LlmError::InvalidInput(format!(
+            "Invalid template name '{}': use letters, digits, '-', '_' or '.'",
+            name
+        )))
+    }
+}
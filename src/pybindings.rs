@@ -1,7 +1,11 @@
+// pyo3 0.22 macros expand `PyResult` returns through a no-op `Into` conversion
+#![allow(clippy::useless_conversion)]
+
 use pyo3::prelude::*;
 use pyo3::exceptions::PyRuntimeError;
-use crate::{open, LlmInterface};
+use crate::{open_with, LlmInterface, OpenOptions};
 use crate::llm::LlmConfig;
+use std::collections::HashMap;
 
 /// Python wrapper around the Llm struct
 #[pyclass]
@@ -18,6 +22,16 @@ impl PyLlm {
             .map_err(|e| PyRuntimeError::new_err(format!("Generation failed: {}", e)))
     }
 
+    /// Generate JSON satisfying a JSON Schema (given as a JSON string); returns the validated JSON
+    #[pyo3(text_signature = "($self, prompt, schema)")]
+    fn generate_json(&self, prompt: &str, schema: &str) -> PyResult<String> {
+        let schema: serde_json::Value = serde_json::from_str(schema)
+            .map_err(|e| PyRuntimeError::new_err(format!("Invalid schema: {}", e)))?;
+        self.inner.generate_json(prompt, &schema)
+            .map(|value| value.to_string())
+            .map_err(|e| PyRuntimeError::new_err(format!("Generation failed: {}", e)))
+    }
+
     /// Create a new instance with additional arguments
     #[pyo3(text_signature = "($self, args)")]
     fn with_args(_slf: PyRef<'_, Self>, _args: Vec<String>) -> PyResult<PyLlm> {
@@ -63,58 +77,52 @@ impl PyLlmConfig {
 }
 
 /// Open a model by name and return a PyLlm instance
-/// If no model_name is provided, uses the first available model
+/// model_name may be an alias; if omitted, uses the configured default model
+/// Pass cache=False to bypass the response cache for deterministic requests
+/// and preset="precise" to apply a sampling preset from config.toml
 #[pyfunction]
-#[pyo3(signature = (model_name = None))]
-fn py_open(model_name: Option<&str>) -> PyResult<PyLlm> {
+#[pyo3(signature = (model_name = None, cache = true, preset = None))]
+fn py_open(model_name: Option<&str>, cache: bool, preset: Option<String>) -> PyResult<PyLlm> {
     let actual_model_name = match model_name {
         Some(name) => name.to_string(),
-        None => {
-            // Get the first available model
-            match crate::discover_models() {
-                Ok(models) => {
-                    if let Some(first_model) = models.keys().next() {
-                        first_model.clone()
-                    } else {
-                        return Err(PyRuntimeError::new_err("No models available. Please download a model first."));
-                    }
-                }
-                Err(e) => return Err(PyRuntimeError::new_err(format!("Failed to discover models: {}", e))),
-            }
-        }
+        None => crate::config::default_model().map_err(|e| PyRuntimeError::new_err(e.to_string()))?,
     };
     
-    match open(&actual_model_name) {
+    let options = OpenOptions {
+        preset,
+        ..OpenOptions::default()
+    };
+    match open_with(&actual_model_name, &options.no_cache(!cache)) {
         Ok(llm) => Ok(PyLlm { inner: llm }),
         Err(e) => Err(PyRuntimeError::new_err(format!("Failed to open model '{}': {}", actual_model_name, e))),
     }
 }
 
 /// Open a model by name with custom arguments
-/// If no model_name is provided, uses the first available model
+/// model_name may be an alias; if omitted, uses the configured default model
+/// Pass cache=False to bypass the response cache for deterministic requests
+/// args replace the model's own arguments; preset="precise" adds a sampling preset beneath them
 #[pyfunction]
-#[pyo3(signature = (args, model_name = None))]
-fn py_open_with_args(args: Vec<String>, model_name: Option<&str>) -> PyResult<PyLlm> {
+#[pyo3(signature = (args, model_name = None, cache = true, preset = None))]
+fn py_open_with_args(args: Vec<String>, model_name: Option<&str>, cache: bool, preset: Option<&str>) -> PyResult<PyLlm> {
     let actual_model_name = match model_name {
         Some(name) => name.to_string(),
-        None => {
-            // Get the first available model
-            match crate::discover_models() {
-                Ok(models) => {
-                    if let Some(first_model) = models.keys().next() {
-                        first_model.clone()
-                    } else {
-                        return Err(PyRuntimeError::new_err("No models available. Please download a model first."));
-                    }
-                }
-                Err(e) => return Err(PyRuntimeError::new_err(format!("Failed to discover models: {}", e))),
+        None => crate::config::default_model().map_err(|e| PyRuntimeError::new_err(e.to_string()))?,
+    };
+    let preset_args = match preset {
+        Some(preset) => {
+            let config = crate::config::load_config().map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+            match config.presets.get(preset) {
+                Some(params) => params.to_args(),
+                None => return Err(PyRuntimeError::new_err(format!("Unknown preset '{}'", preset))),
             }
         }
+        None => Vec::new(),
     };
     
-    match open(&actual_model_name) {
+    match open_with(&actual_model_name, &OpenOptions::default().no_cache(!cache)) {
         Ok(llm) => {
-            let llm_with_args = llm.with_args(args);
+            let llm_with_args = llm.with_args([preset_args, args].concat());
             Ok(PyLlm { inner: llm_with_args })
         }
         Err(e) => Err(PyRuntimeError::new_err(format!("Failed to open model '{}': {}", actual_model_name, e))),
@@ -131,12 +139,73 @@ fn py_list_models() -> PyResult<Vec<String>> {
     }
 }
 
+/// Render a prompt template from .agentd/prompts/ or ~/.agentd/prompts/
+/// Returns a dict with "prompt", "system" and "model" (None when the template has none)
+#[pyfunction]
+#[pyo3(signature = (name, vars = None))]
+fn py_render_prompt(name: &str, vars: Option<HashMap<String, String>>) -> PyResult<HashMap<String, Option<String>>> {
+    let rendered = crate::prompts::render(name, &vars.unwrap_or_default())
+        .map_err(|e| PyRuntimeError::new_err(format!("Failed to render prompt '{}': {}", name, e)))?;
+    Ok(HashMap::from([
+        ("prompt".to_string(), Some(rendered.prompt)),
+        ("system".to_string(), rendered.system),
+        ("model".to_string(), rendered.model),
+    ]))
+}
+
+/// Load an agent definition from .agentd/agents/ or ~/.agentd/agents/ and return it as a JSON string
+#[pyfunction]
+#[pyo3(text_signature = "(name)")]
+fn py_load_agent(name: &str) -> PyResult<String> {
+    let definition = crate::agent::load_definition(name)
+        .map_err(|e| PyRuntimeError::new_err(format!("Failed to load agent '{}': {}", name, e)))?;
+    serde_json::to_string(&definition).map_err(|e| PyRuntimeError::new_err(e.to_string()))
+}
+
+/// Asks a Python callable `approve(tool, summary, arguments_json) -> bool`
+struct PyApprover(Py<PyAny>);
+
+impl crate::agent::Approver for PyApprover {
+    fn approve(&self, request: &crate::agent::ApprovalRequest) -> Result<bool, crate::LlmError> {
+        Python::with_gil(|py| {
+            self.0
+                .call1(py, (request.tool, request.summary, request.arguments.to_string()))
+                .and_then(|answer| answer.is_truthy(py))
+                .map_err(|e| crate::LlmError::InvalidInput(format!("approval callback failed: {}", e)))
+        })
+    }
+}
+
+/// Run an agent definition on a task and return the run as a JSON string
+/// with "answer", "output", "stop_reason", "steps", "tool_calls", "usage" and "messages"
+/// model_name overrides the definition's model; approve(tool, summary, arguments_json)
+/// decides calls the definition's approval policy asks about (without it they are refused)
+#[pyfunction]
+#[pyo3(signature = (name, input, model_name = None, cache = true, approve = None))]
+fn py_run_agent(name: &str, input: &str, model_name: Option<String>, cache: bool, approve: Option<Py<PyAny>>) -> PyResult<String> {
+    let mut definition = crate::agent::load_definition(name)
+        .map_err(|e| PyRuntimeError::new_err(format!("Failed to load agent '{}': {}", name, e)))?;
+    definition.model = model_name.or(definition.model);
+    let run = definition
+        .open(!cache)
+        .map(|agent| match approve {
+            Some(callback) => agent.with_approver(PyApprover(callback)),
+            None => agent,
+        })
+        .and_then(|agent| agent.run(input))
+        .map_err(|e| PyRuntimeError::new_err(format!("Agent '{}' failed: {}", name, e)))?;
+    serde_json::to_string(&run).map_err(|e| PyRuntimeError::new_err(e.to_string()))
+}
+
 /// Python module definition
 #[pymodule]
 pub fn agentd(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(py_open, m)?)?;
     m.add_function(wrap_pyfunction!(py_open_with_args, m)?)?;
     m.add_function(wrap_pyfunction!(py_list_models, m)?)?;
+    m.add_function(wrap_pyfunction!(py_render_prompt, m)?)?;
+    m.add_function(wrap_pyfunction!(py_load_agent, m)?)?;
+    m.add_function(wrap_pyfunction!(py_run_agent, m)?)?;
     m.add_class::<PyLlm>()?;
     m.add_class::<PyLlmConfig>()?;
     
@@ -144,6 +213,9 @@ pub fn agentd(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add("open", m.getattr("py_open")?)?;
     m.add("open_with_args", m.getattr("py_open_with_args")?)?;
     m.add("list_models", m.getattr("py_list_models")?)?;
+    m.add("render_prompt", m.getattr("py_render_prompt")?)?;
+    m.add("load_agent", m.getattr("py_load_agent")?)?;
+    m.add("run_agent", m.getattr("py_run_agent")?)?;
     
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,489 @@
+use crate::chat::ChatMessage;
+use crate::config::RuntimeConfig;
+use crate::error::LlmError;
+use crate::llm::{GenerationParams, LlmConfig, LlmInterface};
+use crate::tools::Tool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CASSETTE_VERSION: u32 = 1;
+
+/// How a cassette file is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    /// Run the real backend and write every interaction to the cassette
+    Record,
+    /// Answer from the cassette only; unmatched requests fail
+    Replay,
+    /// Replay if the cassette exists, otherwise record it
+    Auto,
+}
+
+impl CassetteMode {
+    pub fn parse(value: &str) -> Result<Self, LlmError> {
+        match value {
+            "record" => Ok(CassetteMode::Record),
+            "replay" => Ok(CassetteMode::Replay),
+            "auto" => Ok(CassetteMode::Auto),
+            other => Err(LlmError::InvalidInput(format!(
+                "Unknown cassette mode '{}': expected record, replay or auto",
+                other
+            ))),
+        }
+    }
+}
+
+/// Cassette selection from `AGENTD_CASSETTE`/`AGENTD_CASSETTE_MODE`, falling back
+/// to `runtime.cassette`/`runtime.cassette_mode` in config.toml
+pub fn cassette_settings(runtime: &RuntimeConfig) -> Result<Option<(PathBuf, CassetteMode)>, LlmError> {
+    let path = std::env::var_os("AGENTD_CASSETTE")
+        .map(PathBuf::from)
+        .or_else(|| runtime.cassette.clone());
+    let Some(path) = path else {
+        return Ok(None);
+    };
+
+    let mode = match std::env::var("AGENTD_CASSETTE_MODE") {
+        Ok(mode) => CassetteMode::parse(&mode)?,
+        Err(_) => runtime.cassette_mode.unwrap_or(CassetteMode::Auto),
+    };
+
+    let mode = match mode {
+        CassetteMode::Auto if path.exists() => CassetteMode::Replay,
+        CassetteMode::Auto => CassetteMode::Record,
+        mode => mode,
+    };
+    Ok(Some((path, mode)))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedChunk {
+    pub text: String,
+    /// Milliseconds since the request started
+    pub offset_ms: u64,
+}
+
+/// What was asked: exactly one of `prompt`, `messages` or `embed` is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<ChatMessage>>,
+    /// Tools offered by `chat_with_tools`; the response is the reply message as JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// Text passed to `embed`; the response is the vector as JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embed: Option<String>,
+    #[serde(default, skip_serializing_if = "GenerationParams::is_empty")]
+    pub params: GenerationParams,
+    /// Arguments set through `with_args`, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<RecordedChunk>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub version: u32,
+    pub interactions: Vec<Interaction>,
+}
+
+impl Default for Cassette {
+    fn default() -> Self {
+        Self {
+            version: CASSETTE_VERSION,
+            interactions: Vec::new(),
+        }
+    }
+}
+
+impl Cassette {
+    pub fn load(path: &Path) -> Result<Self, LlmError> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            LlmError::Cassette(format!("Cannot read cassette {}: {}", path.display(), e))
+        })?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), LlmError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(LlmError::Io)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?).map_err(LlmError::Io)
+    }
+}
+
+/// Cassettes being recorded in this process, shared so that every backend
+/// opened against the same file appends to one cassette
+fn recording_cassette(path: &Path) -> Arc<Mutex<Cassette>> {
+    static RECORDING: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<Cassette>>>>> = OnceLock::new();
+    RECORDING
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap()
+        .entry(path.to_path_buf())
+        .or_default()
+        .clone()
+}
+
+/// Wraps a live backend and writes each request and response to a cassette file
+pub struct RecordingBackend {
+    inner: Box<dyn LlmInterface + Send + Sync>,
+    model: String,
+    path: PathBuf,
+    cassette: Arc<Mutex<Cassette>>,
+    args: Option<Vec<String>>,
+}
+
+impl RecordingBackend {
+    /// Start recording to `path`, replacing any cassette from an earlier run
+    pub fn new(inner: Box<dyn LlmInterface + Send + Sync>, model: &str, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            inner,
+            model: model.to_string(),
+            cassette: recording_cassette(&path),
+            path,
+            args: None,
+        }
+    }
+
+    fn request(&self, prompt: Option<&str>, messages: Option<&[ChatMessage]>, params: &GenerationParams) -> RecordedRequest {
+        RecordedRequest {
+            model: self.model.clone(),
+            prompt: prompt.map(str::to_string),
+            messages: messages.map(<[ChatMessage]>::to_vec),
+            tools: None,
+            embed: None,
+            params: params.clone(),
+            args: self.args.clone(),
+        }
+    }
+
+    /// Run a streaming request, recording each chunk with its timing
+    fn record_stream<F>(&self, request: RecordedRequest, on_chunk: &mut dyn FnMut(&str) -> bool, generate: F) -> Result<String, LlmError>
+    where
+        F: FnOnce(&mut dyn FnMut(&str) -> bool) -> Result<String, LlmError>,
+    {
+        let started = Instant::now();
+        let mut chunks = Vec::new();
+        let result = generate(&mut |chunk| {
+            chunks.push(RecordedChunk {
+                text: chunk.to_string(),
+                offset_ms: started.elapsed().as_millis() as u64,
+            });
+            on_chunk(chunk)
+        });
+        self.record(request, &result, chunks, started)?;
+        result
+    }
+
+    fn record(
+        &self,
+        request: RecordedRequest,
+        result: &Result<String, LlmError>,
+        chunks: Vec<RecordedChunk>,
+        started: Instant,
+    ) -> Result<(), LlmError> {
+        let interaction = Interaction {
+            request,
+            response: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            chunks,
+            duration_ms: started.elapsed().as_millis() as u64,
+        };
+
+        let mut cassette = self.cassette.lock().unwrap();
+        cassette.interactions.push(interaction);
+        // Saved after every interaction so an aborted run still leaves a usable cassette
+        cassette.save(&self.path)
+    }
+}
+
+impl LlmInterface for RecordingBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        self.generate_with(prompt, &GenerationParams::default())
+    }
+
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        let started = Instant::now();
+        let result = if params.is_empty() {
+            self.inner.generate(prompt)
+        } else {
+            self.inner.generate_with(prompt, params)
+        };
+        self.record(self.request(Some(prompt), None, params), &result, Vec::new(), started)?;
+        result
+    }
+
+    fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let request = self.request(Some(prompt), None, &GenerationParams::default());
+        self.record_stream(request, on_chunk, |on_chunk| self.inner.generate_stream(prompt, on_chunk))
+    }
+
+    fn chat(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        self.chat_with(messages, &GenerationParams::default())
+    }
+
+    fn chat_with(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<String, LlmError> {
+        let started = Instant::now();
+        let result = if params.is_empty() {
+            self.inner.chat(messages)
+        } else {
+            self.inner.chat_with(messages, params)
+        };
+        self.record(self.request(None, Some(messages), params), &result, Vec::new(), started)?;
+        result
+    }
+
+    fn chat_stream(&self, messages: &[ChatMessage], on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let request = self.request(None, Some(messages), &GenerationParams::default());
+        self.record_stream(request, on_chunk, |on_chunk| self.inner.chat_stream(messages, on_chunk))
+    }
+
+    fn chat_with_tools(&self, messages: &[ChatMessage], tools: &[Tool]) -> Result<ChatMessage, LlmError> {
+        let started = Instant::now();
+        let result = self.inner.chat_with_tools(messages, tools);
+        let recorded = match &result {
+            Ok(reply) => Ok(serde_json::to_string(reply)?),
+            Err(e) => Err(LlmError::ProcessExecution(e.to_string())),
+        };
+        let request = RecordedRequest {
+            tools: Some(tools.to_vec()),
+            ..self.request(None, Some(messages), &GenerationParams::default())
+        };
+        self.record(request, &recorded, Vec::new(), started)?;
+        result
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+        let started = Instant::now();
+        let result = self.inner.embed(text);
+        let recorded = match &result {
+            Ok(vector) => Ok(serde_json::to_string(vector)?),
+            Err(e) => Err(LlmError::ProcessExecution(e.to_string())),
+        };
+        let request = RecordedRequest {
+            embed: Some(text.to_string()),
+            ..self.request(None, None, &GenerationParams::default())
+        };
+        self.record(request, &recorded, Vec::new(), started)?;
+        result
+    }
+
+    fn config(&self) -> &LlmConfig {
+        self.inner.config()
+    }
+
+    fn with_args(mut self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self.args = Some(args.clone());
+        self.inner = self.inner.with_args(args);
+        self
+    }
+}
+
+/// Answers requests from a recorded cassette without running any model.
+///
+/// Matching interactions are replayed in recording order; once all have been
+/// used the last one is repeated. A request with no recorded match fails
+/// with `LlmError::Cassette`.
+pub struct ReplayBackend {
+    config: LlmConfig,
+    model: String,
+    cassette: Arc<Cassette>,
+    used: Arc<Mutex<Vec<bool>>>,
+    args: Option<Vec<String>>,
+    realtime: bool,
+}
+
+impl ReplayBackend {
+    pub fn load(path: &Path, model: &str) -> Result<Self, LlmError> {
+        Ok(Self::from_cassette(Cassette::load(path)?, model))
+    }
+
+    pub fn from_cassette(cassette: Cassette, model: &str) -> Self {
+        let used = vec![false; cassette.interactions.len()];
+        Self {
+            config: LlmConfig::new("replay", model),
+            model: model.to_string(),
+            cassette: Arc::new(cassette),
+            used: Arc::new(Mutex::new(used)),
+            args: None,
+            realtime: false,
+        }
+    }
+
+    /// Reproduce recorded latency and chunk timing instead of answering immediately
+    pub fn with_realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    fn request(&self, prompt: Option<&str>, messages: Option<&[ChatMessage]>, params: &GenerationParams) -> RecordedRequest {
+        RecordedRequest {
+            model: self.model.clone(),
+            prompt: prompt.map(str::to_string),
+            messages: messages.map(<[ChatMessage]>::to_vec),
+            tools: None,
+            embed: None,
+            params: params.clone(),
+            args: self.args.clone(),
+        }
+    }
+
+    fn find(&self, request: RecordedRequest) -> Result<&Interaction, LlmError> {
+
+        let matches: Vec<usize> = self
+            .cassette
+            .interactions
+            .iter()
+            .enumerate()
+            .filter(|(_, interaction)| interaction.request == request)
+            .map(|(index, _)| index)
+            .collect();
+
+        let mut used = self.used.lock().unwrap();
+        let index = matches
+            .iter()
+            .copied()
+            .find(|index| !used[*index])
+            .or_else(|| matches.last().copied())
+            .ok_or_else(|| {
+                let what = request.prompt.clone().or_else(|| request.embed.clone()).unwrap_or_else(|| {
+                    serde_json::to_string(&request.messages).unwrap_or_default()
+                });
+                LlmError::Cassette(format!(
+                    "No recorded interaction for model '{}' and request {:?}",
+                    self.model,
+                    truncate(&what, 120)
+                ))
+            })?;
+        used[index] = true;
+
+        Ok(&self.cassette.interactions[index])
+    }
+
+    /// Replay the recorded chunks, or the whole response as one chunk if there are none
+    fn answer_stream(&self, interaction: &Interaction, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        if interaction.chunks.is_empty() {
+            let text = self.answer(interaction)?;
+            on_chunk(&text);
+            return Ok(text);
+        }
+
+        let mut produced = String::new();
+        let mut elapsed_ms = 0;
+        for chunk in &interaction.chunks {
+            if self.realtime {
+                thread::sleep(Duration::from_millis(chunk.offset_ms.saturating_sub(elapsed_ms)));
+                elapsed_ms = chunk.offset_ms;
+            }
+            produced.push_str(&chunk.text);
+            if !on_chunk(&chunk.text) {
+                return Ok(produced);
+            }
+        }
+
+        match &interaction.error {
+            Some(error) => Err(LlmError::ProcessExecution(error.clone())),
+            None => Ok(produced),
+        }
+    }
+
+    fn answer(&self, interaction: &Interaction) -> Result<String, LlmError> {
+        if self.realtime {
+            thread::sleep(Duration::from_millis(interaction.duration_ms));
+        }
+        match (&interaction.response, &interaction.error) {
+            (Some(response), _) => Ok(response.clone()),
+            (None, Some(error)) => Err(LlmError::ProcessExecution(error.clone())),
+            (None, None) => Err(LlmError::EmptyResponse),
+        }
+    }
+}
+
+impl LlmInterface for ReplayBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        self.generate_with(prompt, &GenerationParams::default())
+    }
+
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        let interaction = self.find(self.request(Some(prompt), None, params))?;
+        self.answer(interaction)
+    }
+
+    fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let interaction = self.find(self.request(Some(prompt), None, &GenerationParams::default()))?;
+        self.answer_stream(interaction, on_chunk)
+    }
+
+    fn chat(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        self.chat_with(messages, &GenerationParams::default())
+    }
+
+    fn chat_with(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<String, LlmError> {
+        let interaction = self.find(self.request(None, Some(messages), params))?;
+        self.answer(interaction)
+    }
+
+    fn chat_stream(&self, messages: &[ChatMessage], on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let interaction = self.find(self.request(None, Some(messages), &GenerationParams::default()))?;
+        self.answer_stream(interaction, on_chunk)
+    }
+
+    fn chat_with_tools(&self, messages: &[ChatMessage], tools: &[Tool]) -> Result<ChatMessage, LlmError> {
+        let request = RecordedRequest {
+            tools: Some(tools.to_vec()),
+            ..self.request(None, Some(messages), &GenerationParams::default())
+        };
+        let reply = self.answer(self.find(request)?)?;
+        Ok(serde_json::from_str(&reply)?)
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+        let request = RecordedRequest {
+            embed: Some(text.to_string()),
+            ..self.request(None, None, &GenerationParams::default())
+        };
+        let vector = self.answer(self.find(request)?)?;
+        Ok(serde_json::from_str(&vector)?)
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(mut self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self.config.additional_args = args.clone();
+        self.args = Some(args);
+        self
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        format!("{}...", text.chars().take(max_chars).collect::<String>())
+    }
+}
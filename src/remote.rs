@@ -0,0 +1,391 @@
+use crate::chat::ChatMessage;
+use crate::config::ModelEntry;
+use crate::error::LlmError;
+use crate::llm::backends::LlamaCppBackend;
+use crate::llm::{GenerationParams, LlmConfig, LlmInterface};
+use crate::tools::{message_from_openai, message_to_openai, Tool};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Backend for any OpenAI-compatible HTTP API (vLLM, LocalAI, llama.cpp's
+/// server, ...), configured by a model entry's `base_url`.
+///
+/// `generate` uses `/completions`, `chat` and `chat_with_tools` use
+/// `/chat/completions` and `embed` uses `/embeddings`; the streaming variants read server-sent events. `config()` reports the base URL as the executable and
+/// the remote model name as the model path; sampling parameters live in
+/// `additional_args` as llama.cpp flags, so `with_args` works as for local models.
+#[derive(Debug, Clone)]
+pub struct OpenAiBackend {
+    config: LlmConfig,
+    api_key: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl OpenAiBackend {
+    /// `base_url` is the API root, usually ending in `/v1`
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(10)).build();
+        Self {
+            config: LlmConfig::new(base_url, model),
+            api_key: None,
+            agent,
+        }
+    }
+
+    /// Send `api_key` as a bearer token
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Build a backend for a `models.toml` entry, reading the API key from `api_key_env`
+    pub fn from_entry(name: &str, entry: &ModelEntry, params: &GenerationParams) -> Result<Self, LlmError> {
+        let base_url = entry.base_url.as_deref().ok_or_else(|| {
+            LlmError::Config(format!("Model '{}' uses the openai backend but has no base_url", name))
+        })?;
+
+        let mut backend = Self::new(base_url, entry.remote_model.as_deref().unwrap_or(name));
+        backend.config.additional_args = params.to_args();
+        if let Some(var) = &entry.api_key_env {
+            let api_key = std::env::var(var).map_err(|_| {
+                LlmError::Config(format!("Model '{}' reads its API key from {}, which is not set", name, var))
+            })?;
+            backend = backend.with_api_key(api_key);
+        }
+        Ok(backend)
+    }
+
+    /// Add the model name and sampling parameters to a request body
+    fn request_body(&self, mut body: Value, params: &GenerationParams) -> Value {
+        let params = GenerationParams::from_args(&self.config.additional_args).merged_with(params);
+        body["model"] = json!(self.config.model_path);
+        if let Some(temperature) = params.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = params.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(repeat_penalty) = params.repeat_penalty {
+            // Not part of the OpenAI API, but understood by vLLM and llama.cpp's server
+            body["repetition_penalty"] = json!(repeat_penalty);
+        }
+        if let Some(max_tokens) = params.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(seed) = params.seed {
+            body["seed"] = json!(seed);
+        }
+        if let Some(schema) = params.json_schema {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "schema": schema, "strict": true },
+            });
+        }
+        if let Some(grammar) = params.grammar {
+            // Understood by llama.cpp's server; other servers ignore it
+            body["grammar"] = json!(grammar);
+        }
+        body
+    }
+
+    fn post(&self, path: &str, body: Value) -> Result<ureq::Response, LlmError> {
+        let url = format!("{}/{}", self.config.executable_path.trim_end_matches('/'), path);
+        let mut request = self.agent.post(&url);
+        if let Some(api_key) = &self.api_key {
+            request = request.set("Authorization", &format!("Bearer {}", api_key));
+        }
+
+        request.send_json(body).map_err(|e| match e {
+            ureq::Error::Status(status, response) => {
+                let detail = response.into_string().unwrap_or_default();
+                LlmError::Http(format!("{} returned {}: {}", url, status, detail.trim()))
+            }
+            other => LlmError::Http(format!("{}: {}", url, other)),
+        })
+    }
+
+    fn post_json(&self, path: &str, body: Value) -> Result<Value, LlmError> {
+        let response = self.post(path, body)?;
+        serde_json::from_reader(response.into_reader()).map_err(LlmError::Json)
+    }
+}
+
+fn openai_messages(messages: &[ChatMessage]) -> Vec<Value> {
+    messages.iter().map(message_to_openai).collect()
+}
+
+/// The first choice's text, or `EmptyResponse` if there is none
+fn choice_text(response: &Value, pointer: &str) -> Result<String, LlmError> {
+    match response["choices"][0].pointer(pointer).and_then(Value::as_str) {
+        Some(text) if !text.is_empty() => Ok(text.to_string()),
+        _ => Err(LlmError::EmptyResponse),
+    }
+}
+
+/// Pass the first choice's text from each server-sent event to `on_chunk` until `[DONE]`
+fn read_events(response: ureq::Response, pointer: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+    let mut produced = String::new();
+
+    for line in BufReader::new(response.into_reader()).lines() {
+        let line = line.map_err(LlmError::Io)?;
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            break;
+        }
+        let event: Value = serde_json::from_str(data)?;
+        let text = event["choices"][0].pointer(pointer).and_then(Value::as_str).unwrap_or_default();
+        if text.is_empty() {
+            continue;
+        }
+        produced.push_str(text);
+        if !on_chunk(text) {
+            break;
+        }
+    }
+
+    if produced.is_empty() {
+        return Err(LlmError::EmptyResponse);
+    }
+    Ok(produced)
+}
+
+impl LlmInterface for OpenAiBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        self.generate_with(prompt, &GenerationParams::default())
+    }
+
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        let body = self.request_body(json!({ "prompt": prompt }), params);
+        let response = self.post_json("completions", body)?;
+        choice_text(&response, "/text")
+    }
+
+    /// Streams server-sent events; dropping the connection stops the remote generation
+    fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let body = self.request_body(json!({ "prompt": prompt, "stream": true }), &GenerationParams::default());
+        let response = self.post("completions", body)?;
+        read_events(response, "/text", on_chunk)
+    }
+
+    fn chat(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        self.chat_with(messages, &GenerationParams::default())
+    }
+
+    fn chat_with(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<String, LlmError> {
+        let body = self.request_body(json!({ "messages": openai_messages(messages) }), params);
+        let response = self.post_json("chat/completions", body)?;
+        choice_text(&response, "/message/content")
+    }
+
+    fn chat_stream(&self, messages: &[ChatMessage], on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let body = self.request_body(json!({ "messages": openai_messages(messages), "stream": true }), &GenerationParams::default());
+        let response = self.post("chat/completions", body)?;
+        read_events(response, "/delta/content", on_chunk)
+    }
+
+    /// Passes the tools in the API's own `tools` field
+    fn chat_with_tools(&self, messages: &[ChatMessage], tools: &[Tool]) -> Result<ChatMessage, LlmError> {
+        let mut body = json!({ "messages": openai_messages(messages) });
+        if !tools.is_empty() {
+            body["tools"] = tools.iter().map(Tool::to_openai).collect();
+        }
+        let response = self.post_json("chat/completions", self.request_body(body, &GenerationParams::default()))?;
+        let message = &response["choices"][0]["message"];
+        if message.is_null() {
+            return Err(LlmError::EmptyResponse);
+        }
+
+        let reply = message_from_openai(message).map_err(|e| LlmError::InvalidResponse(e.to_string()))?;
+        if reply.content.is_empty() && reply.tool_calls.is_empty() {
+            return Err(LlmError::EmptyResponse);
+        }
+        Ok(reply)
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+        // Sampling parameters mean nothing to an embedding endpoint
+        let response = self.post_json("embeddings", json!({ "model": self.config.model_path, "input": text }))?;
+        response["data"][0]["embedding"]
+            .as_array()
+            .map(|values| values.iter().filter_map(Value::as_f64).map(|value| value as f32).collect())
+            .ok_or(LlmError::EmptyResponse)
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(mut self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self.config.additional_args = args;
+        self
+    }
+}
+
+/// How long `LlamaServerBackend::start` waits for the model to load
+const SERVER_STARTUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Lines of server output kept for error messages
+const SERVER_LOG_LINES: usize = 20;
+
+/// A llama.cpp model kept loaded by a `llama-server` process for as long as the
+/// backend lives, so the weights are read once rather than per request.
+///
+/// `llama-server` is run from the same directory as the configured executable,
+/// on a free localhost port, and requests go to it through `OpenAiBackend`; chat
+/// therefore uses the model's own chat template. Tool chats use the family's
+/// `ToolFormat` inside that template, since the server only accepts the native
+/// `tools` field when started with `--jinja`. `with_args` only changes the
+/// per-request sampling parameters, and `embed` runs `llama-embedding` as
+/// `LlamaCppBackend` does. Dropping the backend stops the server.
+#[derive(Debug)]
+pub struct LlamaServerBackend {
+    config: LlmConfig,
+    remote: OpenAiBackend,
+    child: Child,
+}
+
+impl LlamaServerBackend {
+    /// Start `llama-server` for `config` and wait until the model is loaded
+    pub fn start(config: LlmConfig) -> Result<Self, LlmError> {
+        if !Path::new(&config.model_path).exists() {
+            return Err(LlmError::InvalidModelPath(config.model_path.clone()));
+        }
+
+        let executable = Path::new(&config.executable_path).with_file_name("llama-server");
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map_err(LlmError::Io)?
+            .port();
+
+        let mut command = Command::new(&executable);
+        command
+            .args(["--model", &config.model_path])
+            .args(&config.additional_args)
+            .args(["--host", "127.0.0.1", "--port", &port.to_string()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        // Ctrl-C at the chat prompt is for the caller, not the server
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .spawn()
+            .map_err(|e| LlmError::ProcessSpawn(format!("Failed to spawn {}: {}", executable.display(), e)))?;
+
+        // Drain the server's log so it never blocks on a full pipe, keeping the tail for errors
+        let log = Arc::new(Mutex::new(VecDeque::new()));
+        let reader = child.stderr.take().map(|stderr| {
+            let log = log.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    let mut log = log.lock().unwrap();
+                    if log.len() == SERVER_LOG_LINES {
+                        log.pop_front();
+                    }
+                    log.push_back(line);
+                }
+            })
+        });
+
+        let mut remote = OpenAiBackend::new(format!("http://127.0.0.1:{}/v1", port), config.model_path.clone());
+        remote.config.additional_args = GenerationParams::from_args(&config.additional_args).to_args();
+        // From here on, dropping the backend stops the server
+        let mut backend = Self { config, remote, child };
+        backend.wait_until_ready(&executable, reader, &log)?;
+        Ok(backend)
+    }
+
+    fn wait_until_ready(
+        &mut self,
+        executable: &Path,
+        reader: Option<JoinHandle<()>>,
+        log: &Mutex<VecDeque<String>>,
+    ) -> Result<(), LlmError> {
+        let url = format!("{}/models", self.remote.config.executable_path);
+        let started = Instant::now();
+
+        loop {
+            if let Some(status) = self.child.try_wait().map_err(LlmError::Io)? {
+                if let Some(reader) = reader {
+                    let _ = reader.join();
+                }
+                let tail = Vec::from(log.lock().unwrap().clone()).join("\n");
+                return Err(LlmError::ProcessExecution(format!(
+                    "{} exited with {} before the model was loaded: {}",
+                    executable.display(),
+                    status,
+                    tail
+                )));
+            }
+            // The server answers 503 until the model is loaded
+            if self.remote.agent.get(&url).call().is_ok() {
+                return Ok(());
+            }
+            if started.elapsed() > SERVER_STARTUP_TIMEOUT {
+                return Err(LlmError::ProcessExecution(format!(
+                    "{} did not load {} within {} seconds",
+                    executable.display(),
+                    self.config.model_path,
+                    SERVER_STARTUP_TIMEOUT.as_secs()
+                )));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+impl Drop for LlamaServerBackend {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl LlmInterface for LlamaServerBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        self.remote.generate(prompt)
+    }
+
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        self.remote.generate_with(prompt, params)
+    }
+
+    fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        self.remote.generate_stream(prompt, on_chunk)
+    }
+
+    fn chat(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        self.remote.chat(messages)
+    }
+
+    fn chat_with(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<String, LlmError> {
+        self.remote.chat_with(messages, params)
+    }
+
+    fn chat_stream(&self, messages: &[ChatMessage], on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        self.remote.chat_stream(messages, on_chunk)
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+        LlamaCppBackend::new(self.config.clone())?.embed(text)
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(mut self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self.remote.config.additional_args = GenerationParams::from_args(&args).to_args();
+        self.config.additional_args = args;
+        self
+    }
+}
@@ -0,0 +1,246 @@
+use crate::config;
+use crate::error::LlmError;
+use crate::llm::{open_with, GenerationParams, OpenOptions};
+use crate::session::Session;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const HELP: &str = "\
+/model <name>   switch model, keeping the conversation
+/temp <value>   set the sampling temperature
+/system <text>  replace the system prompt
+/save [name]    save the conversation, optionally as a new session
+/reset          clear the conversation, keeping the system prompt
+/tokens         show how much of the context window is used
+/exit           leave (or Ctrl-D)
+
+End a line with \\ to continue it, or wrap several lines in \"\"\".
+Ctrl-C stops the current reply.";
+
+/// A line entered at the `agentd chat` prompt that starts with `/`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplCommand {
+    Model(String),
+    Temp(f32),
+    System(String),
+    /// Save, under a new session name if one is given
+    Save(Option<String>),
+    Reset,
+    Tokens,
+    Help,
+    Exit,
+}
+
+impl ReplCommand {
+    /// Parse a slash command; lines that don't start with `/` are messages and give `Ok(None)`
+    pub fn parse(line: &str) -> Result<Option<Self>, LlmError> {
+        let Some(command) = line.trim().strip_prefix('/') else {
+            return Ok(None);
+        };
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+        let required = |what: &str| {
+            if arg.is_empty() {
+                Err(LlmError::InvalidInput(format!("/{} needs {}", name, what)))
+            } else {
+                Ok(arg.to_string())
+            }
+        };
+
+        let command = match name {
+            "model" => ReplCommand::Model(required("a model name")?),
+            "temp" => {
+                let value: f32 = required("a value")?
+                    .parse()
+                    .ok()
+                    .filter(|value: &f32| (0.0..=2.0).contains(value))
+                    .ok_or_else(|| LlmError::InvalidInput(format!("Temperature must be between 0 and 2, got '{}'", arg)))?;
+                ReplCommand::Temp(value)
+            }
+            "system" => ReplCommand::System(required("a prompt")?),
+            "save" => ReplCommand::Save(Some(arg.to_string()).filter(|name| !name.is_empty())),
+            "reset" => ReplCommand::Reset,
+            "tokens" => ReplCommand::Tokens,
+            "help" | "?" => ReplCommand::Help,
+            "exit" | "quit" => ReplCommand::Exit,
+            other => return Err(LlmError::InvalidInput(format!("Unknown command /{}; try /help", other))),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Interactive chat on a terminal. A llama.cpp model stays loaded in one
+/// `llama-server` process until `/model` replaces it; `/temp` only changes the
+/// arguments of the running backend.
+pub struct Repl {
+    session: Session,
+    options: OpenOptions,
+    params: GenerationParams,
+    /// The backend's arguments before any `/temp` override
+    base_args: Vec<String>,
+    /// Save after every exchange; set once the conversation has a session name
+    save_session: bool,
+}
+
+impl Repl {
+    /// `session` should use a backend opened with `options`, ideally `persistent`
+    pub fn new(session: Session, options: OpenOptions, save_session: bool) -> Self {
+        let base_args = session.llm().config().additional_args.clone();
+        Self {
+            session: session.with_autosave(false),
+            options,
+            params: GenerationParams::default(),
+            base_args,
+            save_session,
+        }
+    }
+
+    /// Read and answer messages until `/exit` or end of input
+    pub fn run(mut self) -> Result<(), LlmError> {
+        let to_io = |e: ReadlineError| LlmError::Io(io::Error::other(e));
+        let mut editor = DefaultEditor::new().map_err(to_io)?;
+        let history = config::get_agentd_home().join("chat_history");
+        let _ = editor.load_history(&history);
+
+        // Ctrl-C while a reply streams stops the reply; at the prompt, the editor handles it
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let flag = interrupted.clone();
+        ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))
+            .map_err(|e| LlmError::Io(io::Error::other(e)))?;
+
+        eprintln!("Chatting with {}. Type /help for commands, Ctrl-D to leave.", self.session.model());
+        while let Some(input) = read_message(&mut editor).map_err(to_io)? {
+            if input.trim().is_empty() {
+                continue;
+            }
+            let _ = editor.add_history_entry(input.as_str());
+
+            let result = match ReplCommand::parse(&input) {
+                Ok(Some(ReplCommand::Exit)) => break,
+                Ok(Some(command)) => self.apply(command),
+                Ok(None) => self.reply(&input, &interrupted),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+            }
+        }
+
+        if let Some(parent) = history.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _ = editor.save_history(&history);
+        Ok(())
+    }
+
+    fn reply(&mut self, message: &str, interrupted: &AtomicBool) -> Result<(), LlmError> {
+        interrupted.store(false, Ordering::SeqCst);
+        let mut stdout = io::stdout();
+        let result = self.session.send_stream(message, &mut |chunk| {
+            print!("{}", chunk);
+            let _ = stdout.flush();
+            !interrupted.load(Ordering::SeqCst)
+        });
+        println!();
+
+        if interrupted.swap(false, Ordering::SeqCst) {
+            eprintln!("[interrupted]");
+        }
+        result?;
+        if self.save_session {
+            self.session.save()?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, command: ReplCommand) -> Result<(), LlmError> {
+        match command {
+            ReplCommand::Model(model) => {
+                let entry = config::find_model_entry(&model).ok();
+                let llm = open_with(&model, &self.options)?;
+                self.base_args = llm.config().additional_args.clone();
+                let args = self.args();
+                self.session.set_llm(&model, llm);
+                self.session.set_llm_args(args);
+                self.session.set_context_size(entry.and_then(|entry| entry.context_size));
+                eprintln!("Now chatting with {}", model);
+            }
+            ReplCommand::Temp(temperature) => {
+                self.params.temperature = Some(temperature);
+                let args = self.args();
+                self.session.set_llm_args(args);
+                eprintln!("Temperature set to {}", temperature);
+            }
+            ReplCommand::System(prompt) => {
+                self.session.set_system(prompt);
+                eprintln!("System prompt updated");
+            }
+            ReplCommand::Save(name) => {
+                match name {
+                    Some(name) => self.session.save_as(&name)?,
+                    None if self.save_session => self.session.save()?,
+                    None => return Err(LlmError::InvalidInput("Name the session: /save <name>".to_string())),
+                }
+                self.save_session = true;
+                eprintln!("Saved session '{}'", self.session.name());
+            }
+            ReplCommand::Reset => {
+                self.session.reset();
+                eprintln!("Conversation cleared");
+            }
+            ReplCommand::Tokens => {
+                let used = self.session.context_tokens();
+                match self.session.record().context_size {
+                    Some(context_size) => eprintln!("~{} of {} context tokens in use", used, context_size),
+                    None => eprintln!("~{} tokens in use", used),
+                }
+            }
+            ReplCommand::Help => eprintln!("{}", HELP),
+            ReplCommand::Exit => {}
+        }
+        Ok(())
+    }
+
+    /// The backend's own arguments followed by any `/temp` override
+    fn args(&self) -> Vec<String> {
+        [self.base_args.clone(), self.params.to_args()].concat()
+    }
+}
+
+/// Read one message: a single line, lines joined by a trailing `\`, or a block
+/// between `"""` lines. Ctrl-C discards the input so far; `None` means end of input.
+fn read_message(editor: &mut DefaultEditor) -> Result<Option<String>, ReadlineError> {
+    let mut lines = Vec::new();
+    let mut in_block = false;
+
+    loop {
+        let prompt = if lines.is_empty() && !in_block { ">>> " } else { "... " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        if line.trim() == "\"\"\"" {
+            if in_block {
+                break;
+            }
+            in_block = true;
+        } else if in_block {
+            lines.push(line);
+        } else if let Some(start) = line.strip_suffix('\\') {
+            lines.push(start.to_string());
+        } else {
+            lines.push(line);
+            break;
+        }
+    }
+
+    Ok(Some(lines.join("\n")))
+}
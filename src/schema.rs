@@ -0,0 +1,344 @@
+use crate::error::LlmError;
+use serde_json::{json, Map, Value};
+
+/// Check `instance` against `schema`, returning every violation found.
+///
+/// Supports the commonly used subset of JSON Schema: `type`, `enum`, `const`,
+/// `properties`, `required`, `additionalProperties`, `items`, `minItems`/`maxItems`,
+/// `minLength`/`maxLength`, `minimum`/`maximum`, `anyOf`, `oneOf`, and `$ref`
+/// to a definition in the same document (e.g. `#/$defs/Node`). Annotations
+/// such as `description` and `format` are allowed; any other keyword is
+/// reported rather than ignored (see `check_schema`).
+pub fn validate(instance: &Value, schema: &Value) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    unsupported_keywords(schema, "$", &mut errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    validate_at(instance, schema, schema, "$", &mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Keywords `validate` checks
+const SUPPORTED_KEYWORDS: &[&str] = &[
+    "type", "enum", "const", "properties", "required", "additionalProperties", "items", "minItems", "maxItems",
+    "minLength", "maxLength", "minimum", "maximum", "anyOf", "oneOf", "$ref", "$defs", "definitions",
+];
+
+/// Keywords that only describe a value, which `validate` may safely skip
+const ANNOTATIONS: &[&str] = &[
+    "$schema", "$id", "$comment", "title", "description", "default", "examples", "format", "deprecated", "readOnly",
+    "writeOnly",
+];
+
+/// Fail with `LlmError::InvalidInput` if `schema` uses a keyword `validate`
+/// doesn't support, such as `pattern` or `allOf`, instead of silently
+/// accepting replies that break it
+pub fn check_schema(schema: &Value) -> Result<(), LlmError> {
+    let mut errors = Vec::new();
+    unsupported_keywords(schema, "$", &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(LlmError::InvalidInput(format!("Unsupported JSON Schema: {}", errors.join("; "))))
+    }
+}
+
+fn unsupported_keywords(schema: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+
+    for (keyword, value) in schema {
+        let child_path = format!("{}.{}", path, keyword);
+        match keyword.as_str() {
+            "properties" | "$defs" | "definitions" => {
+                for (name, subschema) in value.as_object().into_iter().flatten() {
+                    unsupported_keywords(subschema, &format!("{}.{}", child_path, name), errors);
+                }
+            }
+            "anyOf" | "oneOf" => {
+                for (index, subschema) in value.as_array().into_iter().flatten().enumerate() {
+                    unsupported_keywords(subschema, &format!("{}[{}]", child_path, index), errors);
+                }
+            }
+            "items" | "additionalProperties" => unsupported_keywords(value, &child_path, errors),
+            keyword if SUPPORTED_KEYWORDS.contains(&keyword) || ANNOTATIONS.contains(&keyword) => {}
+            keyword => errors.push(format!("{}: unsupported keyword '{}'", path, keyword)),
+        }
+    }
+}
+
+/// The schema a local `$ref` such as `#/$defs/Node` points to within `root`
+fn resolve_ref<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    reference.strip_prefix('#').and_then(|pointer| root.pointer(pointer))
+}
+
+fn validate_at(instance: &Value, schema: &Value, root: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        // `true`/`{}` accept anything, `false` rejects everything
+        if schema == &Value::Bool(false) {
+            errors.push(format!("{}: no value is allowed here", path));
+        }
+        return;
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        match resolve_ref(root, reference) {
+            Some(target) => validate_at(instance, target, root, path, errors),
+            None => errors.push(format!("{}: cannot resolve $ref '{}'", path, reference)),
+        }
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|t| matches_type(instance, t)) {
+            errors.push(format!("{}: expected {}, got {}", path, allowed.join(" or "), type_name(instance)));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(instance) {
+            errors.push(format!("{}: {} is not one of {}", path, instance, Value::Array(options.clone())));
+        }
+    }
+
+    if let Some(constant) = schema.get("const") {
+        if constant != instance {
+            errors.push(format!("{}: expected {}", path, constant));
+        }
+    }
+
+    for keyword in ["anyOf", "oneOf"] {
+        if let Some(Value::Array(options)) = schema.get(keyword) {
+            let matching = options
+                .iter()
+                .filter(|option| {
+                    let mut option_errors = Vec::new();
+                    validate_at(instance, option, root, path, &mut option_errors);
+                    option_errors.is_empty()
+                })
+                .count();
+            let ok = if keyword == "anyOf" { matching > 0 } else { matching == 1 };
+            if !ok {
+                errors.push(format!("{}: does not match {}", path, keyword));
+            }
+        }
+    }
+
+    match instance {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        errors.push(format!("{}: missing required property '{}'", path, name));
+                    }
+                }
+            }
+
+            for (name, value) in object {
+                let child_path = format!("{}.{}", path, name);
+                match properties.and_then(|p| p.get(name)) {
+                    Some(child_schema) => validate_at(value, child_schema, root, &child_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{}: unexpected property '{}'", path, name))
+                        }
+                        Some(additional @ Value::Object(_)) => {
+                            validate_at(value, additional, root, &child_path, errors)
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    errors.push(format!("{}: expected at least {} items", path, min));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if (items.len() as u64) > max {
+                    errors.push(format!("{}: expected at most {} items", path, max));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(item, item_schema, root, &format!("{}[{}]", path, index), errors);
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if length < min {
+                    errors.push(format!("{}: shorter than {} characters", path, min));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if length > max {
+                    errors.push(format!("{}: longer than {} characters", path, max));
+                }
+            }
+        }
+        Value::Number(number) => {
+            let value = number.as_f64().unwrap_or(f64::NAN);
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if value < min {
+                    errors.push(format!("{}: {} is less than {}", path, value, min));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if value > max {
+                    errors.push(format!("{}: {} is greater than {}", path, value, max));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn matches_type(instance: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => instance.is_object(),
+        "array" => instance.is_array(),
+        "string" => instance.is_string(),
+        "number" => instance.is_number(),
+        "integer" => instance.is_i64() || instance.is_u64(),
+        "boolean" => instance.is_boolean(),
+        "null" => instance.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::String(_) => "string",
+        Value::Number(_) => "number",
+        Value::Bool(_) => "boolean",
+        Value::Null => "null",
+    }
+}
+
+/// Parse JSON from model output, tolerating Markdown code fences and text around the value
+pub fn extract_json(text: &str) -> Result<Value, serde_json::Error> {
+    let trimmed = text.trim();
+    let first_error = match serde_json::from_str(trimmed) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    if let Some(start) = trimmed.find("```") {
+        let fenced = &trimmed[start + 3..];
+        // Skip an info string such as ```json
+        let fenced = fenced.split_once('\n').map_or(fenced, |(_, body)| body);
+        if let Some(end) = fenced.find("```") {
+            if let Ok(value) = serde_json::from_str(fenced[..end].trim()) {
+                return Ok(value);
+            }
+        }
+    }
+
+    for (open, close) in [('{', '}'), ('[', ']')] {
+        if let (Some(start), Some(end)) = (trimmed.find(open), trimmed.rfind(close)) {
+            if start < end {
+                if let Ok(value) = serde_json::from_str(&trimmed[start..=end]) {
+                    return Ok(value);
+                }
+            }
+        }
+    }
+
+    Err(first_error)
+}
+
+/// Parse a constrained reply and check it against `schema`
+pub fn parse_reply(text: &str, schema: &Value) -> Result<Value, LlmError> {
+    let value = extract_json(text).map_err(|e| LlmError::InvalidResponse(format!("output is not JSON: {}", e)))?;
+    validate(&value, schema).map_err(|errors| LlmError::InvalidResponse(errors.join("; ")))?;
+    Ok(value)
+}
+
+/// A small value that satisfies `schema`, built from the same keyword subset
+/// `validate` understands: the first `enum` or `const`, every listed property,
+/// the minimum number of items and the shortest allowed string. A `$ref` back
+/// into a definition that is already being expanded becomes `null`.
+pub fn example(schema: &Value) -> Value {
+    example_at(schema, schema, &mut Vec::new())
+}
+
+fn example_at(schema: &Value, root: &Value, expanding: &mut Vec<String>) -> Value {
+    let Some(schema) = schema.as_object() else {
+        return Value::Null;
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        if expanding.iter().any(|seen| seen == reference) {
+            return Value::Null;
+        }
+        let Some(target) = resolve_ref(root, reference) else {
+            return Value::Null;
+        };
+        expanding.push(reference.to_string());
+        let value = example_at(target, root, expanding);
+        expanding.pop();
+        return value;
+    }
+    if let Some(constant) = schema.get("const") {
+        return constant.clone();
+    }
+    if let Some(first) = schema.get("enum").and_then(Value::as_array).and_then(|options| options.first()) {
+        return first.clone();
+    }
+    if let Some(first) = ["anyOf", "oneOf"]
+        .iter()
+        .find_map(|keyword| schema.get(*keyword).and_then(Value::as_array).and_then(|options| options.first()))
+    {
+        return example_at(first, root, expanding);
+    }
+
+    let kind = match schema.get("type") {
+        Some(Value::String(kind)) => kind.as_str(),
+        Some(Value::Array(kinds)) => kinds.iter().find_map(Value::as_str).unwrap_or("null"),
+        _ if schema.contains_key("properties") => "object",
+        _ if schema.contains_key("items") => "array",
+        _ => "null",
+    };
+    let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+
+    match kind {
+        "object" => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let object: Map<String, Value> = properties
+                .into_iter()
+                .flatten()
+                .map(|(name, property)| (name.clone(), example_at(property, root, expanding)))
+                .collect();
+            Value::Object(object)
+        }
+        "array" => {
+            let count = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0) as usize;
+            let item = schema.get("items").map(|items| example_at(items, root, expanding)).unwrap_or(Value::Null);
+            Value::Array(vec![item; count])
+        }
+        "string" => json!("x".repeat(schema.get("minLength").and_then(Value::as_u64).unwrap_or(0) as usize)),
+        "integer" => json!(bound("minimum").map(|minimum| minimum.ceil() as i64).unwrap_or(0)),
+        "number" => json!(bound("minimum").unwrap_or(0.0)),
+        "boolean" => json!(false),
+        _ => Value::Null,
+    }
+}
@@ -0,0 +1,691 @@
+use crate::chat::{render_transcript, ChatMessage};
+use crate::config::{self, ModelEntry};
+use crate::discovery;
+use crate::error::LlmError;
+use crate::llm::{open_with, GenerationParams, LlmInterface, OpenOptions, Usage};
+use crate::tools::{self, Tool};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response};
+
+/// Ollama's own address, so clients find agentd without configuration
+pub const DEFAULT_ADDR: &str = "127.0.0.1:11434";
+
+/// An HTTP server implementing the parts of the Ollama REST API used by
+/// editor plugins: `/api/generate`, `/api/chat`, `/api/tags`, `/api/embeddings`
+/// and `/api/embed`. Responses stream as NDJSON unless `"stream": false`.
+///
+/// OpenAI clients can use `/v1/chat/completions` and `/v1/models`. Both chat
+/// endpoints accept `tools` and return the model's `tool_calls`.
+///
+/// Each model is loaded on its first request and stays loaded for the life of
+/// the server; requests for the same model take turns.
+pub struct Server {
+    http: tiny_http::Server,
+    models: Arc<LoadedModels>,
+}
+
+impl Server {
+    pub fn bind(addr: &str) -> Result<Self, LlmError> {
+        let http = tiny_http::Server::http(addr)
+            .map_err(|e| LlmError::Io(io::Error::other(format!("Failed to listen on {}: {}", addr, e))))?;
+        Ok(Self { http, models: Arc::default() })
+    }
+
+    /// The bound address, useful after binding to port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Serve requests, each on its own thread, until the process exits
+    pub fn run(self) {
+        for request in self.http.incoming_requests() {
+            let models = self.models.clone();
+            thread::spawn(move || handle(request, &models));
+        }
+    }
+}
+
+/// A model opened by the server and the arguments it was opened with
+struct LoadedModel {
+    backend: Box<dyn LlmInterface + Send + Sync>,
+    base_args: Vec<String>,
+}
+
+/// Backends kept open between requests, by the model name clients use
+#[derive(Default)]
+struct LoadedModels {
+    models: Mutex<HashMap<String, Arc<Mutex<Option<LoadedModel>>>>>,
+}
+
+impl LoadedModels {
+    /// Run `task` on the model with `params` applied for this request only,
+    /// opening the model if no earlier request did
+    fn with_model<T>(
+        &self,
+        name: &str,
+        params: &GenerationParams,
+        task: impl FnOnce(&dyn LlmInterface) -> Result<T, LlmError>,
+    ) -> Result<T, LlmError> {
+        let name = model_name(name);
+        // Loading can take minutes, so only this model's slot is held meanwhile
+        let slot = self.models.lock().unwrap_or_else(|e| e.into_inner()).entry(name.to_string()).or_default().clone();
+        let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+        let mut model = match slot.take() {
+            Some(model) => model,
+            None => {
+                let backend = open_with(name, &OpenOptions::default().persistent(true))?;
+                let base_args = backend.config().additional_args.clone();
+                LoadedModel { backend, base_args }
+            }
+        };
+
+        let result = if params.is_empty() {
+            task(model.backend.as_ref())
+        } else {
+            model.backend = model.backend.with_args([model.base_args.clone(), params.to_args()].concat());
+            let result = task(model.backend.as_ref());
+            model.backend = model.backend.with_args(model.base_args.clone());
+            result
+        };
+        *slot = Some(model);
+        result
+    }
+}
+
+/// Ollama's `options` object; only the settings agentd can pass on are read
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Options {
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    repeat_penalty: Option<f32>,
+    /// Negative means unlimited
+    num_predict: Option<i64>,
+    seed: Option<i64>,
+}
+
+impl Options {
+    /// Sampling parameters, constrained by the request's `format`: `"json"`
+    /// for any JSON value or a JSON schema object
+    fn params(&self, format: Option<&Value>) -> GenerationParams {
+        let json_schema = match format {
+            Some(Value::String(format)) if format == "json" => Some(json!({})),
+            Some(schema @ Value::Object(_)) => Some(schema.clone()),
+            _ => None,
+        };
+        GenerationParams {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            repeat_penalty: self.repeat_penalty,
+            max_tokens: self.num_predict.and_then(|n| u32::try_from(n).ok()),
+            seed: self.seed,
+            json_schema,
+            ..GenerationParams::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateRequest {
+    model: String,
+    #[serde(default)]
+    prompt: String,
+    #[serde(default)]
+    system: Option<String>,
+    #[serde(default)]
+    stream: Option<bool>,
+    #[serde(default)]
+    format: Option<Value>,
+    #[serde(default)]
+    options: Options,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<Value>,
+    #[serde(default)]
+    tools: Vec<Value>,
+    #[serde(default)]
+    stream: Option<bool>,
+    #[serde(default)]
+    format: Option<Value>,
+    #[serde(default)]
+    options: Options,
+}
+
+/// The fields of an OpenAI chat completion request that agentd can honour
+#[derive(Debug, Deserialize)]
+struct OpenAiChatRequest {
+    model: String,
+    messages: Vec<Value>,
+    #[serde(default)]
+    tools: Vec<Value>,
+    /// `"none"` disables the tools; other choices are left to the model
+    #[serde(default)]
+    tool_choice: Option<Value>,
+    #[serde(default)]
+    stream: bool,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u32>,
+    max_completion_tokens: Option<u32>,
+    seed: Option<i64>,
+    #[serde(default)]
+    response_format: Option<Value>,
+}
+
+impl OpenAiChatRequest {
+    /// Sampling parameters, constrained by `response_format` when it asks for JSON
+    fn params(&self) -> GenerationParams {
+        let json_schema = self.response_format.as_ref().and_then(|format| match format["type"].as_str() {
+            Some("json_object") => Some(json!({})),
+            Some("json_schema") => Some(format["json_schema"]["schema"].clone()),
+            _ => None,
+        });
+        GenerationParams {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_completion_tokens.or(self.max_tokens),
+            seed: self.seed,
+            json_schema,
+            ..GenerationParams::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsRequest {
+    model: String,
+    prompt: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedRequest {
+    model: String,
+    input: Value,
+}
+
+/// A generation to run for `/api/generate` or `/api/chat`
+struct Completion {
+    model: String,
+    prompt: String,
+    params: GenerationParams,
+    stream: bool,
+    chat: bool,
+    /// The conversation, sent through the backend's chat template; empty for a bare prompt
+    messages: Vec<ChatMessage>,
+    tools: Vec<Tool>,
+}
+
+impl Completion {
+    /// Generate the whole reply
+    fn run(&self, backend: &dyn LlmInterface) -> Result<String, LlmError> {
+        if self.messages.is_empty() {
+            backend.generate(&self.prompt)
+        } else {
+            backend.chat(&self.messages)
+        }
+    }
+
+    /// Generate the reply, passing text to `on_chunk` as it arrives
+    fn run_stream(&self, backend: &dyn LlmInterface, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        if self.messages.is_empty() {
+            backend.generate_stream(&self.prompt, on_chunk)
+        } else {
+            backend.chat_stream(&self.messages, on_chunk)
+        }
+    }
+
+    /// One response object; chat replies carry the text in `message`, generate replies in `response`
+    fn reply(&self, text: &str) -> Value {
+        let mut reply = json!({ "model": self.model, "created_at": rfc3339(SystemTime::now()) });
+        if self.chat {
+            reply["message"] = json!({ "role": "assistant", "content": text });
+        } else {
+            reply["response"] = json!(text);
+        }
+        reply["done"] = json!(false);
+        reply
+    }
+
+    fn final_reply(&self, text: &str, output: &str, started: Instant) -> Value {
+        let usage = Usage::estimate(&self.prompt, output);
+        let mut reply = self.reply(text);
+        reply["done"] = json!(true);
+        reply["done_reason"] = json!("stop");
+        reply["total_duration"] = json!(started.elapsed().as_nanos() as u64);
+        reply["prompt_eval_count"] = json!(usage.prompt_tokens);
+        reply["eval_count"] = json!(usage.completion_tokens);
+        reply
+    }
+
+    /// The final chat reply for an assistant message that may call tools
+    fn tool_reply(&self, message: &ChatMessage, started: Instant) -> Value {
+        let mut reply = self.final_reply(&message.content, &message.content, started);
+        if !message.tool_calls.is_empty() {
+            // Ollama's form: arguments as an object, no call id
+            let calls: Vec<Value> = message
+                .tool_calls
+                .iter()
+                .map(|call| json!({ "function": { "name": call.name, "arguments": call.arguments } }))
+                .collect();
+            reply["message"]["tool_calls"] = json!(calls);
+        }
+        reply
+    }
+}
+
+fn handle(mut request: Request, models: &LoadedModels) {
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return respond_error(request, LlmError::Io(e));
+    }
+
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    let result = match (request.method(), path.as_str()) {
+        (Method::Get | Method::Head, "/") => {
+            let _ = request.respond(Response::from_string("Ollama is running"));
+            return;
+        }
+        (Method::Get, "/api/version") => Ok(json!({ "version": env!("CARGO_PKG_VERSION") })),
+        (Method::Get, "/api/tags") => tags(),
+        (Method::Post, "/api/generate") => match generate_completion(&body) {
+            Ok(completion) => return complete(request, completion, models),
+            Err(e) => Err(e),
+        },
+        (Method::Post, "/api/chat") => match chat_completion(&body) {
+            Ok(completion) => return complete(request, completion, models),
+            Err(e) => Err(e),
+        },
+        (Method::Post, "/api/embeddings") => embeddings(&body, models),
+        (Method::Post, "/api/embed") => embed(&body, models),
+        (Method::Post, "/v1/chat/completions") => return openai_chat(request, &body, models),
+        (Method::Get, "/v1/models") => openai_models(),
+        _ => {
+            let error = json!({ "error": format!("{} {} not found", request.method(), path) });
+            return respond_json(request, 404, &error);
+        }
+    };
+
+    match result {
+        Ok(value) => respond_json(request, 200, &value),
+        Err(e) => respond_error(request, e),
+    }
+}
+
+fn generate_completion(body: &str) -> Result<Completion, LlmError> {
+    let request: GenerateRequest = parse(body)?;
+    // A system prompt makes it a two-message conversation
+    let messages = match &request.system {
+        Some(system) => vec![ChatMessage::system(system.as_str()), ChatMessage::user(request.prompt.as_str())],
+        None => Vec::new(),
+    };
+    Ok(Completion {
+        model: request.model,
+        prompt: if messages.is_empty() { request.prompt } else { render_transcript(&messages) },
+        params: request.options.params(request.format.as_ref()),
+        stream: request.stream.unwrap_or(true),
+        chat: false,
+        messages,
+        tools: Vec::new(),
+    })
+}
+
+fn chat_completion(body: &str) -> Result<Completion, LlmError> {
+    let request: ChatRequest = parse(body)?;
+    let messages = parse_messages(&request.messages)?;
+    Ok(Completion {
+        model: request.model,
+        prompt: render_transcript(&messages),
+        params: request.options.params(request.format.as_ref()),
+        stream: request.stream.unwrap_or(true),
+        chat: true,
+        messages,
+        tools: parse_tools(&request.tools)?,
+    })
+}
+
+fn parse_messages(messages: &[Value]) -> Result<Vec<ChatMessage>, LlmError> {
+    messages.iter().map(tools::message_from_openai).collect()
+}
+
+fn parse_tools(tools: &[Value]) -> Result<Vec<Tool>, LlmError> {
+    tools.iter().map(Tool::from_openai).collect()
+}
+
+/// Run a completion, streaming NDJSON objects straight to the connection as text arrives
+fn complete(request: Request, completion: Completion, models: &LoadedModels) {
+    let started = Instant::now();
+
+    // Tool calls can only be read from the whole reply, so it arrives as one object
+    if !completion.tools.is_empty() {
+        let reply = models.with_model(&completion.model, &completion.params, |backend| {
+            backend.chat_with_tools(&completion.messages, &completion.tools)
+        });
+        let reply = match reply {
+            Ok(message) => completion.tool_reply(&message, started),
+            Err(e) => return respond_error(request, e),
+        };
+        if !completion.stream {
+            return respond_json(request, 200, &reply);
+        }
+        if let Some(mut writer) = start_stream(request, "application/x-ndjson") {
+            let _ = write_line(&mut writer, &reply).and_then(|_| end_stream(&mut writer));
+        }
+        return;
+    }
+
+    if !completion.stream {
+        return match models.with_model(&completion.model, &completion.params, |backend| completion.run(backend)) {
+            Ok(text) => respond_json(request, 200, &completion.final_reply(&text, &text, started)),
+            Err(e) => respond_error(request, e),
+        };
+    }
+
+    // The response head goes out once the model is ready, so a failure to open it is still a 4xx/5xx
+    let mut request = Some(request);
+    let mut writer = None;
+    let result = models.with_model(&completion.model, &completion.params, |backend| {
+        writer = request.take().and_then(|request| start_stream(request, "application/x-ndjson"));
+        let Some(writer) = writer.as_mut() else {
+            return Ok(String::new());
+        };
+        // Stop generating once the client goes away
+        completion.run_stream(backend, &mut |chunk| write_line(writer, &completion.reply(chunk)).is_ok())
+    });
+    if let Some(request) = request {
+        return respond_error(request, result.err().unwrap_or(LlmError::EmptyResponse));
+    }
+    let Some(mut writer) = writer else {
+        return;
+    };
+    let last = match result {
+        Ok(output) => completion.final_reply("", &output, started),
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+    let _ = write_line(&mut writer, &last).and_then(|_| end_stream(&mut writer));
+}
+
+/// Serve `/v1/chat/completions`, streaming server-sent events when asked to
+fn openai_chat(request: Request, body: &str, models: &LoadedModels) {
+    let parsed = parse::<OpenAiChatRequest>(body).and_then(|chat| {
+        let messages = parse_messages(&chat.messages)?;
+        let tools = match chat.tool_choice.as_ref().and_then(Value::as_str) {
+            Some("none") => Vec::new(),
+            _ => parse_tools(&chat.tools)?,
+        };
+        Ok((chat, messages, tools))
+    });
+    let (chat, messages, tools) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return respond_openai_error(request, e),
+    };
+
+    let id = format!("chatcmpl-{:x}", Sha256::digest(format!("{}{:?}", body, SystemTime::now())))[..24].to_string();
+    let created = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let chunk = |delta: Value, finish_reason: Option<&str>| {
+        json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": chat.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+    };
+
+    if !chat.stream {
+        let reply = models.with_model(&chat.model, &chat.params(), |backend| {
+            if tools.is_empty() {
+                backend.chat(&messages).map(ChatMessage::assistant)
+            } else {
+                backend.chat_with_tools(&messages, &tools)
+            }
+        });
+        let message = match reply {
+            Ok(message) => message,
+            Err(e) => return respond_openai_error(request, e),
+        };
+        let usage = Usage::estimate(&render_transcript(&messages), &message.content);
+        let response = json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": created,
+            "model": chat.model,
+            "choices": [{ "index": 0, "message": openai_reply(&message), "finish_reason": finish_reason(&message) }],
+            "usage": usage,
+        });
+        return respond_json(request, 200, &response);
+    }
+
+    // As in `complete`, the stream starts once the model is ready
+    let mut request = Some(request);
+    let mut writer = None;
+    let result = models.with_model(&chat.model, &chat.params(), |backend| {
+        writer = request.take().and_then(|request| start_stream(request, "text/event-stream"));
+        let Some(writer) = writer.as_mut() else {
+            return Ok(ChatMessage::assistant(""));
+        };
+        if tools.is_empty() {
+            // Stop generating once the client goes away
+            backend
+                .chat_stream(&messages, &mut |text| {
+                    write_event(writer, &chunk(json!({ "role": "assistant", "content": text }), None)).is_ok()
+                })
+                .map(ChatMessage::assistant)
+        } else {
+            let message = backend.chat_with_tools(&messages, &tools)?;
+            let mut delta = openai_reply(&message);
+            if let Some(Value::Array(calls)) = delta.get_mut("tool_calls") {
+                // Streamed calls carry their position
+                for (index, call) in calls.iter_mut().enumerate() {
+                    call["index"] = json!(index);
+                }
+            }
+            write_event(writer, &chunk(delta, None)).map_err(LlmError::Io)?;
+            Ok(message)
+        }
+    });
+    if let Some(request) = request {
+        return respond_openai_error(request, result.err().unwrap_or(LlmError::EmptyResponse));
+    }
+    let Some(mut writer) = writer else {
+        return;
+    };
+    let last = match result {
+        Ok(message) => chunk(json!({}), Some(finish_reason(&message))),
+        Err(e) => json!({ "error": { "message": e.to_string(), "type": "server_error" } }),
+    };
+    let _ = write_event(&mut writer, &last)
+        .and_then(|_| write_chunk(&mut writer, "data: [DONE]\n\n"))
+        .and_then(|_| end_stream(&mut writer));
+}
+
+/// An assistant message in OpenAI's form; `content` is null when the model only calls tools
+fn openai_reply(message: &ChatMessage) -> Value {
+    let mut reply = tools::message_to_openai(message);
+    if message.content.is_empty() && !message.tool_calls.is_empty() {
+        reply["content"] = Value::Null;
+    }
+    reply
+}
+
+fn finish_reason(message: &ChatMessage) -> &'static str {
+    if message.tool_calls.is_empty() {
+        "stop"
+    } else {
+        "tool_calls"
+    }
+}
+
+/// Models in OpenAI's list format, from the same listing as `/api/tags`
+fn openai_models() -> Result<Value, LlmError> {
+    let tags = tags()?;
+    let models: Vec<Value> = tags["models"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|model| json!({ "id": model["name"], "object": "model", "owned_by": "agentd" }))
+        .collect();
+    Ok(json!({ "object": "list", "data": models }))
+}
+
+/// Send the response head for a chunked stream, or `None` if the client is gone
+fn start_stream(request: Request, content_type: &str) -> Option<Box<dyn Write + Send>> {
+    let mut writer = request.into_writer();
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+        content_type
+    );
+    writer.write_all(head.as_bytes()).ok()?;
+    Some(writer)
+}
+
+fn end_stream(writer: &mut dyn Write) -> io::Result<()> {
+    writer.write_all(b"0\r\n\r\n")?;
+    writer.flush()
+}
+
+fn write_chunk(writer: &mut dyn Write, data: &str) -> io::Result<()> {
+    write!(writer, "{:x}\r\n{}\r\n", data.len(), data)?;
+    writer.flush()
+}
+
+fn write_line(writer: &mut dyn Write, value: &Value) -> io::Result<()> {
+    write_chunk(writer, &format!("{}\n", value))
+}
+
+fn write_event(writer: &mut dyn Write, value: &Value) -> io::Result<()> {
+    write_chunk(writer, &format!("data: {}\n\n", value))
+}
+
+/// Registered, aliased and discovered models with files on disk
+fn tags() -> Result<Value, LlmError> {
+    let config = config::load_config()?;
+    let mut models: BTreeMap<String, ModelEntry> = discovery::scan(&config.discovery)?
+        .models
+        .into_iter()
+        .filter(|(_, entry)| !config.models.values().any(|registered| registered.file == entry.file))
+        .collect();
+    models.extend(config.models.clone());
+    for (alias, target) in &config.aliases {
+        if let Some(entry) = models.get(target).cloned() {
+            models.insert(alias.clone(), entry);
+        }
+    }
+
+    let models_dir = config::get_models_dir();
+    let listed: Vec<Value> = models
+        .iter()
+        .filter_map(|(name, entry)| {
+            let path = models_dir.join(&entry.file);
+            // Remote models have no file to describe
+            let metadata = fs::metadata(&path).ok().filter(|metadata| metadata.is_file())?;
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            // Hashing multi-gigabyte files is too slow for a listing; identify the file instead
+            let digest = Sha256::digest(format!("{}:{}:{}", path.display(), metadata.len(), rfc3339(modified)));
+            Some(json!({
+                "name": name,
+                "model": name,
+                "modified_at": rfc3339(modified),
+                "size": metadata.len(),
+                "digest": format!("{:x}", digest),
+                "details": { "format": "gguf", "family": "", "parameter_size": "", "quantization_level": "" },
+            }))
+        })
+        .collect();
+
+    Ok(json!({ "models": listed }))
+}
+
+fn embeddings(body: &str, models: &LoadedModels) -> Result<Value, LlmError> {
+    let request: EmbeddingsRequest = parse(body)?;
+    let embedding = models.with_model(&request.model, &GenerationParams::default(), |backend| backend.embed(&request.prompt))?;
+    Ok(json!({ "embedding": embedding }))
+}
+
+/// The newer batch form: `input` is a string or an array of strings
+fn embed(body: &str, models: &LoadedModels) -> Result<Value, LlmError> {
+    let request: EmbedRequest = parse(body)?;
+    let inputs: Vec<String> = match request.input {
+        Value::String(input) => vec![input],
+        Value::Array(inputs) => inputs
+            .into_iter()
+            .map(|input| match input {
+                Value::String(input) => Ok(input),
+                _ => Err(LlmError::InvalidInput("'input' must be a string or an array of strings".to_string())),
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err(LlmError::InvalidInput("'input' must be a string or an array of strings".to_string())),
+    };
+
+    let embeddings = models.with_model(&request.model, &GenerationParams::default(), |backend| {
+        inputs.iter().map(|input| backend.embed(input)).collect::<Result<Vec<_>, _>>()
+    })?;
+    Ok(json!({ "model": request.model, "embeddings": embeddings }))
+}
+
+/// The agentd name for a requested model, dropping Ollama's implicit `:latest` tag
+fn model_name(name: &str) -> &str {
+    match name.strip_suffix(":latest") {
+        Some(base) if config::find_model_entry(name).is_err() && config::find_model_entry(base).is_ok() => base,
+        _ => name,
+    }
+}
+
+fn parse<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, LlmError> {
+    serde_json::from_str(body).map_err(|e| LlmError::InvalidInput(format!("Invalid request body: {}", e)))
+}
+
+fn respond_json(request: Request, status: u16, value: &Value) {
+    let header = Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+    let response = Response::from_string(value.to_string()).with_status_code(status).with_header(header);
+    let _ = request.respond(response);
+}
+
+fn respond_error(request: Request, error: LlmError) {
+    let status = match error {
+        LlmError::InvalidModelPath(_) => 404,
+        LlmError::InvalidInput(_) | LlmError::Json(_) => 400,
+        _ => 500,
+    };
+    respond_json(request, status, &json!({ "error": error.to_string() }));
+}
+
+/// Errors in OpenAI's form: `{"error": {"message", "type"}}`
+fn respond_openai_error(request: Request, error: LlmError) {
+    let (status, kind) = match error {
+        LlmError::InvalidModelPath(_) => (404, "not_found_error"),
+        LlmError::InvalidInput(_) | LlmError::Json(_) => (400, "invalid_request_error"),
+        _ => (500, "server_error"),
+    };
+    respond_json(request, status, &json!({ "error": { "message": error.to_string(), "type": kind } }));
+}
+
+/// Format a time as RFC 3339 in UTC
+fn rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Civil date from days since the epoch (Howard Hinnant's days_from_civil, inverted)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rem / 3_600, rem / 60 % 60, rem % 60)
+}
@@ -0,0 +1,394 @@
+use crate::chat::{ChatMessage, Role};
+use crate::error::LlmError;
+use crate::llm::{estimate_tokens, LlmInterface};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Tokens kept free for the reply when no max_tokens is configured
+const DEFAULT_REPLY_RESERVE: u32 = 256;
+
+/// On-disk representation of a named conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub name: String,
+    pub model: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub context_size: Option<u32>,
+    pub messages: Vec<ChatMessage>,
+}
+
+impl SessionRecord {
+    pub fn new(name: impl Into<String>, model: impl Into<String>) -> Self {
+        let now = unix_now();
+        Self {
+            name: name.into(),
+            model: model.into(),
+            created_at: now,
+            updated_at: now,
+            context_size: None,
+            messages: Vec::new(),
+        }
+    }
+}
+
+/// Short listing entry returned by `SessionStore::list`
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub name: String,
+    pub model: String,
+    pub message_count: usize,
+    pub updated_at: u64,
+}
+
+/// Directory of session files, one `<name>.json` per session
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Store rooted at `~/.agentd/sessions`
+    pub fn open_default() -> Self {
+        Self::new(crate::config::get_sessions_dir())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path(&self, name: &str) -> Result<PathBuf, LlmError> {
+        validate_session_name(name)?;
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).map(|p| p.exists()).unwrap_or(false)
+    }
+
+    pub fn load(&self, name: &str) -> Result<SessionRecord, LlmError> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Err(LlmError::Session(format!("Session '{}' not found", name)));
+        }
+
+        let content = fs::read_to_string(&path).map_err(LlmError::Io)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, record: &SessionRecord) -> Result<(), LlmError> {
+        let path = self.path(&record.name)?;
+        fs::create_dir_all(&self.dir).map_err(LlmError::Io)?;
+
+        // Write to a temporary file first so an interrupted save never truncates history
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(record)?).map_err(LlmError::Io)?;
+        fs::rename(&tmp_path, &path).map_err(LlmError::Io)?;
+        Ok(())
+    }
+
+    /// List all sessions, most recently updated first
+    pub fn list(&self) -> Result<Vec<SessionSummary>, LlmError> {
+        let mut sessions = Vec::new();
+
+        if !self.dir.exists() {
+            return Ok(sessions);
+        }
+
+        for entry in fs::read_dir(&self.dir).map_err(LlmError::Io)? {
+            let path = entry.map_err(LlmError::Io)?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+
+            if let Ok(record) = self.load(name) {
+                sessions.push(SessionSummary {
+                    name: record.name,
+                    model: record.model,
+                    message_count: record.messages.len(),
+                    updated_at: record.updated_at,
+                });
+            }
+        }
+
+        sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| a.name.cmp(&b.name)));
+        Ok(sessions)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), LlmError> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Err(LlmError::Session(format!("Session '{}' not found", name)));
+        }
+        fs::remove_file(path).map_err(LlmError::Io)
+    }
+
+    /// Copy an existing session's history into a new session
+    pub fn fork(&self, source: &str, target: &str) -> Result<SessionRecord, LlmError> {
+        if self.exists(target) {
+            return Err(LlmError::Session(format!("Session '{}' already exists", target)));
+        }
+
+        let mut record = self.load(source)?;
+        let now = unix_now();
+        record.name = target.to_string();
+        record.created_at = now;
+        record.updated_at = now;
+
+        self.save(&record)?;
+        Ok(record)
+    }
+}
+
+/// A persisted conversation driving any `LlmInterface`.
+///
+/// The full history is kept on disk; only the prompt sent to the model is
+/// trimmed to fit the model's context size.
+pub struct Session {
+    record: SessionRecord,
+    llm: Box<dyn LlmInterface + Send + Sync>,
+    store: SessionStore,
+    reply_reserve: u32,
+    autosave: bool,
+}
+
+impl Session {
+    /// Start a new, empty session. Nothing is written until the first message.
+    pub fn new(
+        store: SessionStore,
+        name: &str,
+        model: &str,
+        llm: Box<dyn LlmInterface + Send + Sync>,
+    ) -> Result<Self, LlmError> {
+        validate_session_name(name)?;
+        if store.exists(name) {
+            return Err(LlmError::Session(format!("Session '{}' already exists", name)));
+        }
+
+        Ok(Self::from_record(store, SessionRecord::new(name, model), llm))
+    }
+
+    /// Resume a session previously saved in `store`
+    pub fn resume(
+        store: SessionStore,
+        name: &str,
+        llm: Box<dyn LlmInterface + Send + Sync>,
+    ) -> Result<Self, LlmError> {
+        let record = store.load(name)?;
+        Ok(Self::from_record(store, record, llm))
+    }
+
+    pub fn from_record(
+        store: SessionStore,
+        record: SessionRecord,
+        llm: Box<dyn LlmInterface + Send + Sync>,
+    ) -> Self {
+        Self {
+            record,
+            llm,
+            store,
+            reply_reserve: DEFAULT_REPLY_RESERVE,
+            autosave: true,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.record.name
+    }
+
+    pub fn model(&self) -> &str {
+        &self.record.model
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.record.messages
+    }
+
+    pub fn record(&self) -> &SessionRecord {
+        &self.record
+    }
+
+    pub fn llm(&self) -> &(dyn LlmInterface + Send + Sync) {
+        self.llm.as_ref()
+    }
+
+    /// Switch the backend used for subsequent replies, keeping the history
+    pub fn set_llm(&mut self, model: &str, llm: Box<dyn LlmInterface + Send + Sync>) {
+        self.record.model = model.to_string();
+        self.llm = llm;
+    }
+
+    /// Replace the backend's arguments, keeping the backend and any model it has loaded
+    pub fn set_llm_args(&mut self, args: Vec<String>) {
+        // `with_args` takes the backend by value; a mock holds its place meanwhile
+        let llm = std::mem::replace(&mut self.llm, Box::new(crate::testing::MockBackend::new()));
+        self.llm = llm.with_args(args);
+    }
+
+    pub fn with_context_size(mut self, context_size: Option<u32>) -> Self {
+        self.record.context_size = context_size;
+        self
+    }
+
+    pub fn set_context_size(&mut self, context_size: Option<u32>) {
+        self.record.context_size = context_size;
+    }
+
+    /// Number of tokens kept free for the model's reply when trimming history
+    pub fn with_reply_reserve(mut self, tokens: u32) -> Self {
+        self.reply_reserve = tokens;
+        self
+    }
+
+    /// Whether `send` and `send_stream` save after each exchange (the default)
+    pub fn with_autosave(mut self, autosave: bool) -> Self {
+        self.autosave = autosave;
+        self
+    }
+
+    /// Replace the system prompt, or add one at the start of the history
+    pub fn set_system(&mut self, content: impl Into<String>) {
+        let content = content.into();
+        match self.record.messages.iter_mut().find(|m| m.role == Role::System) {
+            Some(message) => message.content = content,
+            None => self.record.messages.insert(0, ChatMessage::system(content)),
+        }
+    }
+
+    /// Drop all messages except the system prompt
+    pub fn reset(&mut self) {
+        self.record.messages.retain(|m| m.role == Role::System);
+    }
+
+    /// Send a user message, persist the exchange and return the reply
+    pub fn send(&mut self, content: &str) -> Result<String, LlmError> {
+        self.exchange(content, |llm, messages| llm.chat(messages))
+    }
+
+    /// Like `send`, passing the reply to `on_chunk` as it is generated. A reply
+    /// stopped early by `on_chunk` is kept in the history as far as it got.
+    pub fn send_stream(&mut self, content: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        self.exchange(content, |llm, messages| llm.chat_stream(messages, on_chunk))
+    }
+
+    fn exchange<F>(&mut self, content: &str, reply_to: F) -> Result<String, LlmError>
+    where
+        F: FnOnce(&(dyn LlmInterface + Send + Sync), &[ChatMessage]) -> Result<String, LlmError>,
+    {
+        self.record.messages.push(ChatMessage::user(content));
+
+        let reply = match reply_to(self.llm.as_ref(), &self.context_window()) {
+            Ok(reply) => reply,
+            Err(e) => {
+                // Keep the stored history consistent with what the model has answered
+                self.record.messages.pop();
+                return Err(e);
+            }
+        };
+
+        self.record.messages.push(ChatMessage::assistant(reply.clone()));
+        if self.autosave {
+            self.save()?;
+        }
+        Ok(reply)
+    }
+
+    pub fn save(&mut self) -> Result<(), LlmError> {
+        self.record.updated_at = unix_now();
+        self.store.save(&self.record)
+    }
+
+    /// Save under a new name, which later saves also use
+    pub fn save_as(&mut self, name: &str) -> Result<(), LlmError> {
+        validate_session_name(name)?;
+        if name != self.record.name && self.store.exists(name) {
+            return Err(LlmError::Session(format!("Session '{}' already exists", name)));
+        }
+        self.record.name = name.to_string();
+        self.save()
+    }
+
+    /// Estimated tokens in the messages that will be sent to the model
+    pub fn context_tokens(&self) -> usize {
+        self.context_window().iter().map(message_cost).sum()
+    }
+
+    /// The messages that will be sent to the model: system prompts plus as many
+    /// of the most recent messages as fit in the context size. The latest
+    /// message is always included.
+    pub fn context_window(&self) -> Vec<ChatMessage> {
+        let messages = &self.record.messages;
+        let Some(context_size) = self.record.context_size else {
+            return messages.clone();
+        };
+
+        let budget = context_size.saturating_sub(self.reply_reserve) as usize;
+        let cost = message_cost;
+
+        let mut used: usize = messages
+            .iter()
+            .filter(|m| m.role == Role::System)
+            .map(cost)
+            .sum();
+
+        let mut keep = vec![false; messages.len()];
+        for (index, message) in messages.iter().enumerate().rev() {
+            if message.role == Role::System {
+                continue;
+            }
+
+            let is_latest = index + 1 == messages.len();
+            if !is_latest && used + cost(message) > budget {
+                break;
+            }
+            used += cost(message);
+            keep[index] = true;
+        }
+
+        messages
+            .iter()
+            .enumerate()
+            .filter(|(index, message)| keep[*index] || message.role == Role::System)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+}
+
+/// Estimated tokens for a message, including a few for its role markers
+fn message_cost(message: &ChatMessage) -> usize {
+    estimate_tokens(&message.content) + 4
+}
+
+fn validate_session_name(name: &str) -> Result<(), LlmError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(LlmError::Session(format!(
+            "Invalid session name '{}': use letters, digits, '-', '_' or '.'",
+            name
+        )))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,269 @@
+use crate::error::LlmError;
+use crate::llm::{GenerationParams, LlmConfig, LlmInterface};
+use crate::schema;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// One scripted reply from a `MockBackend`
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockReply {
+    Text(String),
+    /// Fails with `LlmError::ProcessExecution` carrying this message
+    Error(String),
+    /// Fails with `LlmError::EmptyResponse`
+    Empty,
+}
+
+impl MockReply {
+    pub fn text(text: impl Into<String>) -> Self {
+        MockReply::Text(text.into())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        MockReply::Error(message.into())
+    }
+}
+
+/// A request received by a `MockBackend`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockCall {
+    pub prompt: String,
+    pub params: GenerationParams,
+}
+
+/// In-memory `LlmInterface` for testing code that uses agentd without a model.
+///
+/// Replies are chosen in this order: the next scripted reply, the first rule
+/// whose pattern appears in the prompt, the fixed response, and finally an
+/// echo of the prompt, or a minimal conforming value when the request carries
+/// a JSON schema. Every call is recorded and can be inspected through
+/// the handle returned by `calls()`, which stays valid after boxing.
+#[derive(Debug, Clone)]
+pub struct MockBackend {
+    config: LlmConfig,
+    script: Arc<Mutex<VecDeque<MockReply>>>,
+    rules: Vec<(String, MockReply)>,
+    response: Option<MockReply>,
+    latency: Duration,
+    chunk_latency: Duration,
+    calls: Arc<Mutex<Vec<MockCall>>>,
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self {
+            config: LlmConfig::new("mock", "mock"),
+            script: Arc::new(Mutex::new(VecDeque::new())),
+            rules: Vec::new(),
+            response: None,
+            latency: Duration::ZERO,
+            chunk_latency: Duration::ZERO,
+            calls: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Report `model_name` as the model path in `config()`
+    pub fn with_model_name(mut self, model_name: &str) -> Self {
+        self.config.model_path = model_name.to_string();
+        self
+    }
+
+    /// Answer every request with `text`
+    pub fn with_response(mut self, text: impl Into<String>) -> Self {
+        self.response = Some(MockReply::Text(text.into()));
+        self
+    }
+
+    /// Fail every request with `LlmError::ProcessExecution(message)`
+    pub fn with_error(mut self, message: impl Into<String>) -> Self {
+        self.response = Some(MockReply::Error(message.into()));
+        self
+    }
+
+    /// Answer with `reply` when the prompt contains `pattern`
+    pub fn with_rule(mut self, pattern: impl Into<String>, reply: MockReply) -> Self {
+        self.rules.push((pattern.into(), reply));
+        self
+    }
+
+    /// Answer the next requests with these replies, in order
+    pub fn with_script(self, replies: impl IntoIterator<Item = MockReply>) -> Self {
+        self.script.lock().unwrap().extend(replies);
+        self
+    }
+
+    /// Sleep before answering each request
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sleep between chunks when streaming
+    pub fn with_chunk_latency(mut self, latency: Duration) -> Self {
+        self.chunk_latency = latency;
+        self
+    }
+
+    /// Shared log of every request this backend (or any clone of it) received
+    pub fn calls(&self) -> Arc<Mutex<Vec<MockCall>>> {
+        self.calls.clone()
+    }
+
+    fn reply(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        self.calls.lock().unwrap().push(MockCall {
+            prompt: prompt.to_string(),
+            params: params.clone(),
+        });
+
+        if !self.latency.is_zero() {
+            thread::sleep(self.latency);
+        }
+
+        let reply = self
+            .script
+            .lock()
+            .unwrap()
+            .pop_front()
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .find(|(pattern, _)| prompt.contains(pattern.as_str()))
+                    .map(|(_, reply)| reply.clone())
+            })
+            .or_else(|| self.response.clone())
+            .unwrap_or_else(|| match &params.json_schema {
+                Some(schema) => MockReply::Text(schema::example(schema).to_string()),
+                None => MockReply::Text(format!("mock: {}", prompt)),
+            });
+
+        match reply {
+            MockReply::Text(text) => Ok(text),
+            MockReply::Error(message) => Err(LlmError::ProcessExecution(message)),
+            MockReply::Empty => Err(LlmError::EmptyResponse),
+        }
+    }
+}
+
+impl LlmInterface for MockBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        self.reply(prompt, &GenerationParams::default())
+    }
+
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        self.reply(prompt, params)
+    }
+
+    /// Streams the reply word by word, keeping the whitespace that follows each word
+    fn generate_stream(&self, prompt: &str, on_chunk: &mut dyn FnMut(&str) -> bool) -> Result<String, LlmError> {
+        let text = self.reply(prompt, &GenerationParams::default())?;
+        let mut produced = String::new();
+
+        for chunk in text.split_inclusive(char::is_whitespace) {
+            if !self.chunk_latency.is_zero() {
+                thread::sleep(self.chunk_latency);
+            }
+            produced.push_str(chunk);
+            if !on_chunk(chunk) {
+                break;
+            }
+        }
+
+        Ok(produced)
+    }
+
+    /// A deterministic, unit-length vector of byte frequencies, so equal texts embed equally
+    fn embed(&self, text: &str) -> Result<Vec<f32>, LlmError> {
+        let mut vector = vec![0.0f32; MOCK_EMBEDDING_DIMENSIONS];
+        for byte in text.bytes() {
+            vector[byte as usize % MOCK_EMBEDDING_DIMENSIONS] += 1.0;
+        }
+        let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|value| *value /= norm);
+        }
+        Ok(vector)
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(mut self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self.config.additional_args = args;
+        self
+    }
+}
+
+/// Length of the vectors returned by `MockBackend::embed`
+pub const MOCK_EMBEDDING_DIMENSIONS: usize = 16;
+
+/// A stand-in `llama-cli` executable and model file for exercising the real
+/// llama.cpp backend without llama.cpp or a model installed.
+///
+/// The script discards stdin, records its arguments and prints a fixed response.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct FakeLlamaCli {
+    pub executable: PathBuf,
+    pub model_path: PathBuf,
+    args_log: PathBuf,
+}
+
+#[cfg(unix)]
+impl FakeLlamaCli {
+    /// Create the fake executable and an empty `fake-model.gguf` in `dir`
+    pub fn create(dir: &Path, response: &str) -> Result<Self, LlmError> {
+        Self::create_with_status(dir, response, 0)
+    }
+
+    /// Like `create`, but the executable exits with `status` after printing
+    pub fn create_with_status(dir: &Path, response: &str, status: i32) -> Result<Self, LlmError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::create_dir_all(dir).map_err(LlmError::Io)?;
+        let executable = dir.join("fake-llama-cli");
+        let model_path = dir.join("fake-model.gguf");
+        let args_log = dir.join("fake-llama-cli.args");
+
+        let script = format!(
+            "#!/bin/sh\nprintf '%s\\n' \"$@\" > '{}'\ncat > /dev/null\nprintf '%s\\n' '{}'\nexit {}\n",
+            args_log.display(),
+            response.replace('\'', "'\\''"),
+            status
+        );
+        fs::write(&executable, script).map_err(LlmError::Io)?;
+        fs::set_permissions(&executable, fs::Permissions::from_mode(0o755)).map_err(LlmError::Io)?;
+        fs::write(&model_path, b"GGUF").map_err(LlmError::Io)?;
+
+        Ok(Self {
+            executable,
+            model_path,
+            args_log,
+        })
+    }
+
+    /// Backend configuration pointing at the fake executable and model
+    pub fn config(&self) -> LlmConfig {
+        LlmConfig::new(
+            self.executable.to_string_lossy(),
+            self.model_path.to_string_lossy(),
+        )
+    }
+
+    /// Arguments passed on the most recent invocation
+    pub fn last_args(&self) -> Vec<String> {
+        fs::read_to_string(&self.args_log)
+            .map(|log| log.lines().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+}
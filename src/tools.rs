@@ -0,0 +1,545 @@
+use crate::chat::{ChatMessage, Role};
+use crate::error::LlmError;
+use crate::llm::LlmInterface;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A function the model may call, described by a JSON Schema for its arguments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON Schema of the arguments object
+    #[serde(default = "no_parameters")]
+    pub parameters: Value,
+}
+
+fn no_parameters() -> Value {
+    json!({"type": "object", "properties": {}})
+}
+
+impl Tool {
+    pub fn new(name: impl Into<String>, description: impl Into<String>, parameters: Value) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+        }
+    }
+
+    /// The OpenAI (and Ollama) form: `{"type": "function", "function": {...}}`
+    pub fn to_openai(&self) -> Value {
+        json!({ "type": "function", "function": self })
+    }
+
+    /// Read a tool in the OpenAI form, or a bare `{name, description, parameters}` object
+    pub fn from_openai(value: &Value) -> Result<Self, LlmError> {
+        let function = value.get("function").unwrap_or(value);
+        serde_json::from_value(function.clone()).map_err(|e| LlmError::InvalidInput(format!("Invalid tool: {}", e)))
+    }
+}
+
+/// A request from the model to run a tool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Identifies the call, so a tool message can say which call it answers
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    /// Arguments object, as generated; check it against the tool's schema before use
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+impl ToolCall {
+    /// A call with a fresh id
+    pub fn new(name: impl Into<String>, arguments: Value) -> Self {
+        Self {
+            id: new_call_id(),
+            name: name.into(),
+            arguments,
+        }
+    }
+
+    /// The OpenAI form, whose `arguments` is a JSON string
+    pub fn to_openai(&self) -> Value {
+        json!({
+            "id": self.id,
+            "type": "function",
+            "function": { "name": self.name, "arguments": self.arguments.to_string() },
+        })
+    }
+
+    /// Read a call in the OpenAI form or Ollama's, where `arguments` is an object and `id` is optional
+    pub fn from_openai(value: &Value) -> Result<Self, LlmError> {
+        let function = value.get("function").unwrap_or(value);
+        let name = function["name"]
+            .as_str()
+            .ok_or_else(|| LlmError::InvalidInput("Tool call has no function name".to_string()))?;
+        let arguments = match &function["arguments"] {
+            Value::String(text) => serde_json::from_str(text)
+                .map_err(|e| LlmError::InvalidInput(format!("Arguments of tool call '{}' are not JSON: {}", name, e)))?,
+            Value::Null => json!({}),
+            arguments => arguments.clone(),
+        };
+        Ok(Self {
+            id: value["id"].as_str().map(str::to_string).unwrap_or_else(new_call_id),
+            name: name.to_string(),
+            arguments,
+        })
+    }
+}
+
+fn new_call_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    format!("call_{:08x}{:04x}", nanos, COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff)
+}
+
+/// A message in the OpenAI chat format
+pub fn message_to_openai(message: &ChatMessage) -> Value {
+    let mut value = json!({ "role": message.role.as_str(), "content": message.content });
+    if !message.tool_calls.is_empty() {
+        value["tool_calls"] = message.tool_calls.iter().map(ToolCall::to_openai).collect();
+    }
+    if let Some(id) = &message.tool_call_id {
+        value["tool_call_id"] = json!(id);
+    }
+    value
+}
+
+/// Read a message in the OpenAI or Ollama chat format. `content` may be null
+/// or, as in OpenAI's multi-part form, a list of text parts.
+pub fn message_from_openai(value: &Value) -> Result<ChatMessage, LlmError> {
+    let role: Role = serde_json::from_value(value["role"].clone())
+        .map_err(|_| LlmError::InvalidInput(format!("Invalid message role: {}", value["role"])))?;
+    let content = match &value["content"] {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().filter_map(|part| part["text"].as_str()).collect::<Vec<_>>().join(""),
+        _ => String::new(),
+    };
+    let tool_calls = match &value["tool_calls"] {
+        Value::Array(calls) => calls.iter().map(ToolCall::from_openai).collect::<Result<_, _>>()?,
+        _ => Vec::new(),
+    };
+    Ok(ChatMessage {
+        tool_calls,
+        tool_call_id: value["tool_call_id"].as_str().map(str::to_string),
+        ..ChatMessage::new(role, content)
+    })
+}
+
+/// How a model family expects tools to be described and calls to be written.
+///
+/// Each format is the family's documented tool-calling convention in text
+/// form, sent through `chat`. A server such as `llama-server` wraps it in the
+/// model's chat template; a one-off llama.cpp run sees agentd's plain transcript,
+/// as family chat templates are not rendered locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolFormat {
+    /// Hermes-style `<tool_call>` tags, also the fallback for unknown models
+    Hermes,
+    /// Qwen 2.5 and later: `<tool_call>` tags with a `# Tools` system section
+    Qwen,
+    /// Llama 3.1 and later: a bare `{"name": ..., "parameters": ...}` object
+    Llama3,
+    /// Gemma: Python-style calls in a ```` ```tool_code ```` block
+    Gemma,
+}
+
+impl ToolFormat {
+    /// Guess the format from a model name or file path
+    pub fn detect(model: &str) -> Self {
+        let name = model.rsplit(['/', '\\']).next().unwrap_or(model).to_lowercase();
+        if name.contains("hermes") {
+            ToolFormat::Hermes
+        } else if name.contains("qwen") {
+            ToolFormat::Qwen
+        } else if name.contains("gemma") {
+            ToolFormat::Gemma
+        } else if name.contains("llama-3") || name.contains("llama3") {
+            ToolFormat::Llama3
+        } else {
+            ToolFormat::Hermes
+        }
+    }
+
+    /// System prompt text describing `tools` and how to call them
+    pub fn render_tools(&self, tools: &[Tool]) -> String {
+        let signatures: Vec<String> = tools.iter().map(|tool| tool.to_openai().to_string()).collect();
+        match self {
+            ToolFormat::Hermes => format!(
+                "You are a function calling AI model. You are provided with function signatures within <tools></tools> XML tags. \
+                 You may call one or more functions to assist with the user query. \
+                 Don't make assumptions about what values to plug into functions.\n\
+                 <tools>\n{}\n</tools>\n\
+                 For each function call return a JSON object with function name and arguments within <tool_call></tool_call> XML tags as follows:\n\
+                 <tool_call>\n{{\"name\": <function-name>, \"arguments\": <args-json-object>}}\n</tool_call>",
+                signatures.join("\n")
+            ),
+            ToolFormat::Qwen => format!(
+                "# Tools\n\nYou may call one or more functions to assist with the user query.\n\n\
+                 You are provided with function signatures within <tools></tools> XML tags:\n<tools>\n{}\n</tools>\n\n\
+                 For each function call, return a json object with function name and arguments within <tool_call></tool_call> XML tags:\n\
+                 <tool_call>\n{{\"name\": <function-name>, \"arguments\": <args-json-object>}}\n</tool_call>",
+                signatures.join("\n")
+            ),
+            ToolFormat::Llama3 => {
+                let functions: Vec<String> = tools.iter().map(|tool| json!(tool).to_string()).collect();
+                format!(
+                    "You have access to the following functions. To call a function, respond only with a JSON object of the form \
+                     {{\"name\": function name, \"parameters\": dictionary of argument name and its value}}. Do not use variables.\n\n{}",
+                    functions.join("\n\n")
+                )
+            }
+            ToolFormat::Gemma => format!(
+                "You have access to functions. If you decide to invoke any of the function(s), you MUST put it in the format of\n\
+                 ```tool_code\nfunction_name(param1=value1, param2=value2)\n```\n\
+                 You SHOULD NOT include any other text in the response if you call a function.\n\n{}",
+                signatures.join("\n")
+            ),
+        }
+    }
+
+    /// How an assistant message with these calls appears in the transcript
+    pub fn render_calls(&self, calls: &[ToolCall]) -> String {
+        match self {
+            ToolFormat::Hermes | ToolFormat::Qwen => calls
+                .iter()
+                .map(|call| format!("<tool_call>\n{}\n</tool_call>", json!({"name": call.name, "arguments": call.arguments})))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ToolFormat::Llama3 => calls
+                .iter()
+                .map(|call| json!({"name": call.name, "parameters": call.arguments}).to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+            ToolFormat::Gemma => {
+                let lines: Vec<String> = calls.iter().map(python_call).collect();
+                format!("```tool_code\n{}\n```", lines.join("\n"))
+            }
+        }
+    }
+
+    /// How the output of tool `name` is passed back to the model
+    pub fn render_result(&self, name: &str, content: &str) -> String {
+        match self {
+            ToolFormat::Hermes => format!("<tool_response>\n{}\n</tool_response>", json!({"name": name, "content": content})),
+            ToolFormat::Qwen => format!("<tool_response>\n{}\n</tool_response>", content),
+            ToolFormat::Llama3 => content.to_string(),
+            ToolFormat::Gemma => format!("```tool_output\n{}\n```", content),
+        }
+    }
+
+    /// Split a reply into text and tool calls. A call that cannot be read
+    /// is `LlmError::InvalidResponse`. Llama 3 calls are bare JSON, so there
+    /// a reply only counts as calls if each one names one of the offered `tools`.
+    pub fn parse_reply(&self, text: &str, tools: &[Tool]) -> Result<ChatMessage, LlmError> {
+        let (content, tool_calls) = match self {
+            ToolFormat::Hermes | ToolFormat::Qwen => parse_tagged_calls(text)?,
+            ToolFormat::Llama3 => parse_json_calls(text, tools),
+            ToolFormat::Gemma => parse_tool_code(text)?,
+        };
+        Ok(ChatMessage {
+            tool_calls,
+            ..ChatMessage::assistant(content)
+        })
+    }
+}
+
+/// Rewrite a conversation for a model that only understands text: `tools`
+/// are described in the system prompt, and tool calls and results become
+/// ordinary assistant and user messages in the family's format
+pub fn render_messages(format: ToolFormat, messages: &[ChatMessage], tools: &[Tool]) -> Vec<ChatMessage> {
+    let mut rendered: Vec<ChatMessage> = Vec::with_capacity(messages.len() + 1);
+    for message in messages {
+        let content = match message.role {
+            Role::Assistant if !message.tool_calls.is_empty() => {
+                let calls = format.render_calls(&message.tool_calls);
+                [message.content.trim(), calls.as_str()].join("\n").trim().to_string()
+            }
+            Role::Tool => {
+                let name = message
+                    .tool_call_id
+                    .as_deref()
+                    .and_then(|id| messages.iter().flat_map(|m| &m.tool_calls).find(|call| call.id == id))
+                    .map_or("tool", |call| call.name.as_str());
+                format.render_result(name, &message.content)
+            }
+            _ => message.content.clone(),
+        };
+        let role = if message.role == Role::Tool { Role::User } else { message.role };
+        rendered.push(ChatMessage::new(role, content));
+    }
+
+    if !tools.is_empty() {
+        let description = format.render_tools(tools);
+        match rendered.iter_mut().find(|message| message.role == Role::System) {
+            Some(system) => system.content = format!("{}\n\n{}", system.content.trim_end(), description),
+            None => rendered.insert(0, ChatMessage::system(description)),
+        }
+    }
+    rendered
+}
+
+/// Chat with `tools` through any backend, prompting in `format` and parsing calls from the reply
+pub fn chat_with_format(
+    llm: &(impl LlmInterface + ?Sized),
+    format: ToolFormat,
+    messages: &[ChatMessage],
+    tools: &[Tool],
+) -> Result<ChatMessage, LlmError> {
+    let reply = llm.chat(&render_messages(format, messages, tools))?;
+    format.parse_reply(&reply, tools)
+}
+
+/// A call object as written by the model: `{"name": ..., "arguments"|"parameters": ...}`.
+/// Objects without either arguments key are not calls.
+fn call_from_json(value: &Value) -> Option<ToolCall> {
+    let name = value.get("name")?.as_str()?;
+    let arguments = match value.get("arguments").or_else(|| value.get("parameters"))? {
+        // Some models write the arguments as a JSON string
+        Value::String(text) => serde_json::from_str(text).ok()?,
+        arguments @ Value::Object(_) => arguments.clone(),
+        Value::Null => json!({}),
+        _ => return None,
+    };
+    Some(ToolCall::new(name, arguments))
+}
+
+/// `<tool_call>{...}</tool_call>` blocks; a block cut off at the end of the reply still counts
+fn parse_tagged_calls(text: &str) -> Result<(String, Vec<ToolCall>), LlmError> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"(?s)<tool_call>\s*(.*?)\s*(?:</tool_call>|\z)").unwrap());
+
+    let mut calls = Vec::new();
+    for captures in pattern.captures_iter(text) {
+        let call = serde_json::from_str(&captures[1])
+            .ok()
+            .as_ref()
+            .and_then(call_from_json)
+            .ok_or_else(|| LlmError::InvalidResponse(format!("unreadable tool call: {}", &captures[1])))?;
+        calls.push(call);
+    }
+    Ok((pattern.replace_all(text, "").trim().to_string(), calls))
+}
+
+/// A reply that consists only of call objects naming one of `tools`, separated
+/// by whitespace or `;`. Anything else is an ordinary answer, such as JSON the
+/// user asked for that happens to have a `name`.
+fn parse_json_calls(text: &str, tools: &[Tool]) -> (String, Vec<ToolCall>) {
+    let body = text.trim().trim_start_matches("<|python_tag|>").trim();
+    let body = body
+        .strip_prefix("```json")
+        .or_else(|| body.strip_prefix("```"))
+        .and_then(|inner| inner.trim_end().strip_suffix("```"))
+        .unwrap_or(body);
+
+    let mut calls = Vec::new();
+    for part in body.split(';').map(str::trim).filter(|part| !part.is_empty()) {
+        let values = serde_json::Deserializer::from_str(part).into_iter::<Value>();
+        for value in values {
+            match value.ok().as_ref().and_then(call_from_json) {
+                Some(call) if tools.iter().any(|tool| tool.name == call.name) => calls.push(call),
+                _ => return (text.trim().to_string(), Vec::new()),
+            }
+        }
+    }
+    if calls.is_empty() {
+        (text.trim().to_string(), calls)
+    } else {
+        (String::new(), calls)
+    }
+}
+
+/// ```` ```tool_code ```` blocks holding one `name(key=value, ...)` call per line
+fn parse_tool_code(text: &str) -> Result<(String, Vec<ToolCall>), LlmError> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"(?s)```tool_code\s*\n(.*?)(?:```|\z)").unwrap());
+
+    let mut calls = Vec::new();
+    for captures in pattern.captures_iter(text) {
+        for line in captures[1].lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let call = parse_python_call(line)
+                .ok_or_else(|| LlmError::InvalidResponse(format!("unreadable tool call: {}", line)))?;
+            calls.push(call);
+        }
+    }
+    Ok((pattern.replace_all(text, "").trim().to_string(), calls))
+}
+
+fn python_call(call: &ToolCall) -> String {
+    let arguments: Vec<String> = call
+        .arguments
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| format!("{}={}", name, python_literal(value)))
+        .collect();
+    format!("{}({})", call.name, arguments.join(", "))
+}
+
+fn python_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "None".to_string(),
+        Value::Bool(true) => "True".to_string(),
+        Value::Bool(false) => "False".to_string(),
+        Value::Array(items) => format!("[{}]", items.iter().map(python_literal).collect::<Vec<_>>().join(", ")),
+        Value::Object(object) => {
+            let entries: Vec<String> = object.iter().map(|(key, value)| format!("{}: {}", json!(key), python_literal(value))).collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        // JSON strings and numbers are valid Python literals
+        other => other.to_string(),
+    }
+}
+
+/// Parse `name(key=value, ...)`, where values are Python literals
+fn parse_python_call(line: &str) -> Option<ToolCall> {
+    let open = line.find('(')?;
+    let name = line[..open].trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-')) {
+        return None;
+    }
+
+    let mut parser = PythonLiteral { text: &line[open + 1..], pos: 0 };
+    let mut arguments = Map::new();
+    loop {
+        if parser.eat(')') {
+            break;
+        }
+        let key = parser.identifier()?;
+        if !parser.eat('=') {
+            return None;
+        }
+        arguments.insert(key, parser.value()?);
+        if !parser.eat(',') {
+            parser.eat(')').then_some(())?;
+            break;
+        }
+    }
+    parser.rest().trim().is_empty().then(|| ToolCall::new(name, Value::Object(arguments)))
+}
+
+/// A small reader for the Python literals models write in `tool_code` calls:
+/// strings, numbers, `True`/`False`/`None`, lists and dicts
+struct PythonLiteral<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl PythonLiteral<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.text.len() - self.rest().trim_start().len();
+    }
+
+    /// Consume `expected` after any whitespace
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(expected) {
+            self.pos += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> &str {
+        self.skip_whitespace();
+        let start = self.pos;
+        let length = self.rest().find(|c: char| !accept(c)).unwrap_or(self.rest().len());
+        self.pos += length;
+        &self.text[start..self.pos]
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        let identifier = self.take_while(|c| c.is_alphanumeric() || c == '_');
+        (!identifier.is_empty()).then(|| identifier.to_string())
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        let first = self.rest().chars().next()?;
+        match first {
+            '"' | '\'' => self.string(first).map(Value::String),
+            '[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                while !self.eat(']') {
+                    items.push(self.value()?);
+                    if !self.eat(',') {
+                        self.eat(']').then_some(())?;
+                        break;
+                    }
+                }
+                Some(Value::Array(items))
+            }
+            '{' => {
+                self.pos += 1;
+                let mut object = Map::new();
+                while !self.eat('}') {
+                    let Value::String(key) = self.value()? else {
+                        return None;
+                    };
+                    if !self.eat(':') {
+                        return None;
+                    }
+                    object.insert(key, self.value()?);
+                    if !self.eat(',') {
+                        self.eat('}').then_some(())?;
+                        break;
+                    }
+                }
+                Some(Value::Object(object))
+            }
+            _ => {
+                let word = self.take_while(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | '+' | '_'));
+                match word {
+                    "True" | "true" => Some(Value::Bool(true)),
+                    "False" | "false" => Some(Value::Bool(false)),
+                    "None" | "null" => Some(Value::Null),
+                    number => number
+                        .parse::<i64>()
+                        .map(Value::from)
+                        .ok()
+                        .or_else(|| number.parse::<f64>().ok().filter(|n| n.is_finite()).map(Value::from)),
+                }
+            }
+        }
+    }
+
+    /// A quoted string, with the usual backslash escapes
+    fn string(&mut self, quote: char) -> Option<String> {
+        let mut chars = self.rest().char_indices().skip(1);
+        let mut result = String::new();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    let (_, escaped) = chars.next()?;
+                    result.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        other => other,
+                    });
+                }
+                c if c == quote => {
+                    self.pos += index + c.len_utf8();
+                    return Some(result);
+                }
+                c => result.push(c),
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,269 @@
+use agentd::agent::{self, Agent, AgentDefinition, ApprovalAction, ApprovalPolicy, ApprovalRequest, ApprovalRule, DecidedBy, HttpLocalhost, ListDirectory, ReadFile, RunCommand, StopReason, Tool};
+use agentd::config;
+use agentd::testing::{MockBackend, MockReply};
+use serde_json::json;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+// Environment variables are process-wide
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn call(name: &str, arguments: serde_json::Value) -> String {
+    format!("<tool_call>\n{}\n</tool_call>", json!({"name": name, "arguments": arguments}))
+}
+
+#[test]
+fn test_agent_runs_tools_until_it_answers() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("project");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("notes.txt"), "ship on friday").unwrap();
+    fs::write(dir.path().join("secret.txt"), "hunter2").unwrap();
+
+    let mock = MockBackend::new().with_script([
+        MockReply::text(format!(
+            "{}\n{}",
+            call("list_directory", json!({})),
+            call("read_file", json!({"path": "notes.txt"}))
+        )),
+        MockReply::text(format!("{}\n{}", call("read_file", json!({"path": "../secret.txt"})), call("delete_file", json!({"path": "x"})))),
+        MockReply::text("<tool_call>{\"name\": </tool_call>"),
+        MockReply::text(call("read_file", json!({"file": "notes.txt"}))),
+        MockReply::text("We ship on Friday."),
+    ]);
+    let calls = mock.calls();
+    let agent = Agent::new(Box::new(mock)).with_tool(ReadFile::new([&root])).with_tool(ListDirectory::new([&root]));
+
+    let mut events = Vec::new();
+    let run = agent
+        .run_with_events("When do we ship?", &mut |event| events.push(format!("{:?}", event)))
+        .unwrap();
+    assert_eq!(run.stop_reason, StopReason::Answer);
+    assert_eq!(run.answer, "We ship on Friday.");
+    assert_eq!(run.steps, 5);
+    assert_eq!(events.len(), 10);
+
+    let outputs: Vec<(&str, bool)> = run.tool_calls.iter().map(|t| (t.output.as_str(), t.error)).collect();
+    assert_eq!(outputs[0], ("notes.txt\nsrc/", false));
+    assert_eq!(outputs[1], ("ship on friday", false));
+    assert!(outputs[2].1 && outputs[2].0.contains("outside the allowed directories"), "{:?}", outputs[2]);
+    assert!(outputs[3].1 && outputs[3].0.contains("Unknown tool 'delete_file'"), "{:?}", outputs[3]);
+    assert!(outputs[4].1 && outputs[4].0.contains("Invalid arguments"), "{:?}", outputs[4]);
+    assert!(run.usage.total_tokens > 0);
+
+    let calls = calls.lock().unwrap();
+    assert!(calls[0].prompt.contains("\"name\":\"read_file\""));
+    assert!(calls[1].prompt.contains("{\"content\":\"ship on friday\",\"name\":\"read_file\"}"), "{}", calls[1].prompt);
+    assert!(calls[3].prompt.contains("Your last tool call could not be read"));
+}
+
+#[test]
+fn test_agent_budgets_and_tool_allowlists() {
+    let looping = || MockBackend::new().with_response(call("list_directory", json!({})));
+    let dir = TempDir::new().unwrap();
+
+    let run = Agent::new(Box::new(looping()))
+        .with_tool(ListDirectory::new([dir.path()]))
+        .with_max_steps(3)
+        .run("Look around")
+        .unwrap();
+    assert_eq!((run.stop_reason, run.steps, run.answer.as_str()), (StopReason::MaxSteps, 3, ""));
+
+    let run = Agent::new(Box::new(looping()))
+        .with_tool(ListDirectory::new([dir.path()]))
+        .with_token_budget(1)
+        .run("Look around")
+        .unwrap();
+    assert_eq!((run.stop_reason, run.steps), (StopReason::TokenBudget, 1));
+
+    // Nothing is allowed unless listed
+    assert!(ReadFile::new(Vec::<&str>::new()).call(&json!({"path": "."})).is_err());
+
+    let commands = RunCommand::new(["echo"], dir.path());
+    assert_eq!(commands.parameters()["properties"]["command"]["enum"], json!(["echo"]));
+    let output = commands.call(&json!({"command": "echo", "args": ["hi; rm -rf /"]})).unwrap();
+    assert_eq!(output, "exit status: 0\nstdout:\nhi; rm -rf /\nstderr:\n");
+    assert!(commands.call(&json!({"command": "sh", "args": ["-c", "true"]})).is_err());
+
+    let http = HttpLocalhost::new([9]);
+    for url in ["http://example.com:9/", "http://localhost:8080/", "https://localhost:9/", "http://localhost:9@example.com/"] {
+        let error = http.call(&json!({"url": url})).unwrap_err();
+        assert!(error.to_string().contains("not an allowed URL"), "{}: {}", url, error);
+    }
+}
+
+#[test]
+fn test_agent_definitions_load_validate_and_run() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    let agents_dir = home.path().join("agents");
+    let docs = home.path().join("docs");
+    fs::create_dir_all(&agents_dir).unwrap();
+    fs::create_dir_all(&docs).unwrap();
+    fs::write(docs.join("notes.txt"), "all good").unwrap();
+    fs::write(
+        agents_dir.join("reviewer.toml"),
+        format!(
+            "description = \"Reviews notes\"\nsystem = \"You review notes.\"\nmax_steps = 4\ntemperature = 0.1\n\
+             output_schema = \"verdict.json\"\n\n[tools]\nread = [{:?}]\n",
+            docs.display().to_string()
+        ),
+    )
+    .unwrap();
+    let verdict = json!({"type": "object", "properties": {"ok": {"type": "boolean"}}, "required": ["ok"]});
+    fs::write(agents_dir.join("verdict.json"), verdict.to_string()).unwrap();
+    fs::write(agents_dir.join("broken.toml"), "modle = \"x\"\nmax_steps = 0\nmodel = \"nope\"\n[tools]\nshell = true\n").unwrap();
+
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("system"));
+    std::env::set_var("AGENTD_RUNTIME_DEFAULT_BACKEND", "mock");
+    let listed = agent::list_definitions();
+    let issues = config::validate_config();
+    let definition = agent::load_definition("reviewer").unwrap();
+    let mock = MockBackend::new().with_script([
+        MockReply::text(call("read_file", json!({"path": "notes.txt"}))),
+        MockReply::text("Looks fine to me."),
+        MockReply::text("{\"ok\": true}"),
+    ]);
+    let calls = mock.calls();
+    let run = definition.build(Box::new(mock)).unwrap().run("Review the notes").unwrap();
+    std::env::remove_var("AGENTD_HOME");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_RUNTIME_DEFAULT_BACKEND");
+
+    assert_eq!(listed.unwrap().keys().collect::<Vec<_>>(), ["broken", "reviewer"]);
+    let issues: Vec<String> = issues.unwrap().iter().filter(|issue| issue.source.contains("agents")).map(ToString::to_string).collect();
+    assert_eq!(issues.len(), 3, "{:?}", issues);
+    assert!(issues.iter().all(|issue| issue.contains("broken.toml")), "{:?}", issues);
+    assert!(issues.iter().any(|issue| issue.ends_with("unknown key 'modle'")));
+    assert!(issues.iter().any(|issue| issue.ends_with("unknown key 'tools.shell'")));
+    assert!(issues.iter().any(|issue| issue.ends_with("max_steps must be greater than 0")));
+
+    assert_eq!(definition.params.temperature, Some(0.1));
+    assert_eq!(definition.resolve_output_schema().unwrap(), Some(verdict));
+    assert_eq!((run.stop_reason, run.steps), (StopReason::Answer, 3));
+    assert_eq!(run.output, Some(json!({"ok": true})));
+    assert_eq!(run.tool_calls[0].output, "all good");
+    // Reads need no approval, but are still logged
+    let log = fs::read_to_string(home.path().join("logs").join("approvals.jsonl")).unwrap();
+    assert!(log.contains("\"decided_by\":\"read_only\""), "{}", log);
+
+    let calls = calls.lock().unwrap();
+    assert!(calls[0].prompt.starts_with("System: You review notes.\n\nWhen you are done, reply with only a JSON value"));
+    assert!(calls[2].prompt.contains("Your answer was rejected: "), "{}", calls[2].prompt);
+}
+
+#[test]
+fn test_tool_calls_follow_the_approval_policy() {
+    let dir = TempDir::new().unwrap();
+    let rule = |arguments: &str, action| ApprovalRule { tool: "run_*".to_string(), arguments: Some(arguments.to_string()), action };
+    let policy = ApprovalPolicy {
+        default: ApprovalAction::Ask,
+        rules: vec![rule("*rm *", ApprovalAction::Deny), rule("echo safe*", ApprovalAction::Allow)],
+        ..ApprovalPolicy::default()
+    };
+    let echo = |args: serde_json::Value| call("run_command", json!({"command": "echo", "args": args}));
+    let script = || {
+        MockBackend::new().with_script([
+            MockReply::text(echo(json!(["safe", "one"]))),
+            MockReply::text(echo(json!(["safe", "rm -rf /"]))),
+            MockReply::text(echo(json!(["hello world"]))),
+            MockReply::text("Done."),
+        ])
+    };
+
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let approver = {
+        let asked = asked.clone();
+        move |request: &ApprovalRequest| {
+            asked.lock().unwrap().push(format!("{}: {}", request.tool, request.summary));
+            false
+        }
+    };
+    let log = dir.path().join("logs").join("approvals.jsonl");
+    let run = Agent::new(Box::new(script()))
+        .with_tool(RunCommand::new(["echo"], dir.path()))
+        .with_approval_policy(policy.clone())
+        .with_approver(approver)
+        .with_approval_log(&log)
+        .run("Say hello")
+        .unwrap();
+
+    let decisions: Vec<(bool, DecidedBy)> = run
+        .tool_calls
+        .iter()
+        .map(|tool_run| tool_run.approval.as_ref().map(|a| (a.approved, a.decided_by)).unwrap())
+        .collect();
+    assert_eq!(decisions, [(true, DecidedBy::Rule), (false, DecidedBy::Rule), (false, DecidedBy::Approver)]);
+    assert!(run.tool_calls[0].output.contains("stdout:\nsafe one"));
+    assert_eq!(run.tool_calls[1].output, "Error: Invalid input: Not approved: the approval policy denies this call");
+    assert!(run.tool_calls[2].output.contains("the user declined this call"));
+    assert_eq!(*asked.lock().unwrap(), ["run_command: echo \"hello world\""]);
+
+    let lines: Vec<serde_json::Value> = fs::read_to_string(&log).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!((lines[1]["summary"].as_str(), lines[1]["approved"].as_bool()), (Some("echo safe \"rm -rf /\""), Some(false)));
+
+    // Without an approver, calls that need asking are refused
+    let run = Agent::new(Box::new(script()))
+        .with_tool(RunCommand::new(["echo"], dir.path()))
+        .with_approval_policy(policy)
+        .run("Say hello")
+        .unwrap();
+    assert!(run.tool_calls[2].output.contains("there is no one to ask"));
+    assert_eq!(run.answer, "Done.");
+}
+
+#[test]
+fn test_project_definitions_cannot_allow_calls() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    let project = TempDir::new().unwrap();
+    let definition = "[tools]\ncommands = [\"sh\"]\n\n[approval]\ndefault = \"allow\"\n\n\
+                      [[approval.rules]]\ntool = \"run_command\"\naction = \"allow\"\n\n\
+                      [[approval.rules]]\ntool = \"http_request\"\naction = \"deny\"\n";
+    let project_file = project.path().join(".agentd").join("agents").join("shell.toml");
+    let user_file = home.path().join("agents").join("shell.toml");
+    for file in [&project_file, &user_file] {
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, definition).unwrap();
+    }
+
+    std::env::set_var("AGENTD_HOME", home.path());
+    let from_project = AgentDefinition::from_file(&project_file).unwrap();
+    let from_user = AgentDefinition::from_file(&user_file).unwrap();
+    std::env::remove_var("AGENTD_HOME");
+
+    let actions = |policy: &ApprovalPolicy| -> Vec<ApprovalAction> {
+        std::iter::once(policy.default).chain(policy.rules.iter().map(|rule| rule.action)).collect()
+    };
+    assert_eq!(actions(&from_project.approval), [ApprovalAction::Ask, ApprovalAction::Ask, ApprovalAction::Deny]);
+    assert_eq!(actions(&from_user.approval), [ApprovalAction::Allow, ApprovalAction::Allow, ApprovalAction::Deny]);
+}
+
+#[test]
+fn test_project_definitions_ask_before_reading() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    let project = TempDir::new().unwrap();
+    let secrets = TempDir::new().unwrap();
+    fs::write(secrets.path().join("id_rsa"), "PRIVATE KEY").unwrap();
+    let file = project.path().join(".agentd").join("agents").join("snoop.toml");
+    fs::create_dir_all(file.parent().unwrap()).unwrap();
+    fs::write(&file, format!("[tools]\nread = [{:?}]\n", secrets.path().display().to_string())).unwrap();
+
+    std::env::set_var("AGENTD_HOME", home.path());
+    let definition = AgentDefinition::from_file(&file).unwrap();
+    let mock = MockBackend::new().with_script([
+        MockReply::text(call("read_file", json!({"path": "id_rsa"}))),
+        MockReply::text("Nothing to see."),
+    ]);
+    let run = definition.build(Box::new(mock)).unwrap().run("Read the key").unwrap();
+    std::env::remove_var("AGENTD_HOME");
+
+    let approval = run.tool_calls[0].approval.as_ref().unwrap();
+    assert_eq!((approval.approved, approval.decided_by), (false, DecidedBy::Default));
+    assert!(!run.tool_calls[0].output.contains("PRIVATE KEY"));
+    assert_eq!(definition.approval.action("read_file", "id_rsa", true).0, ApprovalAction::Ask);
+}
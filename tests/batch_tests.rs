@@ -0,0 +1,94 @@
+use agentd::batch::{compact_results, read_requests, run_batch, BatchResult};
+use agentd::llm::{GenerationParams, LlmConfig};
+use agentd::{ChatMessage, LlmError, LlmInterface};
+use std::fs;
+use tempfile::TempDir;
+
+// Echoes the prompt, or the number of chat messages, and the requested temperature
+struct EchoBackend {
+    config: LlmConfig,
+}
+
+impl LlmInterface for EchoBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        self.generate_with(prompt, &GenerationParams::default())
+    }
+
+    fn generate_with(&self, prompt: &str, params: &GenerationParams) -> Result<String, LlmError> {
+        if prompt == "fail" {
+            return Err(LlmError::EmptyResponse);
+        }
+        Ok(format!("{} @ {:?}", prompt, params.temperature))
+    }
+
+    fn chat_with(&self, messages: &[ChatMessage], params: &GenerationParams) -> Result<String, LlmError> {
+        Ok(format!("{} messages @ {:?}", messages.len(), params.temperature))
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(self: Box<Self>, _args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self
+    }
+}
+
+#[test]
+fn test_batch_runs_prompts_and_messages_in_parallel() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input.jsonl");
+    fs::write(
+        &input,
+        concat!(
+            "{\"id\": \"a\", \"prompt\": \"hello\", \"temperature\": 0.0}\n",
+            "\n",
+            "{\"messages\": [{\"role\": \"user\", \"content\": \"hi\"}]}\n",
+            "{\"id\": \"c\", \"prompt\": \"fail\"}\n",
+        ),
+    )
+    .unwrap();
+
+    let requests = read_requests(&input).unwrap();
+    assert_eq!(requests[1].id.as_deref(), Some("line-3"));
+
+    let llm = EchoBackend { config: LlmConfig::new("none", "none") };
+    let mut results: Vec<BatchResult> = Vec::new();
+    run_batch(&llm, requests, 4, |result| {
+        results.push(result);
+        Ok(())
+    })
+    .unwrap();
+
+    results.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(results[0].text.as_deref(), Some("hello @ Some(0.0)"));
+    assert!(results[0].usage.total_tokens > 0);
+    assert!(results[1].error.is_some());
+    assert_eq!(results[2].text.as_deref(), Some("1 messages @ None"));
+}
+
+#[test]
+fn test_compact_results_keeps_one_success_per_id() {
+    let dir = TempDir::new().unwrap();
+    let output = dir.path().join("output.jsonl");
+
+    // Resuming rewrites the file, so rerun requests don't repeat an id
+    let ok = |id: &str, text: &str| {
+        format!("{{\"id\":\"{}\",\"text\":\"{}\",\"usage\":{{\"prompt_tokens\":1,\"completion_tokens\":1,\"total_tokens\":2}},\"duration_ms\":3}}\n", id, text)
+    };
+    let failed = "{\"id\":\"b\",\"error\":\"boom\",\"usage\":{\"prompt_tokens\":0,\"completion_tokens\":0,\"total_tokens\":0},\"duration_ms\":3}\n";
+    fs::write(&output, format!("{}{}{}{{\"id\":\"c\",\"te", ok("a", "first"), failed, ok("a", "again"))).unwrap();
+    let done = compact_results(&output).unwrap();
+    assert_eq!(done.len(), 1);
+    assert!(done.contains("a"));
+    assert_eq!(fs::read_to_string(&output).unwrap(), ok("a", "first"));
+}
+
+#[test]
+fn test_batch_rejects_duplicate_ids() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input.jsonl");
+    fs::write(&input, "{\"id\": \"a\", \"prompt\": \"x\"}\n{\"id\": \"a\", \"prompt\": \"y\"}\n").unwrap();
+
+    assert!(matches!(read_requests(&input), Err(LlmError::InvalidInput(_))));
+}
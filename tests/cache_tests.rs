@@ -0,0 +1,119 @@
+use agentd::llm::backends::CachedBackend;
+use agentd::cache::{is_deterministic, PromptCache, ResponseCache};
+use agentd::llm::LlmConfig;
+use agentd::chat::render_transcript;
+use agentd::{ChatMessage, LlmError, LlmInterface};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+struct CountingBackend {
+    config: LlmConfig,
+    calls: Arc<AtomicUsize>,
+}
+
+impl LlmInterface for CountingBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        let n = self.calls.fetch_add(1, Ordering::SeqCst);
+        Ok(format!("{} #{}", prompt, n))
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(mut self: Box<Self>, args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self.config.additional_args = args;
+        self
+    }
+}
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_prompt_cache_key_shared_prefix() {
+    let dir = TempDir::new().unwrap();
+    let cache = PromptCache::new(dir.path(), 1024, 8192);
+    // About 2k tokens of instructions, longer than the hashed prefix
+    let preamble = "You are a careful assistant who answers questions about our codebase. ".repeat(120);
+    let transcript = |system: &str, question: &str| {
+        render_transcript(&[ChatMessage::system(system), ChatMessage::user(question)])
+    };
+
+    let a = cache.key("model.gguf", &transcript(&preamble, "Question one"));
+    let b = cache.key("model.gguf", &transcript(&preamble, "Question two"));
+    let other_model = cache.key("other.gguf", &transcript(&preamble, "Question one"));
+    let other_preamble = cache.key("model.gguf", &transcript("You are terse.", "Question one"));
+
+    assert_eq!(a, b);
+    assert_ne!(a, other_model);
+    assert_ne!(a, other_preamble);
+    // Plain prompts share one file per model and rely on llama.cpp's prefix matching
+    assert_eq!(cache.key("model.gguf", "Summarize this"), cache.key("model.gguf", "Translate that"));
+}
+
+#[test]
+fn test_prompt_cache_evicts_least_recently_used() {
+    let dir = TempDir::new().unwrap();
+    let cache = PromptCache::new(dir.path(), 250, 16);
+
+    let old = cache.path_for("old.gguf", "first prompt").unwrap();
+    let new = cache.path_for("new.gguf", "second prompt").unwrap();
+    fs::write(&old, vec![0u8; 200]).unwrap();
+    fs::File::options()
+        .append(true)
+        .open(&old)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(60))
+        .unwrap();
+    fs::write(&new, vec![0u8; 200]).unwrap();
+
+    cache.record_use(&new).unwrap();
+
+    assert!(!old.exists());
+    assert!(new.exists());
+    assert_eq!(cache.stats().unwrap().entries, 1);
+}
+
+#[test]
+fn test_is_deterministic() {
+    assert!(is_deterministic(&args(&["--temp", "0"])));
+    assert!(is_deterministic(&args(&["--temp", "0.7", "--seed", "42"])));
+    assert!(!is_deterministic(&args(&["--temp", "0.7"])));
+    assert!(!is_deterministic(&args(&["--temp", "0", "--temp", "0.7"])));
+    assert!(!is_deterministic(&args(&["--seed", "-1"])));
+}
+
+#[test]
+fn test_cached_backend_reuses_deterministic_responses() {
+    let dir = TempDir::new().unwrap();
+    let model = dir.path().join("model.gguf");
+    fs::write(&model, b"GGUF test weights").unwrap();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let inner = Box::new(CountingBackend {
+        config: LlmConfig::new("llama-cli", model.to_string_lossy()),
+        calls: calls.clone(),
+    });
+    let cache = ResponseCache::new(dir.path().join("responses"), Duration::from_secs(60), 1024 * 1024);
+    let llm: Box<dyn LlmInterface + Send + Sync> = Box::new(CachedBackend::new(inner, cache.clone()));
+
+    // Sampling requests always reach the model
+    let llm = llm.with_args(args(&["--temp", "0.8"]));
+    assert_ne!(llm.generate("hi").unwrap(), llm.generate("hi").unwrap());
+    assert_eq!(cache.stats().unwrap().entries, 0);
+
+    let llm = llm.with_args(args(&["--temp", "0"]));
+    let first = llm.generate("hi").unwrap();
+    assert_eq!(llm.generate("hi").unwrap(), first);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    assert_eq!(cache.stats().unwrap().entries, 1);
+
+    // Different params are a different key
+    let llm = llm.with_args(args(&["--temp", "0", "--n-predict", "5"]));
+    assert_ne!(llm.generate("hi").unwrap(), first);
+}
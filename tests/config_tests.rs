@@ -0,0 +1,159 @@
+use agentd::config::{self, ConfigOrigin};
+use std::fs;
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+// Environment variables and the working directory are process-wide
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_agentd_home_controls_all_paths() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+
+    assert_eq!(config::get_agentd_home(), home.path());
+    assert_eq!(config::get_config_path(), home.path().join("config").join("config.toml"));
+    assert_eq!(config::get_models_dir(), home.path().join("models"));
+    assert_eq!(config::get_sessions_dir(), home.path().join("sessions"));
+
+    std::env::remove_var("AGENTD_HOME");
+}
+
+#[test]
+fn test_layers_merge_in_precedence_order() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    let project = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+
+    fs::create_dir_all(home.path().join("config")).unwrap();
+    fs::write(
+        home.path().join("config").join("config.toml"),
+        "[defaults]\ntemperature = 0.2\nmax_tokens = 64\n",
+    )
+    .unwrap();
+    fs::write(project.path().join(config::PROJECT_CONFIG_FILE), "[defaults]\nmax_tokens = 128\n").unwrap();
+    std::env::set_var("AGENTD_CACHE_RESPONSE_CACHE", "false");
+
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(project.path()).unwrap();
+    let layered = config::load_layered_config();
+    std::env::set_current_dir(original_dir).unwrap();
+    std::env::remove_var("AGENTD_CACHE_RESPONSE_CACHE");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+
+    let layered = layered.unwrap();
+    assert_eq!(layered.config.defaults.temperature, 0.2);
+    assert_eq!(layered.config.defaults.max_tokens, 128);
+    assert!(!layered.config.cache.response_cache);
+
+    assert!(matches!(layered.origins["defaults.temperature"], ConfigOrigin::User(_)));
+    assert!(matches!(layered.origins["defaults.max_tokens"], ConfigOrigin::Project(_)));
+    assert_eq!(
+        layered.origins["cache.response_cache"],
+        ConfigOrigin::Env("AGENTD_CACHE_RESPONSE_CACHE".to_string())
+    );
+    assert_eq!(layered.origins["defaults.top_p"], ConfigOrigin::Default);
+}
+
+#[test]
+fn test_invalid_env_value_is_a_config_error() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_DEFAULTS_MAX_TOKENS", "lots");
+
+    let result = config::load_layered_config();
+    std::env::remove_var("AGENTD_DEFAULTS_MAX_TOKENS");
+    std::env::remove_var("AGENTD_HOME");
+
+    assert!(matches!(result, Err(agentd::LlmError::Config(_))));
+}
+
+#[test]
+fn test_set_keeps_comments_and_rejects_bad_values() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "# my settings\n[defaults]\ntemperature = 0.7 # warm\n").unwrap();
+
+    config::set_config_value(&path, "defaults.temperature", "0.2", false).unwrap();
+    config::set_config_value(&path, "cache.response_cache", "false", false).unwrap();
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.starts_with("# my settings\n"));
+    assert!(content.contains("temperature = 0.2 # warm"));
+    assert!(content.contains("[cache]\nresponse_cache = false"));
+
+    assert!(config::set_config_value(&path, "defaults.top_p", "1.5", false).is_err());
+    assert!(config::set_config_value(&path, "defaults.temprature", "0.5", false).is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), content);
+}
+
+#[test]
+fn test_init_is_idempotent() {
+    let dir = TempDir::new().unwrap();
+    let written = config::init_config(dir.path(), false).unwrap();
+    assert!(written.iter().all(|(_, created)| *created));
+
+    fs::write(dir.path().join("config.toml"), "[defaults]\nmax_tokens = 32\n").unwrap();
+    let written = config::init_config(dir.path(), false).unwrap();
+    assert!(written.iter().all(|(_, created)| !*created));
+    assert!(fs::read_to_string(dir.path().join("config.toml")).unwrap().contains("max_tokens = 32"));
+}
+
+#[test]
+fn test_validate_reports_unknown_keys_and_missing_models() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+
+    let config_dir = home.path().join("config");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(config_dir.join("config.toml"), "[defaults]\ntemperture = 0.2\n").unwrap();
+    let with_typo = config::validate_config().unwrap();
+
+    fs::write(config_dir.join("config.toml"), "[runtime]\nllama_executable = \"/bin/sh\"\n").unwrap();
+    fs::write(config_dir.join("models.toml"), "[ghost]\nfile = \"ghost.gguf\"\n").unwrap();
+    let missing_model = config::validate_config().unwrap();
+
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+
+    assert_eq!(with_typo.len(), 1);
+    assert!(with_typo[0].message.contains("defaults.temperture"));
+    assert_eq!(missing_model.len(), 1);
+    assert!(missing_model[0].message.contains("ghost"));
+}
+
+#[test]
+fn test_xdg_dirs_are_chosen_per_variable() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    let xdg_config = home.path().join("xdg-config");
+    let old_home = std::env::var_os("HOME");
+    std::env::remove_var("AGENTD_HOME");
+    std::env::remove_var("XDG_DATA_HOME");
+    std::env::remove_var("XDG_CACHE_HOME");
+    std::env::set_var("HOME", home.path());
+    std::env::set_var("XDG_CONFIG_HOME", &xdg_config);
+
+    assert_eq!(config::get_config_dir(), xdg_config.join("agentd"));
+    assert_eq!(config::get_agentd_home(), home.path().join(".agentd"));
+
+    // Writing data creates ~/.agentd, which must not move the config
+    fs::create_dir_all(config::get_sessions_dir()).unwrap();
+    assert_eq!(config::get_config_dir(), xdg_config.join("agentd"));
+
+    // An older install's config directory still wins
+    fs::create_dir_all(home.path().join(".agentd").join("config")).unwrap();
+    assert_eq!(config::get_config_dir(), home.path().join(".agentd").join("config"));
+
+    std::env::remove_var("XDG_CONFIG_HOME");
+    match old_home {
+        Some(value) => std::env::set_var("HOME", value),
+        None => std::env::remove_var("HOME"),
+    }
+}
@@ -0,0 +1,168 @@
+use agentd::config::DiscoveryConfig;
+use agentd::discovery;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+// AGENTD_MODELS_DIR is process-wide
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn touch(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, b"GGUF").unwrap();
+}
+
+#[test]
+fn test_scan_groups_shards_and_attaches_mmproj() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let models = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_MODELS_DIR", models.path());
+
+    touch(&models.path().join("plain.gguf"));
+    touch(&models.path().join("big/big-00001-of-00002.gguf"));
+    touch(&models.path().join("big/big-00002-of-00002.gguf"));
+    touch(&models.path().join("partial/half-00001-of-00003.gguf"));
+    touch(&models.path().join("vision/llava.gguf"));
+    touch(&models.path().join("vision/mmproj-llava-f16.gguf"));
+    touch(&models.path().join("vision/gemma-3-4b-it-Q4_K_M.gguf"));
+    touch(&models.path().join("vision/gemma-3-4b-it-mmproj-f16.gguf"));
+    touch(&models.path().join("vision/phi.gguf"));
+    touch(&models.path().join("solo/moondream.gguf"));
+    touch(&models.path().join("solo/mmproj-f16.gguf"));
+
+    let flat = discovery::scan(&DiscoveryConfig { recursive: false, ..DiscoveryConfig::default() });
+    let found = discovery::scan(&DiscoveryConfig::default());
+    std::env::remove_var("AGENTD_MODELS_DIR");
+
+    assert_eq!(flat.unwrap().models.len(), 1);
+
+    let found = found.unwrap();
+    let mut names: Vec<_> = found.models.keys().cloned().collect();
+    names.sort();
+    assert_eq!(names, ["big", "gemma-3-4b-it-Q4_K_M", "llava", "moondream", "phi", "plain"]);
+    assert_eq!(found.models["big"].file, Path::new("big").join("big-00001-of-00002.gguf").to_string_lossy());
+    assert_eq!(
+        found.models["llava"].mmproj.as_deref(),
+        Some(Path::new("vision").join("mmproj-llava-f16.gguf").to_string_lossy().as_ref())
+    );
+    // Each model gets the mmproj named after it; a lone model takes a lone mmproj
+    assert_eq!(
+        found.models["gemma-3-4b-it-Q4_K_M"].mmproj.as_deref(),
+        Some(Path::new("vision").join("gemma-3-4b-it-mmproj-f16.gguf").to_string_lossy().as_ref())
+    );
+    assert_eq!(found.models["phi"].mmproj, None);
+    assert_eq!(
+        found.models["moondream"].mmproj.as_deref(),
+        Some(Path::new("solo").join("mmproj-f16.gguf").to_string_lossy().as_ref())
+    );
+    assert!(found.warnings.iter().any(|warning| warning.contains("'half'")));
+}
+
+#[test]
+fn test_scan_prefers_earlier_roots_and_warns_on_collisions() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let models = TempDir::new().unwrap();
+    let shared = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_MODELS_DIR", models.path());
+
+    touch(&models.path().join("qwen.gguf"));
+    touch(&shared.path().join("qwen.gguf"));
+    touch(&shared.path().join("nested/mistral.gguf"));
+
+    let settings = DiscoveryConfig {
+        roots: vec![shared.path().to_path_buf()],
+        ..DiscoveryConfig::default()
+    };
+    let found = discovery::scan(&settings);
+    std::env::remove_var("AGENTD_MODELS_DIR");
+
+    let found = found.unwrap();
+    assert_eq!(found.models["qwen"].file, "qwen.gguf");
+    assert_eq!(
+        Path::new(&found.models["mistral"].file),
+        shared.path().join("nested").join("mistral.gguf")
+    );
+    assert_eq!(found.warnings.len(), 1);
+    assert!(found.warnings[0].contains("'qwen'"));
+}
+
+#[test]
+fn test_scan_indexes_ollama_and_huggingface_caches_in_place() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let models = TempDir::new().unwrap();
+    let ollama = TempDir::new().unwrap();
+    let hub = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_MODELS_DIR", models.path());
+    std::env::set_var("OLLAMA_MODELS", ollama.path());
+    std::env::set_var("HF_HUB_CACHE", hub.path());
+
+    let manifest = r#"{"layers": [
+        {"mediaType": "application/vnd.ollama.image.model", "digest": "sha256:abc"},
+        {"mediaType": "application/vnd.ollama.image.template", "digest": "sha256:def"}
+    ]}"#;
+    let manifest_path = ollama.path().join("manifests/registry.ollama.ai/library/llama3.2/3b");
+    fs::create_dir_all(manifest_path.parent().unwrap()).unwrap();
+    fs::write(&manifest_path, manifest).unwrap();
+    touch(&ollama.path().join("blobs/sha256-abc"));
+
+    let repo = hub.path().join("models--acme--tiny-GGUF");
+    fs::create_dir_all(repo.join("refs")).unwrap();
+    fs::write(repo.join("refs/main"), "rev2").unwrap();
+    touch(&repo.join("snapshots/rev1/tiny-q4.gguf"));
+    touch(&repo.join("snapshots/rev2/tiny-q8.gguf"));
+
+    let disabled = discovery::scan(&DiscoveryConfig::default());
+    let settings = DiscoveryConfig {
+        ollama: true,
+        huggingface: true,
+        ..DiscoveryConfig::default()
+    };
+    let found = discovery::scan(&settings);
+    std::env::remove_var("HF_HUB_CACHE");
+    std::env::remove_var("OLLAMA_MODELS");
+    std::env::remove_var("AGENTD_MODELS_DIR");
+
+    assert!(disabled.unwrap().models.is_empty());
+
+    let found = found.unwrap();
+    let mut names: Vec<_> = found.models.keys().cloned().collect();
+    names.sort();
+    assert_eq!(names, ["llama3.2:3b", "tiny-q8"]);
+    assert_eq!(
+        found.models["llama3.2:3b"].file,
+        ollama.path().join("blobs/sha256-abc").to_string_lossy()
+    );
+    assert_eq!(
+        found.models["tiny-q8"].file,
+        repo.join("snapshots/rev2/tiny-q8.gguf").to_string_lossy()
+    );
+    assert!(fs::read_dir(models.path()).unwrap().next().is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scan_skips_unreadable_directories() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let models = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_MODELS_DIR", models.path());
+    touch(&models.path().join("readable.gguf"));
+    let locked = models.path().join("locked");
+    touch(&locked.join("hidden.gguf"));
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let found = discovery::scan(&DiscoveryConfig::default());
+    // Root can read the directory anyway
+    let unreadable = fs::read_dir(&locked).is_err();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    std::env::remove_var("AGENTD_MODELS_DIR");
+
+    let found = found.unwrap();
+    assert!(found.models.contains_key("readable"));
+    if unreadable {
+        assert!(!found.models.contains_key("hidden"));
+        assert!(found.warnings.iter().any(|warning| warning.contains("locked")), "{:?}", found.warnings);
+    }
+}
@@ -0,0 +1,128 @@
+use agentd::eval::{load_suite, run_suite, Baseline};
+use agentd::llm::LlmConfig;
+use agentd::schema::{extract_json, validate};
+use agentd::{LlmError, LlmInterface};
+use serde_json::json;
+use std::fs;
+use tempfile::TempDir;
+
+// Answers from a fixed table keyed by prompt; anything else is graded PASS
+struct TableBackend {
+    config: LlmConfig,
+    answers: Vec<(&'static str, &'static str)>,
+}
+
+impl LlmInterface for TableBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        Ok(self
+            .answers
+            .iter()
+            .find(|(p, _)| *p == prompt)
+            .map(|(_, a)| a.to_string())
+            .unwrap_or_else(|| "PASS - looks right".to_string()))
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(self: Box<Self>, _args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self
+    }
+}
+
+const SUITE: &str = r#"
+models = ["good", "bad"]
+
+[[cases]]
+name = "capital"
+prompt = "Capital of France?"
+assert = [
+    { type = "contains", value = "paris" },
+    { type = "regex", pattern = "^The" },
+]
+
+[[cases]]
+name = "math"
+prompt = "2 + 2?"
+assert = [{ type = "numeric", expected = 4.0, tolerance = 0.1 }]
+
+[[cases]]
+name = "json"
+prompt = "Give me a person"
+assert = [
+    { type = "json_schema", schema = { type = "object", required = ["name"], properties = { name = { type = "string" } } } },
+    { type = "llm_judge", criteria = "Mentions a person" },
+]
+"#;
+
+fn open_model(name: &str) -> Result<Box<dyn LlmInterface + Send + Sync>, LlmError> {
+    let answers = match name {
+        "good" => vec![
+            ("Capital of France?", "The capital is Paris."),
+            ("2 + 2?", "It is 4."),
+            ("Give me a person", "```json\n{\"name\": \"Ada\"}\n```"),
+        ],
+        "bad" => vec![
+            ("Capital of France?", "Lyon"),
+            ("2 + 2?", "five"),
+            ("Give me a person", "{\"name\": 3}"),
+        ],
+        _ => return Err(LlmError::InvalidModelPath(name.to_string())),
+    };
+    Ok(Box::new(TableBackend { config: LlmConfig::new("none", "none"), answers }))
+}
+
+#[test]
+fn test_eval_suite_pass_rates_and_regressions() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("suite.toml");
+    fs::write(&path, SUITE).unwrap();
+
+    let suite = load_suite(&path).unwrap();
+    let report = run_suite(&suite, open_model).unwrap();
+
+    let rates = report.pass_rates();
+    assert_eq!(rates["good"], (3, 3));
+    assert_eq!(rates["bad"], (0, 3));
+
+    let mut baseline = Baseline::new();
+    baseline.insert("bad::math".to_string(), true);
+    baseline.insert("good::math".to_string(), true);
+    let regressions = report.regressions(&baseline);
+    assert_eq!(regressions.len(), 1);
+    assert_eq!(regressions[0].key(), "bad::math");
+}
+
+#[test]
+fn test_eval_suite_rejects_invalid_regex() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("suite.toml");
+    fs::write(
+        &path,
+        "models = [\"m\"]\n[[cases]]\nname = \"x\"\nprompt = \"y\"\nassert = [{ type = \"regex\", pattern = \"(\" }]\n",
+    )
+    .unwrap();
+
+    assert!(matches!(load_suite(&path), Err(LlmError::InvalidInput(_))));
+}
+
+#[test]
+fn test_schema_validation() {
+    let schema = json!({
+        "type": "object",
+        "required": ["items"],
+        "additionalProperties": false,
+        "properties": {
+            "items": { "type": "array", "minItems": 1, "items": { "type": "integer", "minimum": 0 } },
+            "kind": { "enum": ["a", "b"] }
+        }
+    });
+
+    assert!(validate(&json!({"items": [1, 2]}), &schema).is_ok());
+
+    let errors = validate(&json!({"items": [-1], "kind": "c", "extra": true}), &schema).unwrap_err();
+    assert_eq!(errors.len(), 3);
+
+    assert_eq!(extract_json("Sure! {\"a\": 1} Hope that helps").unwrap(), json!({"a": 1}));
+}
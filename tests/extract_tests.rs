@@ -0,0 +1,121 @@
+use agentd::extract::{Extract, LlmExtract};
+use agentd::testing::{MockBackend, MockReply};
+use agentd::LlmError;
+use serde_json::json;
+
+/// An invoice as printed on paper
+#[derive(Debug, PartialEq, LlmExtract)]
+#[llm(instructions = "Read the invoice below.")]
+struct Invoice {
+    /// Invoice number, as printed
+    number: String,
+    #[llm(rename = "total_cents")]
+    total: u64,
+    status: Status,
+    lines: Vec<Line>,
+    due: Option<String>,
+}
+
+#[derive(Debug, PartialEq, LlmExtract)]
+struct Line {
+    item: String,
+    quantity: u32,
+}
+
+#[derive(Debug, PartialEq, LlmExtract)]
+enum Status {
+    Paid,
+    #[llm(rename = "open")]
+    Unpaid,
+}
+
+#[test]
+fn test_derived_schema_and_parsing() {
+    let schema = Invoice::json_schema();
+    assert_eq!(schema["description"], "An invoice as printed on paper");
+    assert_eq!(schema["required"], json!(["number", "total_cents", "status", "lines", "due"]));
+    assert_eq!(schema["additionalProperties"], false);
+    assert_eq!(schema["properties"]["number"]["description"], "Invoice number, as printed");
+    assert_eq!(schema["properties"]["total_cents"]["minimum"], 0);
+    assert_eq!(schema["properties"]["status"]["enum"], json!(["Paid", "open"]));
+    assert_eq!(schema["properties"]["lines"]["items"]["properties"]["quantity"]["type"], "integer");
+    assert!(Invoice::prompt("INV-1").starts_with("Read the invoice below.\n"));
+
+    let invoice = Invoice::from_json(&json!({
+        "number": "INV-1",
+        "total_cents": 1250,
+        "status": "open",
+        "lines": [{"item": "tea", "quantity": 2}],
+        "due": null
+    }))
+    .unwrap();
+    assert_eq!(invoice.status, Status::Unpaid);
+    assert_eq!(invoice.lines, [Line { item: "tea".to_string(), quantity: 2 }]);
+    assert_eq!(invoice.due, None);
+
+    let error = Line::from_json(&json!({"item": "tea", "quantity": -1})).unwrap_err();
+    assert!(error.starts_with("quantity: "), "{}", error);
+}
+
+#[test]
+fn test_extract_retries_until_the_reply_validates() {
+    let valid = r#"{"item": "tea", "quantity": 2}"#;
+    let mock = MockBackend::new().with_script([
+        MockReply::text("Sure! Here is the line item."),
+        MockReply::text(r#"{"item": "tea", "quantity": "two"}"#),
+        MockReply::text(valid),
+    ]);
+    let calls = mock.calls();
+
+    let line: Line = mock.extract("2x tea").unwrap();
+    assert_eq!(line, Line { item: "tea".to_string(), quantity: 2 });
+
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(calls[0].params.json_schema, Some(Line::json_schema()));
+    assert!(calls[0].prompt.ends_with("Text:\n2x tea"));
+    assert!(calls[2].prompt.contains("rejected: $.quantity: expected integer"), "{}", calls[2].prompt);
+    drop(calls);
+
+    let stubborn = MockBackend::new().with_response("no JSON here");
+    let result = stubborn.extract_with_retries::<Line>("2x tea", 1);
+    assert!(matches!(result, Err(LlmError::InvalidResponse(_))));
+    assert_eq!(stubborn.calls().lock().unwrap().len(), 2);
+
+    // Without a scripted reply the mock answers with a conforming example
+    let boxed: Box<dyn agentd::LlmInterface + Send + Sync> = Box::new(MockBackend::new());
+    let status: Status = boxed.extract("It was paid").unwrap();
+    assert_eq!(status, Status::Paid);
+}
+
+/// A section of an outline
+#[derive(Debug, PartialEq, LlmExtract)]
+struct Section {
+    title: String,
+    children: Vec<Section>,
+    parent: Option<Box<Section>>,
+}
+
+#[test]
+fn test_recursive_types_use_defs_and_refs() {
+    let schema = Section::json_schema();
+    assert_eq!(schema["properties"]["children"]["items"], json!({"$ref": "#/$defs/Section"}));
+    assert_eq!(schema["$defs"]["Section"]["properties"]["title"]["type"], "string");
+    // Nested in a list, the definitions still live at the root
+    let list = Vec::<Section>::json_schema();
+    assert_eq!(list["items"]["$ref"], "#/$defs/Section");
+    assert!(list["$defs"]["Section"].is_object());
+    assert!(Line::json_schema().get("$defs").is_none());
+
+    let outline = json!({"title": "a", "parent": null, "children": [{"title": "b", "parent": null, "children": []}]});
+    assert!(agentd::schema::validate(&outline, &schema).is_ok());
+    let errors = agentd::schema::validate(&json!({"title": "a", "parent": null, "children": [{"title": 1}]}), &schema).unwrap_err();
+    assert!(errors.iter().any(|e| e.starts_with("$.children[0].title: expected string")), "{:?}", errors);
+    // Examples stop where a definition refers back to itself
+    let example = agentd::schema::example(&schema);
+    assert_eq!(example["parent"], json!({"title": "", "children": [], "parent": null}));
+
+    let llm = MockBackend::new().with_response(outline.to_string());
+    let section: Section = llm.extract("an outline").unwrap();
+    assert_eq!(section.children[0].title, "b");
+}
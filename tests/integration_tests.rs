@@ -1,26 +1,39 @@
-use agentd::{open, LlmError};
-use std::io::Write;
-use tempfile::NamedTempFile;
+use agentd::llm::backends::LlamaCppBackend;
+use agentd::llm::LlmConfig;
+use agentd::testing::{FakeLlamaCli, MockBackend, MockReply};
+use agentd::{config, open, LlmError, LlmInterface};
 use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tempfile::TempDir;
 
-// Helper to create a dummy config file with a specific model name
+// Tests below share one AGENTD_HOME, so they run one at a time
+static AGENTD_HOME_LOCK: Mutex<()> = Mutex::new(());
+
+// Points AGENTD_HOME at a temporary directory for the whole test binary
+fn test_home() -> &'static Path {
+    static HOME: OnceLock<TempDir> = OnceLock::new();
+    HOME.get_or_init(|| {
+        let dir = TempDir::new().unwrap();
+        std::env::set_var("AGENTD_HOME", dir.path());
+        std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", dir.path().join("etc"));
+        dir
+    })
+    .path()
+}
+
+// Helper to register a placeholder model file under a model name
 fn setup_with_model_name(model_name: &str) {
-    let config_dir = dirs::home_dir().unwrap().join(".agentd/config");
+    let home = test_home();
+    let config_dir = home.join("config");
+    let models_dir = home.join("models");
     fs::create_dir_all(&config_dir).unwrap();
-    let models_toml = config_dir.join("models.toml");
-    let mut file = fs::File::create(models_toml).unwrap();
-    file.write_all(format!("[{}]\nfile = \"gemma-3-12B-it-QAT-Q4_0.gguf\"\n", model_name).as_bytes()).unwrap();
-
-    let model_dir = dirs::home_dir().unwrap().join(".agentd/models");
-    fs::create_dir_all(&model_dir).unwrap();
-    // Copy the actual model file to the test location
-    let src_model = "/Users/stevenwalsh/repositories/agentd/models/gemma-3-12B-it-QAT-Q4_0.gguf";
-    let dst_model = model_dir.join("gemma-3-12B-it-QAT-Q4_0.gguf");
-    if std::path::Path::new(src_model).exists() {
-        fs::copy(src_model, dst_model).unwrap();
-    } else {
-        panic!("Source model file not found: {}", src_model);
-    }
+    fs::create_dir_all(&models_dir).unwrap();
+
+    let file_name = format!("{}.gguf", model_name);
+    fs::write(config_dir.join("models.toml"), format!("[{}]\nfile = \"{}\"\n", model_name, file_name)).unwrap();
+    fs::write(models_dir.join(file_name), b"GGUF").unwrap();
 }
 
 // Helper to create a dummy config file
@@ -30,27 +43,14 @@ fn setup() {
 
 // Helper to clean up created files
 fn cleanup() {
-    let agentd_dir = dirs::home_dir().unwrap().join(".agentd");
-    if agentd_dir.exists() {
-        // Use ignore errors in case directories don't exist or are already cleaned
-        let _ = fs::remove_dir_all(agentd_dir);
-    }
-}
-
-fn create_mock_executable() -> std::io::Result<NamedTempFile> {
-    let mut file = NamedTempFile::new()?;
-    writeln!(file, "#!/bin/sh")?;
-    writeln!(file, "echo Mock LLM response")?;
-    Ok(file)
-}
-
-fn create_mock_model() -> std::io::Result<NamedTempFile> {
-    NamedTempFile::new()
+    let _ = fs::remove_dir_all(test_home().join("config"));
+    let _ = fs::remove_dir_all(test_home().join("models"));
 }
 
 #[test]
 fn test_open_with_model_name() {
-    cleanup(); // Clean up any previous state
+    let _lock = AGENTD_HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    cleanup();
     setup_with_model_name("test-model-1");
     let llm = open("test-model-1");
     match llm {
@@ -62,7 +62,8 @@ fn test_open_with_model_name() {
 
 #[test]
 fn test_open_with_invalid_model_name() {
-    cleanup(); // Clean up any previous state
+    let _lock = AGENTD_HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    cleanup();
     setup();
     let model_name = "non-existent-model";
     let result = open(model_name);
@@ -72,15 +73,190 @@ fn test_open_with_invalid_model_name() {
 
 #[test]
 fn test_with_args() {
-    cleanup(); // Clean up any previous state
+    let _lock = AGENTD_HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    cleanup();
     setup_with_model_name("test-model-2");
     let model_name = "test-model-2";
     let llm = open(model_name)
         .unwrap()
         .with_args(vec!["--temp".to_string(), "0.5".to_string()]);
-    
+
     let config = llm.config();
     assert!(config.additional_args.contains(&"--temp".to_string()));
     assert!(config.additional_args.contains(&"0.5".to_string()));
     cleanup();
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_model_params_and_preset_are_layered() {
+    let _lock = AGENTD_HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    cleanup();
+    setup_with_model_name("coder");
+    let config_dir = test_home().join("config");
+    fs::write(
+        config_dir.join("models.toml"),
+        "[coder]\nfile = \"coder.gguf\"\ntemperature = 0.2\nmax_tokens = 1024\nthreads = 8\nargs = [\"--mlock\"]\n",
+    )
+    .unwrap();
+    fs::write(config_dir.join("config.toml"), "[presets.short]\nmax_tokens = 64\n").unwrap();
+
+    let plain = LlmConfig::from_model_name("coder").unwrap().additional_args;
+    let preset = LlmConfig::from_model_name_with("coder", Some("short")).unwrap().additional_args;
+    let unknown = LlmConfig::from_model_name_with("coder", Some("missing"));
+    cleanup();
+
+    assert!(plain.windows(2).any(|pair| pair == ["--temp", "0.2"]));
+    assert!(plain.windows(2).any(|pair| pair == ["--n-predict", "1024"]));
+    assert!(plain.windows(2).any(|pair| pair == ["--threads", "8"]));
+    assert_eq!(plain.last().unwrap(), "--mlock");
+    assert!(preset.windows(2).any(|pair| pair == ["--temp", "0.2"]));
+    assert!(preset.windows(2).any(|pair| pair == ["--n-predict", "64"]));
+    assert!(matches!(unknown, Err(LlmError::InvalidInput(_))));
+}
+
+#[test]
+fn test_default_model_and_aliases() {
+    let _lock = AGENTD_HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    cleanup();
+    setup_with_model_name("zeta");
+    let config_dir = test_home().join("config");
+    fs::write(test_home().join("models").join("alpha.gguf"), b"GGUF").unwrap();
+    fs::write(
+        config_dir.join("models.toml"),
+        "[zeta]\nfile = \"zeta.gguf\"\n\n[alpha]\nfile = \"alpha.gguf\"\n",
+    )
+    .unwrap();
+
+    // Without a configured default the choice doesn't depend on map order
+    let first = config::default_model().unwrap();
+
+    fs::write(
+        config_dir.join("config.toml"),
+        "[runtime]\ndefault_model = \"fast\"\n\n[aliases]\nfast = \"zeta\"\n",
+    )
+    .unwrap();
+    let configured = config::default_model().unwrap();
+    let resolved = config::resolve_model_path(&configured).unwrap();
+    let opened = open("fast").map(|llm| llm.config().model_path.clone());
+    cleanup();
+
+    assert_eq!(first, "alpha");
+    assert_eq!(configured, "fast");
+    assert_eq!(resolved, test_home().join("models").join("zeta.gguf"));
+    assert!(opened.unwrap().ends_with("zeta.gguf"));
+}
+
+#[test]
+fn test_generate_with_fake_llama_cli() {
+    let dir = TempDir::new().unwrap();
+    let fake = FakeLlamaCli::create(dir.path(), "Paris is the capital of France.").unwrap();
+    let llm = Box::new(LlamaCppBackend::new(fake.config()).unwrap())
+        .with_args(vec!["--temp".to_string(), "0.2".to_string()]);
+
+    let response = llm.generate("What is the capital of France?").unwrap();
+    assert_eq!(response, "Paris is the capital of France.");
+
+    let args = fake.last_args();
+    assert_eq!(args[0], "--model");
+    assert!(args.windows(2).any(|pair| pair == ["--temp", "0.2"]));
+}
+
+#[test]
+fn test_fake_llama_cli_streams_output() {
+    let dir = TempDir::new().unwrap();
+    let fake = FakeLlamaCli::create(dir.path(), "Streamed reply.").unwrap();
+    let llm = LlamaCppBackend::new(fake.config()).unwrap();
+
+    let mut streamed = String::new();
+    let response = llm
+        .generate_stream("hi", &mut |chunk| {
+            streamed.push_str(chunk);
+            true
+        })
+        .unwrap();
+    assert_eq!(response, "Streamed reply.");
+    assert_eq!(streamed.trim(), "Streamed reply.");
+}
+
+#[test]
+fn test_fake_llama_cli_failure_is_reported() {
+    let dir = TempDir::new().unwrap();
+    let fake = FakeLlamaCli::create_with_status(dir.path(), "boom", 1).unwrap();
+    let llm = LlamaCppBackend::new(fake.config()).unwrap();
+
+    assert!(matches!(llm.generate("hi"), Err(LlmError::ProcessExecution(_))));
+}
+
+#[test]
+fn test_mock_backend_script_rules_and_errors() {
+    let mock = MockBackend::new()
+        .with_script([MockReply::text("first"), MockReply::error("overloaded")])
+        .with_rule("weather", MockReply::text("sunny"))
+        .with_response("default");
+    let calls = mock.calls();
+    let llm: Box<dyn LlmInterface + Send + Sync> = Box::new(mock);
+
+    assert_eq!(llm.generate("anything").unwrap(), "first");
+    assert!(matches!(llm.generate("anything"), Err(LlmError::ProcessExecution(_))));
+    assert_eq!(llm.generate("what's the weather?").unwrap(), "sunny");
+    assert_eq!(llm.generate("hello").unwrap(), "default");
+    assert_eq!(calls.lock().unwrap().len(), 4);
+}
+
+#[test]
+fn test_mock_backend_streaming_can_stop_early() {
+    let llm = MockBackend::new()
+        .with_response("one two three four")
+        .with_chunk_latency(Duration::from_millis(1));
+
+    let mut chunks = Vec::new();
+    let produced = llm
+        .generate_stream("count", &mut |chunk| {
+            chunks.push(chunk.to_string());
+            chunks.len() < 2
+        })
+        .unwrap();
+
+    assert_eq!(chunks, vec!["one ", "two "]);
+    assert_eq!(produced, "one two ");
+}
+
+#[test]
+fn test_generate_json_constrains_and_validates() {
+    #[derive(serde::Deserialize)]
+    struct City {
+        name: String,
+        population: u64,
+    }
+
+    let schema = serde_json::json!({
+        "type": "object",
+        "properties": { "name": { "type": "string", "minLength": 2 }, "population": { "type": "integer", "minimum": 1 } },
+        "required": ["name", "population"]
+    });
+
+    let dir = TempDir::new().unwrap();
+    let fake = FakeLlamaCli::create(dir.path(), r#"{"name": "Paris", "population": 2100000}"#).unwrap();
+    let llm = LlamaCppBackend::new(fake.config()).unwrap();
+    let city: City = agentd::llm::generate_typed(&llm, "Largest city in France?", &schema).unwrap();
+    assert_eq!((city.name.as_str(), city.population), ("Paris", 2100000));
+    let args = fake.last_args();
+    let schema_arg = schema.to_string();
+    assert!(args.windows(2).any(|pair| pair[0] == "--json-schema" && pair[1] == schema_arg));
+    let params = agentd::llm::GenerationParams::from_args(&args);
+    assert_eq!(params.json_schema.as_ref(), Some(&schema));
+
+    // Without a scripted reply the mock answers with a minimal conforming value
+    let mock = MockBackend::new();
+    assert_eq!(mock.generate_json("city", &schema).unwrap(), serde_json::json!({ "name": "xx", "population": 1 }));
+
+    let wrong = MockBackend::new().with_response(r#"{"name": "Paris"}"#);
+    let error = wrong.generate_json("city", &schema).unwrap_err();
+    assert!(matches!(error, LlmError::InvalidResponse(message) if message.contains("population")));
+
+    // Keywords the validator cannot check are refused before anything is generated
+    let patterned = serde_json::json!({ "type": "object", "properties": { "zip": { "type": "string", "pattern": "^[0-9]{5}$" } }, "allOf": [] });
+    let error = mock.generate_json("zip", &patterned).unwrap_err();
+    assert!(matches!(&error, LlmError::InvalidInput(message) if message.contains("$.properties.zip: unsupported keyword 'pattern'") && message.contains("$: unsupported keyword 'allOf'")), "{}", error);
+    assert!(agentd::schema::validate(&serde_json::json!({ "zip": "x" }), &patterned).is_err());
+}
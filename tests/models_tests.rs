@@ -0,0 +1,111 @@
+use agentd::config;
+use agentd::models::{self, ImportMode};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+// Every test points AGENTD_HOME somewhere different
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn with_home<T>(test: impl FnOnce(&TempDir) -> T) -> T {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+    config::init_config(&config::get_config_dir(), false).unwrap();
+
+    let result = test(&home);
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+    result
+}
+
+#[test]
+fn test_import_registers_model_and_alias_resolves() {
+    with_home(|home| {
+        let source = home.path().join("download.gguf");
+        fs::write(&source, b"GGUF").unwrap();
+
+        let (name, target) = models::import_model(&source, Some("tiny"), ImportMode::Copy, None, Some(2048)).unwrap();
+        assert_eq!(name, "tiny");
+        assert!(source.exists());
+        assert_eq!(target, config::get_models_dir().join("download.gguf"));
+
+        models::set_alias("fast", "tiny").unwrap();
+        assert_eq!(config::resolve_model_path("fast").unwrap(), target);
+        assert_eq!(config::find_model_entry("fast").unwrap().context_size, Some(2048));
+
+        // The template's comments survive the edits
+        let registry = fs::read_to_string(config::get_models_registry_path()).unwrap();
+        assert!(registry.starts_with("# Model Registry"));
+        assert!(registry.contains("[tiny]\nfile = \"download.gguf\""));
+    });
+}
+
+#[test]
+fn test_rename_and_remove_keep_aliases_consistent() {
+    with_home(|_| {
+        fs::create_dir_all(config::get_models_dir()).unwrap();
+        fs::write(config::get_models_dir().join("a.gguf"), b"GGUF").unwrap();
+        let entry = config::ModelEntry {
+            file: "a.gguf".to_string(),
+            ..config::ModelEntry::default()
+        };
+        models::add_model("alpha", &entry).unwrap();
+        assert!(models::add_model("alpha", &entry).is_err());
+        models::set_alias("main", "alpha").unwrap();
+        config::set_config_value(&config::get_config_path(), "runtime.default_model", "alpha", false).unwrap();
+
+        models::rename_model("alpha", "beta").unwrap();
+        let loaded = config::load_config().unwrap();
+        assert!(loaded.models.contains_key("beta"));
+        assert!(!loaded.models.contains_key("alpha"));
+        assert_eq!(loaded.aliases["main"], "beta");
+        assert_eq!(loaded.runtime.default_model.as_deref(), Some("beta"));
+
+        // A default naming the model through an alias goes with it
+        config::set_config_value(&config::get_config_path(), "runtime.default_model", "main", false).unwrap();
+        models::remove_model("beta", false).unwrap();
+        let loaded = config::load_config().unwrap();
+        assert!(loaded.models.is_empty());
+        assert!(loaded.aliases.is_empty());
+        assert_eq!(loaded.runtime.default_model, None);
+        assert!(config::get_models_dir().join("a.gguf").exists());
+    });
+}
+
+#[test]
+fn test_generate_takes_the_model_before_the_prompt() {
+    let home = TempDir::new().unwrap();
+    fs::create_dir_all(home.path().join("models")).unwrap();
+    for name in ["tiny", "other"] {
+        fs::write(home.path().join("models").join(format!("{}.gguf", name)), b"GGUF").unwrap();
+    }
+    let generate = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_agentd"))
+            .env("AGENTD_HOME", home.path())
+            .env("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"))
+            .env("AGENTD_RUNTIME_DEFAULT_BACKEND", "mock")
+            .env("AGENTD_RUNTIME_DEFAULT_MODEL", "other")
+            .args(["--format", "json", "generate"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"Say hi").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let record: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        record["model"].as_str().unwrap().to_string()
+    };
+
+    // A lone argument names the model and the prompt comes from stdin
+    assert_eq!(generate(&["tiny"]), "tiny");
+    assert_eq!(generate(&["tiny", "Say hi"]), "tiny");
+    assert_eq!(generate(&["--model", "tiny", "Say hi"]), "tiny");
+    assert_eq!(generate(&[]), "other");
+}
@@ -0,0 +1,91 @@
+use agentd::config::{self, ModelEntry};
+use agentd::models;
+use agentd::output::{self, GenerationRecord, ModelSource, ModelStatus, OutputFormat};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Environment variables are process-wide
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_list_models_is_sorted_with_status_and_aliases() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+    let models_dir = home.path().join("models");
+    fs::create_dir_all(&models_dir).unwrap();
+    fs::write(models_dir.join("zeta.gguf"), b"GGUF").unwrap();
+    fs::write(models_dir.join("shared.gguf"), b"GGUF").unwrap();
+
+    let mut config = config::load_config().unwrap();
+    let entry = |file: &str| ModelEntry {
+        file: file.to_string(),
+        ..ModelEntry::default()
+    };
+    config.models.insert("zeta".to_string(), entry("zeta.gguf"));
+    config.models.insert("alpha".to_string(), entry("gone.gguf"));
+    config.models.insert(
+        "remote".to_string(),
+        ModelEntry {
+            base_url: Some("http://gpu-box:8000/v1".to_string()),
+            ..ModelEntry::default()
+        },
+    );
+    config.aliases.insert("fast".to_string(), "zeta".to_string());
+    config.runtime.default_model = Some("fast".to_string());
+
+    let discovered: HashMap<String, ModelEntry> = [("shared", "shared.gguf"), ("beta", "zeta.gguf"), ("aardvark", "shared.gguf")]
+        .into_iter()
+        .map(|(name, file)| (name.to_string(), entry(file)))
+        .collect();
+    let records = models::list_models(&config, &discovered);
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+
+    // Registered models first, then discovered files not registered under another name
+    let names: Vec<&str> = records.iter().map(|record| record.name.as_str()).collect();
+    assert_eq!(names, ["alpha", "remote", "zeta", "aardvark", "shared"]);
+    assert_eq!(records[0].status, ModelStatus::Missing);
+    assert_eq!(records[1].status, ModelStatus::Remote);
+    assert_eq!(records[1].path, None);
+    assert_eq!(records[2].status, ModelStatus::Available);
+    assert_eq!(records[2].size, Some(4));
+    assert!(records[2].default);
+    assert_eq!(records[2].aliases, ["fast"]);
+    assert_eq!(records[3].source, ModelSource::Discovered);
+
+    let json: Value = serde_json::from_str(&output::render_list(OutputFormat::Json, &records).unwrap().unwrap()).unwrap();
+    assert_eq!(json[2]["status"], "available");
+    assert_eq!(json[1]["metadata"]["base_url"], "http://gpu-box:8000/v1");
+}
+
+#[test]
+fn test_render_formats() {
+    let record = GenerationRecord::new("tiny", 3, "  hello\nworld \n", Duration::from_millis(42));
+    assert_eq!(record.text, "hello\nworld");
+    assert_eq!(record.usage.total_tokens, 3 + record.usage.completion_tokens);
+    assert_eq!(output::render_record(OutputFormat::Plain, &record).unwrap(), None);
+
+    let line = output::render_record(OutputFormat::Jsonl, &record).unwrap().unwrap();
+    assert_eq!(line.lines().count(), 1);
+    let json: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(json["timings"]["total_ms"], 42);
+    assert!(json.get("session").is_none());
+
+    let table = output::render_record(OutputFormat::Table, &record).unwrap().unwrap();
+    assert!(table.starts_with("FIELD              VALUE\nMODEL              tiny\n"));
+    assert!(table.contains("TEXT               hello world\n"));
+
+    let records = [record.clone(), GenerationRecord::new("a-longer-name", 1, "hi", Duration::ZERO)];
+    let lines = output::render_list(OutputFormat::Jsonl, &records).unwrap().unwrap();
+    assert_eq!(lines.lines().count(), 2);
+    let table = output::render_list(OutputFormat::Table, &records).unwrap().unwrap();
+    let rows: Vec<&str> = table.lines().collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0].find("PROMPT_TOKENS"), rows[1].find('3'));
+}
@@ -0,0 +1,96 @@
+use agentd::prompts::{self, PromptTemplate};
+use agentd::LlmError;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+// Environment variables and the working directory are process-wide
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+const SUMMARIZE: &str = r#"
+description = "Summarize a document"
+model = "fast"
+system = "You write {{style}} summaries."
+template = """{{@header.txt}}
+Summarize this, focusing on {{focus}}:
+
+{{file}}"""
+temperature = 0.2
+
+[vars.focus]
+default = "key decisions"
+
+[vars.style]
+default = "short"
+
+[vars.file]
+description = "The document"
+"#;
+
+#[test]
+fn test_render_fills_defaults_and_includes() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("summarize.toml");
+    fs::write(&path, SUMMARIZE).unwrap();
+    fs::write(dir.path().join("header.txt"), "Be accurate.").unwrap();
+    let template = PromptTemplate::from_file(&path).unwrap();
+    assert_eq!(template.placeholders(), ["style", "focus", "file"]);
+
+    let vars = HashMap::from([
+        ("file".to_string(), "Notes with {{focus}} inside".to_string()),
+        ("style".to_string(), "bulleted".to_string()),
+    ]);
+    let rendered = template.render(&vars).unwrap();
+    assert_eq!(
+        rendered.prompt,
+        "Be accurate.\nSummarize this, focusing on key decisions:\n\nNotes with {{focus}} inside"
+    );
+    assert_eq!(rendered.system.as_deref(), Some("You write bulleted summaries."));
+    assert_eq!(rendered.model.as_deref(), Some("fast"));
+    assert_eq!(rendered.params.temperature, Some(0.2));
+
+    let missing = template.render(&HashMap::new());
+    assert!(matches!(missing, Err(LlmError::InvalidInput(message)) if message.contains("'file'")));
+    let typo = HashMap::from([("file".to_string(), "x".to_string()), ("fokus".to_string(), "y".to_string())]);
+    assert!(matches!(template.render(&typo), Err(LlmError::InvalidInput(message)) if message.contains("'fokus'")));
+
+    let notes = dir.path().join("notes.txt");
+    fs::write(&notes, "from a file").unwrap();
+    let (name, value) = prompts::parse_var(&format!("file=@{}", notes.display())).unwrap();
+    assert_eq!((name.as_str(), value.as_str()), ("file", "from a file"));
+    assert_eq!(prompts::parse_var("focus=a=b").unwrap().1, "a=b");
+    assert!(prompts::parse_var("focus").is_err());
+}
+
+#[test]
+fn test_project_templates_shadow_user_templates() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    let project = TempDir::new().unwrap();
+    let user_dir = home.path().join("prompts");
+    let project_dir = project.path().join(".agentd").join("prompts");
+    fs::create_dir_all(&user_dir).unwrap();
+    fs::create_dir_all(&project_dir).unwrap();
+    fs::write(user_dir.join("greet.toml"), "template = \"Hello from home, {{name}}\"").unwrap();
+    fs::write(user_dir.join("other.toml"), "template = \"Other\"").unwrap();
+    fs::write(project_dir.join("greet.toml"), "template = \"Hello from the project, {{name}}\"").unwrap();
+    let nested = project.path().join("src");
+    fs::create_dir_all(&nested).unwrap();
+
+    let cwd = std::env::current_dir().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_current_dir(&nested).unwrap();
+    let vars = HashMap::from([("name".to_string(), "Ada".to_string())]);
+    let rendered = prompts::render("greet", &vars);
+    let listed = prompts::list_templates();
+    let invalid = prompts::render("../greet", &vars);
+    std::env::set_current_dir(cwd).unwrap();
+    std::env::remove_var("AGENTD_HOME");
+
+    assert_eq!(rendered.unwrap().prompt, "Hello from the project, Ada");
+    let listed = listed.unwrap();
+    assert_eq!(listed.keys().collect::<Vec<_>>(), ["greet", "other"]);
+    assert!(listed["greet"].path.as_ref().unwrap().starts_with(project.path()));
+    assert!(invalid.is_err());
+}
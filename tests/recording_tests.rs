@@ -0,0 +1,114 @@
+use agentd::cache::ResponseCache;
+use agentd::chat::ChatMessage;
+use agentd::llm::backends::{CachedBackend, RecordingBackend, ReplayBackend};
+use agentd::llm::{GenerationParams, LlmConfig};
+use agentd::testing::{MockBackend, MockReply};
+use agentd::{LlmError, LlmInterface, Tool, ToolCall};
+use serde_json::json;
+use std::time::Duration;
+use tempfile::TempDir;
+
+#[test]
+fn test_record_then_replay_offline() {
+    let dir = TempDir::new().unwrap();
+    let cassette = dir.path().join("flows").join("cassette.json");
+
+    let live = MockBackend::new()
+        .with_rule("capital", MockReply::text("Paris"))
+        .with_rule("stream", MockReply::text("one two three"))
+        .with_rule("broken", MockReply::error("model crashed"))
+        .with_response("hello there");
+    let recorder = RecordingBackend::new(Box::new(live), "gemma", &cassette);
+
+    let params = GenerationParams { temperature: Some(0.0), ..GenerationParams::default() };
+    assert_eq!(recorder.generate_with("What is the capital?", &params).unwrap(), "Paris");
+    recorder.generate_stream("stream please", &mut |_| true).unwrap();
+    assert!(recorder.generate("broken request").is_err());
+    recorder.chat(&[ChatMessage::user("hi")]).unwrap();
+
+    let replay = ReplayBackend::load(&cassette, "gemma").unwrap();
+    assert_eq!(replay.generate_with("What is the capital?", &params).unwrap(), "Paris");
+
+    let mut chunks = Vec::new();
+    let streamed = replay
+        .generate_stream("stream please", &mut |chunk| {
+            chunks.push(chunk.to_string());
+            true
+        })
+        .unwrap();
+    assert_eq!(streamed, "one two three");
+    assert_eq!(chunks.len(), 3);
+
+    assert!(matches!(replay.generate("broken request"), Err(LlmError::ProcessExecution(_))));
+    assert!(replay.chat(&[ChatMessage::user("hi")]).unwrap().contains("hello there"));
+}
+
+#[test]
+fn test_replay_fails_on_unmatched_requests() {
+    let dir = TempDir::new().unwrap();
+    let cassette = dir.path().join("cassette.json");
+
+    let recorder = RecordingBackend::new(Box::new(MockBackend::new()), "gemma", &cassette);
+    recorder.generate("known prompt").unwrap();
+
+    let replay = ReplayBackend::load(&cassette, "gemma").unwrap();
+    assert!(matches!(replay.generate("unknown prompt"), Err(LlmError::Cassette(_))));
+    // Parameters and model are part of the match
+    let params = GenerationParams { seed: Some(1), ..GenerationParams::default() };
+    assert!(matches!(replay.generate_with("known prompt", &params), Err(LlmError::Cassette(_))));
+    let other_model = ReplayBackend::load(&cassette, "llama").unwrap();
+    assert!(matches!(other_model.generate("known prompt"), Err(LlmError::Cassette(_))));
+
+    // Repeated requests reuse the last recorded answer
+    assert_eq!(replay.generate("known prompt").unwrap(), replay.generate("known prompt").unwrap());
+}
+
+/// Answers tool chats natively, the way a remote API does, rather than through text
+struct NativeToolsBackend {
+    config: LlmConfig,
+}
+
+impl LlmInterface for NativeToolsBackend {
+    fn generate(&self, _prompt: &str) -> Result<String, LlmError> {
+        Ok("plain text".to_string())
+    }
+
+    fn chat_with_tools(&self, _messages: &[ChatMessage], tools: &[Tool]) -> Result<ChatMessage, LlmError> {
+        Ok(ChatMessage {
+            tool_calls: vec![ToolCall::new(tools[0].name.clone(), json!({"city": "Oslo"}))],
+            ..ChatMessage::assistant("")
+        })
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(self: Box<Self>, _args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self
+    }
+}
+
+#[test]
+fn test_wrappers_forward_tool_chats_and_embeddings() {
+    let dir = TempDir::new().unwrap();
+    let cassette = dir.path().join("cassette.json");
+    let cache = ResponseCache::new(dir.path().join("responses"), Duration::from_secs(60), 1024 * 1024);
+    let native = NativeToolsBackend { config: LlmConfig::new("native", "native") };
+    let recorder = RecordingBackend::new(Box::new(CachedBackend::new(Box::new(native), cache)), "native", &cassette);
+
+    let messages = [ChatMessage::user("Weather in Oslo?")];
+    let tools = [Tool::new("get_weather", "", json!({"type": "object"}))];
+    let reply = recorder.chat_with_tools(&messages, &tools).unwrap();
+    assert_eq!((reply.tool_calls[0].name.as_str(), reply.content.as_str()), ("get_weather", ""));
+
+    let embedder = RecordingBackend::new(Box::new(MockBackend::new()), "native", &cassette);
+    let vector = embedder.embed("hello").unwrap();
+
+    let replay = ReplayBackend::load(&cassette, "native").unwrap();
+    assert_eq!(replay.chat_with_tools(&messages, &tools).unwrap(), reply);
+    assert_eq!(replay.embed("hello").unwrap(), vector);
+    assert!(matches!(replay.embed("goodbye"), Err(LlmError::Cassette(_))));
+    // The tools offered are part of the match
+    assert!(matches!(replay.chat_with_tools(&messages, &[]), Err(LlmError::Cassette(_))));
+}
@@ -0,0 +1,213 @@
+use agentd::llm::backends::OpenAiBackend;
+use agentd::llm::GenerationParams;
+use agentd::{ChatMessage, LlmError, LlmInterface, Tool};
+use serde_json::{json, Value};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tempfile::TempDir;
+
+// Environment variables are process-wide
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// A received request: path, Authorization header and JSON body
+type Received = Arc<Mutex<Vec<(String, Option<String>, Value)>>>;
+
+/// A minimal OpenAI-compatible server answering on `/v1`; returns its base URL
+fn start_stub() -> (String, Received) {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}/v1", server.server_addr().to_ip().unwrap());
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let log = received.clone();
+
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            let body: Value = serde_json::from_str(&body).unwrap();
+            let auth = request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv("Authorization"))
+                .map(|header| header.value.to_string());
+            let path = request.url().to_string();
+            log.lock().unwrap().push((path.clone(), auth, body.clone()));
+
+            let (status, reply) = match (path.as_str(), body["stream"] == true) {
+                ("/v1/completions", false) => (200, json!({ "choices": [{ "text": "remote reply" }] }).to_string()),
+                ("/v1/completions", true) => {
+                    let events: String = ["remote", " streamed"]
+                        .iter()
+                        .map(|text| format!("data: {}\n\n", json!({ "choices": [{ "text": text }] })))
+                        .collect();
+                    (200, format!("{}data: [DONE]\n\n", events))
+                }
+                ("/v1/chat/completions", _) if body["tools"].is_array() => (
+                    200,
+                    json!({ "choices": [{ "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" } }],
+                    } }] })
+                    .to_string(),
+                ),
+                ("/v1/chat/completions", _) => (
+                    200,
+                    json!({ "choices": [{ "message": { "role": "assistant", "content": "remote chat" } }] }).to_string(),
+                ),
+                ("/v1/embeddings", _) => (200, json!({ "data": [{ "embedding": [0.5, 0.25] }] }).to_string()),
+                _ => (404, json!({ "error": "no such route" }).to_string()),
+            };
+            let _ = request.respond(tiny_http::Response::from_string(reply).with_status_code(status));
+        }
+    });
+
+    (base_url, received)
+}
+
+#[test]
+fn test_models_toml_entry_opens_remote_backend() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (base_url, received) = start_stub();
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+    std::env::set_var("AGENTD_TEST_REMOTE_KEY", "secret");
+
+    let config_dir = home.path().join("config");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(
+        config_dir.join("models.toml"),
+        format!(
+            "[gpu-box]\nbase_url = \"{}\"\napi_key_env = \"AGENTD_TEST_REMOTE_KEY\"\nremote_model = \"qwen-72b\"\nmax_tokens = 64\n",
+            base_url
+        ),
+    )
+    .unwrap();
+
+    let llm = agentd::open("gpu-box");
+    let issues = agentd::config::validate_config();
+    std::env::remove_var("AGENTD_TEST_REMOTE_KEY");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+
+    let llm = llm.unwrap();
+    let params = GenerationParams {
+        temperature: Some(0.1),
+        ..GenerationParams::default()
+    };
+    assert_eq!(llm.generate_with("hello", &params).unwrap(), "remote reply");
+    assert_eq!(llm.chat(&[ChatMessage::user("hi")]).unwrap(), "remote chat");
+    assert_eq!(llm.embed("text").unwrap(), [0.5, 0.25]);
+    assert!(issues.unwrap().iter().all(|issue| !issue.message.contains("gpu-box")));
+
+    let received = received.lock().unwrap();
+    let (path, auth, body) = &received[0];
+    assert_eq!(path, "/v1/completions");
+    assert_eq!(auth.as_deref(), Some("Bearer secret"));
+    assert_eq!(body["model"], "qwen-72b");
+    assert_eq!(body["prompt"], "hello");
+    assert_eq!(body["max_tokens"], 64);
+    assert!((body["temperature"].as_f64().unwrap() - 0.1).abs() < 1e-6);
+    assert_eq!(received[1].2["messages"][0]["content"], "hi");
+}
+
+#[test]
+fn test_streaming_and_http_errors() {
+    let (base_url, received) = start_stub();
+    let llm: Box<dyn LlmInterface> = Box::new(OpenAiBackend::new(&base_url, "tiny"));
+
+    let mut chunks = Vec::new();
+    let text = llm
+        .generate_stream("go", &mut |chunk| {
+            chunks.push(chunk.to_string());
+            true
+        })
+        .unwrap();
+    assert_eq!(text, "remote streamed");
+    assert_eq!(chunks, ["remote", " streamed"]);
+    assert_eq!(received.lock().unwrap()[0].1, None);
+
+    let missing = OpenAiBackend::new(format!("{}/missing", base_url), "tiny").generate("go");
+    assert!(matches!(missing, Err(LlmError::Http(message)) if message.contains("404")));
+}
+
+#[test]
+fn test_chat_with_tools_uses_native_tool_calls() {
+    let (base_url, received) = start_stub();
+    let llm = OpenAiBackend::new(&base_url, "qwen-72b");
+    let tool = Tool::new("get_weather", "Weather for a city", json!({"type": "object"}));
+
+    let mut messages = vec![ChatMessage::user("Weather in Paris?")];
+    let reply = llm.chat_with_tools(&messages, std::slice::from_ref(&tool)).unwrap();
+    assert_eq!(reply.content, "");
+    assert_eq!(reply.tool_calls[0].id, "call_1");
+    assert_eq!(reply.tool_calls[0].arguments, json!({"city": "Paris"}));
+
+    messages.push(reply);
+    messages.push(ChatMessage::tool("call_1", "sunny"));
+    assert_eq!(llm.chat(&messages).unwrap(), "remote chat");
+
+    let received = received.lock().unwrap();
+    assert_eq!(received[0].2["tools"][0]["function"]["name"], "get_weather");
+    let sent = &received[1].2["messages"];
+    assert_eq!(sent[1]["tool_calls"][0]["function"]["arguments"], "{\"city\":\"Paris\"}");
+    assert_eq!(sent[2]["role"], "tool");
+    assert_eq!(sent[2]["tool_call_id"], "call_1");
+}
+
+/// Write a fake `llama-server` into `dir` that logs its arguments and runs `body`
+#[cfg(unix)]
+fn fake_llama_server(dir: &std::path::Path, body: &str) -> agentd::llm::LlmConfig {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script = dir.join("llama-server");
+    fs::write(&script, format!("#!/bin/sh\necho \"$*\" >> '{}'\n{}\n", dir.join("launches").display(), body)).unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("model.gguf"), b"GGUF").unwrap();
+    agentd::llm::LlmConfig::new(dir.join("llama-cli").to_string_lossy(), dir.join("model.gguf").to_string_lossy())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_llama_server_backend_keeps_one_server_loaded() {
+    use agentd::llm::backends::LlamaServerBackend;
+
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new().unwrap();
+    // Stands in for llama-server: agentd's own server answers the OpenAI chat API
+    let config = fake_llama_server(
+        dir.path(),
+        &format!(
+            "while [ $# -gt 0 ]; do [ \"$1\" = --port ] && port=$2; shift; done\nexec '{}' serve --addr 127.0.0.1:$port",
+            env!("CARGO_BIN_EXE_agentd")
+        ),
+    );
+    std::env::set_var("AGENTD_HOME", dir.path().join("home"));
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", dir.path().join("etc"));
+    std::env::set_var("AGENTD_RUNTIME_DEFAULT_BACKEND", "mock");
+
+    let args = ["--temp", "0.2", "--ctx-size", "4096"].map(String::from).to_vec();
+    let llm: Box<dyn LlmInterface + Send + Sync> = Box::new(LlamaServerBackend::start(config.with_args(args)).unwrap());
+    let first = llm.chat(&[ChatMessage::user("hello")]);
+    let llm = llm.with_args(vec!["--temp".to_string(), "0.9".to_string()]);
+    let second = llm.chat(&[ChatMessage::user("again")]);
+    drop(llm);
+    std::env::remove_var("AGENTD_HOME");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_RUNTIME_DEFAULT_BACKEND");
+
+    assert!(first.unwrap().contains("hello"));
+    assert!(second.unwrap().contains("again"));
+    let launches = fs::read_to_string(dir.path().join("launches")).unwrap();
+    assert_eq!(launches.lines().count(), 1, "{}", launches);
+    assert!(launches.contains("model.gguf --temp 0.2 --ctx-size 4096 --host 127.0.0.1 --port "), "{}", launches);
+    // Dropping the backend stopped the server
+    let port = launches.split_whitespace().last().unwrap();
+    assert!(std::net::TcpStream::connect(format!("127.0.0.1:{}", port)).is_err());
+
+    let failing = TempDir::new().unwrap();
+    let config = fake_llama_server(failing.path(), "echo 'error: unknown model architecture' >&2\nexit 1");
+    let error = LlamaServerBackend::start(config).unwrap_err();
+    assert!(error.to_string().contains("unknown model architecture"), "{}", error);
+}
@@ -0,0 +1,244 @@
+use agentd::server::Server;
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use tempfile::TempDir;
+
+// Environment variables are process-wide
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn start_server() -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// Send one request and return the status code and body
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+/// JSON objects in a response body, skipping chunked-encoding size lines
+fn json_lines(body: &str) -> Vec<Value> {
+    body.lines()
+        .filter(|line| line.starts_with('{'))
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_generate_and_tags() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+    std::env::set_var("AGENTD_RUNTIME_DEFAULT_BACKEND", "mock");
+    fs::create_dir_all(home.path().join("models")).unwrap();
+    fs::write(home.path().join("models").join("tiny.gguf"), b"GGUF").unwrap();
+
+    let addr = start_server();
+    let tags = request(addr, "GET", "/api/tags", "");
+    let generated = request(
+        addr,
+        "POST",
+        "/api/generate",
+        r#"{"model": "tiny", "prompt": "hello", "stream": false, "options": {"temperature": 0.1}}"#,
+    );
+    let bad = request(addr, "POST", "/api/generate", "{}");
+    let missing = request(addr, "GET", "/api/pull", "");
+
+    std::env::remove_var("AGENTD_RUNTIME_DEFAULT_BACKEND");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+
+    assert_eq!(tags.0, 200);
+    let tags: Value = serde_json::from_str(&tags.1).unwrap();
+    assert_eq!(tags["models"][0]["name"], "tiny");
+    assert_eq!(tags["models"][0]["size"], 4);
+
+    assert_eq!(generated.0, 200);
+    let generated: Value = serde_json::from_str(&generated.1).unwrap();
+    assert_eq!(generated["response"], "mock: hello");
+    assert_eq!(generated["done"], true);
+
+    assert_eq!(bad.0, 400);
+    assert_eq!(missing.0, 404);
+}
+
+#[test]
+fn test_chat_streams_ndjson_and_embeddings() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+    std::env::set_var("AGENTD_RUNTIME_DEFAULT_BACKEND", "mock");
+
+    let addr = start_server();
+    let chat = request(
+        addr,
+        "POST",
+        "/api/chat",
+        r#"{"model": "tiny", "messages": [{"role": "user", "content": "hi there"}]}"#,
+    );
+    let embedded = request(addr, "POST", "/api/embed", r#"{"model": "tiny", "input": ["a", "b"]}"#);
+
+    std::env::remove_var("AGENTD_RUNTIME_DEFAULT_BACKEND");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+
+    assert_eq!(chat.0, 200);
+    let lines = json_lines(&chat.1);
+    assert!(lines.len() > 2);
+    let streamed: String = lines
+        .iter()
+        .map(|line| line["message"]["content"].as_str().unwrap())
+        .collect();
+    assert_eq!(streamed, "mock: User: hi there\n\nAssistant:");
+    assert!(lines[..lines.len() - 1].iter().all(|line| line["done"] == false));
+    assert_eq!(lines.last().unwrap()["done"], true);
+
+    assert_eq!(embedded.0, 200);
+    let embedded: Value = serde_json::from_str(&embedded.1).unwrap();
+    assert_eq!(embedded["embeddings"].as_array().unwrap().len(), 2);
+    assert_eq!(embedded["embeddings"][0].as_array().unwrap().len(), agentd::testing::MOCK_EMBEDDING_DIMENSIONS);
+}
+
+#[test]
+fn test_openai_chat_completions_with_tools() {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let home = TempDir::new().unwrap();
+    std::env::set_var("AGENTD_HOME", home.path());
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"));
+    std::env::set_var("AGENTD_RUNTIME_DEFAULT_BACKEND", "mock");
+
+    let addr = start_server();
+    let tools = r#"[{"type": "function", "function": {"name": "get_weather", "description": "Weather for a city", "parameters": {"type": "object"}}}]"#;
+    let completion = request(
+        addr,
+        "POST",
+        "/v1/chat/completions",
+        &format!(r#"{{"model": "tiny-llama3", "messages": [{{"role": "user", "content": "hi"}}], "tools": {}, "max_tokens": 8}}"#, tools),
+    );
+    let streamed = request(
+        addr,
+        "POST",
+        "/v1/chat/completions",
+        r#"{"model": "tiny", "messages": [{"role": "user", "content": "hi"}], "stream": true}"#,
+    );
+    let ollama = request(
+        addr,
+        "POST",
+        "/api/chat",
+        &format!(r#"{{"model": "tiny-llama3", "messages": [{{"role": "user", "content": "hi"}}], "tools": {}, "stream": false}}"#, tools),
+    );
+    let invalid = request(addr, "POST", "/v1/chat/completions", r#"{"model": "tiny", "messages": [{"role": "robot"}]}"#);
+
+    std::env::remove_var("AGENTD_RUNTIME_DEFAULT_BACKEND");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+
+    // The mock echoes its prompt, so the tool description shows up in the reply
+    assert_eq!(completion.0, 200);
+    let completion: Value = serde_json::from_str(&completion.1).unwrap();
+    assert_eq!(completion["object"], "chat.completion");
+    assert_eq!(completion["choices"][0]["finish_reason"], "stop");
+    let content = completion["choices"][0]["message"]["content"].as_str().unwrap();
+    assert!(content.contains("\"name\":\"get_weather\""), "{}", content);
+    assert!(completion["usage"]["total_tokens"].as_u64().unwrap() > 0);
+
+    assert_eq!(streamed.0, 200);
+    let events: Vec<&str> = streamed.1.lines().filter_map(|line| line.strip_prefix("data: ")).collect();
+    assert_eq!(events.last(), Some(&"[DONE]"));
+    let chunks: Vec<Value> = events[..events.len() - 1].iter().map(|event| serde_json::from_str(event).unwrap()).collect();
+    let text: String = chunks.iter().filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str()).collect();
+    assert_eq!(text, "mock: User: hi\n\nAssistant:");
+    assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "stop");
+
+    assert_eq!(ollama.0, 200);
+    let ollama: Value = serde_json::from_str(&ollama.1).unwrap();
+    assert!(ollama["message"]["content"].as_str().unwrap().contains("get_weather"));
+    assert_eq!(ollama["done"], true);
+
+    assert_eq!(invalid.0, 400);
+    let invalid: Value = serde_json::from_str(&invalid.1).unwrap();
+    assert_eq!(invalid["error"]["type"], "invalid_request_error");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_chats_reach_one_loaded_model_through_its_chat_api() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = TempDir::new().unwrap();
+    let home = dir.path().join("home");
+    fs::create_dir_all(home.join("models")).unwrap();
+    fs::write(home.join("models").join("tiny.gguf"), b"GGUF").unwrap();
+    // Stands in for llama-server: agentd's own server with a mock model, which has no /v1/completions
+    let script = dir.path().join("llama-server");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho $$ >> '{}'\nwhile [ $# -gt 0 ]; do [ \"$1\" = --port ] && port=$2; shift; done\n\
+             AGENTD_RUNTIME_DEFAULT_BACKEND=mock exec '{}' serve --addr 127.0.0.1:$port\n",
+            dir.path().join("launches").display(),
+            env!("CARGO_BIN_EXE_agentd")
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    std::env::set_var("AGENTD_HOME", &home);
+    std::env::set_var("AGENTD_SYSTEM_CONFIG_DIR", home.join("etc"));
+    std::env::set_var("AGENTD_RUNTIME_LLAMA_EXECUTABLE", dir.path().join("llama-cli"));
+    std::env::set_var("AGENTD_CACHE_RESPONSE_CACHE", "false");
+
+    let addr = start_server();
+    let ollama = request(
+        addr,
+        "POST",
+        "/api/chat",
+        r#"{"model": "tiny", "messages": [{"role": "user", "content": "hi there"}], "stream": false}"#,
+    );
+    let openai = request(
+        addr,
+        "POST",
+        "/v1/chat/completions",
+        r#"{"model": "tiny", "messages": [{"role": "user", "content": "again"}], "temperature": 0.2}"#,
+    );
+
+    std::env::remove_var("AGENTD_CACHE_RESPONSE_CACHE");
+    std::env::remove_var("AGENTD_RUNTIME_LLAMA_EXECUTABLE");
+    std::env::remove_var("AGENTD_SYSTEM_CONFIG_DIR");
+    std::env::remove_var("AGENTD_HOME");
+    let launches = fs::read_to_string(dir.path().join("launches")).unwrap();
+    for pid in launches.lines() {
+        let _ = std::process::Command::new("kill").arg(pid).status();
+    }
+
+    assert_eq!(ollama.0, 200, "{}", ollama.1);
+    let ollama: Value = serde_json::from_str(&ollama.1).unwrap();
+    assert!(ollama["message"]["content"].as_str().unwrap().contains("hi there"));
+    assert_eq!(openai.0, 200, "{}", openai.1);
+    let openai: Value = serde_json::from_str(&openai.1).unwrap();
+    assert!(openai["choices"][0]["message"]["content"].as_str().unwrap().contains("again"));
+    assert_eq!(launches.lines().count(), 1, "{}", launches);
+}
@@ -0,0 +1,174 @@
+use agentd::chat::{ChatMessage, Role};
+use agentd::llm::LlmConfig;
+use agentd::session::{Session, SessionStore};
+use agentd::{LlmError, LlmInterface};
+use std::fs;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+// Backend that answers with the number of messages it was sent
+struct CountingBackend {
+    config: LlmConfig,
+}
+
+impl CountingBackend {
+    fn boxed() -> Box<dyn LlmInterface + Send + Sync> {
+        Box::new(Self { config: LlmConfig::new("none", "none") })
+    }
+}
+
+impl LlmInterface for CountingBackend {
+    fn generate(&self, prompt: &str) -> Result<String, LlmError> {
+        Ok(prompt.to_string())
+    }
+
+    fn chat(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        Ok(format!("seen {}", messages.len()))
+    }
+
+    fn config(&self) -> &LlmConfig {
+        &self.config
+    }
+
+    fn with_args(self: Box<Self>, _args: Vec<String>) -> Box<dyn LlmInterface + Send + Sync> {
+        self
+    }
+}
+
+#[test]
+fn test_session_persists_and_resumes() {
+    let dir = TempDir::new().unwrap();
+    let store = SessionStore::new(dir.path());
+
+    let mut session = Session::new(store.clone(), "triage", "test-model", CountingBackend::boxed()).unwrap();
+    session.set_system("You are on call.");
+    assert_eq!(session.send("first").unwrap(), "seen 2");
+
+    let mut resumed = Session::resume(store.clone(), "triage", CountingBackend::boxed()).unwrap();
+    assert_eq!(resumed.model(), "test-model");
+    assert_eq!(resumed.messages().len(), 3);
+    assert_eq!(resumed.send("second").unwrap(), "seen 4");
+
+    let sessions = store.list().unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].message_count, 5);
+}
+
+#[test]
+fn test_session_fork_and_delete() {
+    let dir = TempDir::new().unwrap();
+    let store = SessionStore::new(dir.path());
+
+    let mut session = Session::new(store.clone(), "base", "test-model", CountingBackend::boxed()).unwrap();
+    session.send("hello").unwrap();
+
+    let forked = store.fork("base", "branch").unwrap();
+    assert_eq!(forked.messages.len(), 2);
+    assert!(store.fork("base", "branch").is_err());
+
+    store.delete("base").unwrap();
+    assert!(!store.exists("base"));
+    assert!(store.exists("branch"));
+    assert!(matches!(store.load("base"), Err(LlmError::Session(_))));
+}
+
+#[test]
+fn test_session_rejects_path_like_names() {
+    let dir = TempDir::new().unwrap();
+    let store = SessionStore::new(dir.path());
+
+    assert!(Session::new(store.clone(), "../escape", "m", CountingBackend::boxed()).is_err());
+    assert!(Session::new(store, ".hidden", "m", CountingBackend::boxed()).is_err());
+}
+
+#[test]
+fn test_context_window_trims_oldest_messages() {
+    let dir = TempDir::new().unwrap();
+    let store = SessionStore::new(dir.path());
+
+    let mut session = Session::new(store, "long", "m", CountingBackend::boxed())
+        .unwrap()
+        .with_context_size(Some(120))
+        .with_reply_reserve(20);
+    session.set_system("system prompt");
+
+    for i in 0..10 {
+        session.send(&format!("message {} {}", i, "x".repeat(80))).unwrap();
+    }
+
+    let window = session.context_window();
+    assert_eq!(window[0].role, Role::System);
+    assert!(window.len() < session.messages().len());
+    assert_eq!(window.last().unwrap(), session.messages().last().unwrap());
+}
+
+#[test]
+fn test_send_stream_keeps_partial_reply_and_save_as() {
+    let dir = TempDir::new().unwrap();
+    let store = SessionStore::new(dir.path());
+    let mock = agentd::testing::MockBackend::new().with_response("one two three four");
+
+    let mut session = Session::new(store.clone(), "scratch", "m", Box::new(mock))
+        .unwrap()
+        .with_autosave(false);
+    let mut chunks = Vec::new();
+    let reply = session
+        .send_stream("count", &mut |chunk| {
+            chunks.push(chunk.to_string());
+            chunks.len() < 2
+        })
+        .unwrap();
+
+    assert_eq!(chunks, ["one ", "two "]);
+    assert_eq!(reply, "one two ");
+    assert_eq!(session.messages().last().unwrap(), &ChatMessage::assistant("one two "));
+    assert!(!store.exists("scratch"));
+
+    session.save_as("kept").unwrap();
+    assert_eq!(store.load("kept").unwrap().messages.len(), 2);
+
+    // Changing arguments keeps the backend, as `/temp` does in the REPL
+    session.set_llm_args(vec!["--temp".to_string(), "0.5".to_string()]);
+    assert_eq!(session.llm().config().additional_args, ["--temp", "0.5"]);
+    assert_eq!(session.send("again").unwrap(), "one two three four");
+}
+
+#[test]
+fn test_repl_command_parsing() {
+    use agentd::repl::ReplCommand;
+
+    assert_eq!(ReplCommand::parse("hello /model").unwrap(), None);
+    assert_eq!(ReplCommand::parse("/model qwen").unwrap(), Some(ReplCommand::Model("qwen".to_string())));
+    assert_eq!(ReplCommand::parse(" /temp 0.5 ").unwrap(), Some(ReplCommand::Temp(0.5)));
+    assert_eq!(ReplCommand::parse("/save").unwrap(), Some(ReplCommand::Save(None)));
+    assert_eq!(ReplCommand::parse("/system Be brief.").unwrap(), Some(ReplCommand::System("Be brief.".to_string())));
+    assert!(ReplCommand::parse("/temp hot").is_err());
+    assert!(ReplCommand::parse("/model").is_err());
+    assert!(ReplCommand::parse("/teleport").is_err());
+}
+
+#[test]
+fn test_chat_without_session_runs_after_default_is_saved() {
+    let home = TempDir::new().unwrap();
+    fs::create_dir_all(home.path().join("models")).unwrap();
+    fs::write(home.path().join("models").join("tiny.gguf"), b"GGUF").unwrap();
+    let chat = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_agentd"))
+            .env("AGENTD_HOME", home.path())
+            .env("AGENTD_SYSTEM_CONFIG_DIR", home.path().join("etc"))
+            .env("AGENTD_RUNTIME_DEFAULT_BACKEND", "mock")
+            .args(["chat", "--model", "tiny"])
+            .args(extra)
+            .arg("hello")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    assert!(chat(&["--session", "default"]).status.success());
+    for _ in 0..2 {
+        let output = chat(&[]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    assert_eq!(SessionStore::new(home.path().join("sessions")).load("default").unwrap().messages.len(), 2);
+}
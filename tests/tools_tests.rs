@@ -0,0 +1,104 @@
+use agentd::testing::{MockBackend, MockReply};
+use agentd::tools::{self, ToolFormat};
+use agentd::{ChatMessage, LlmError, LlmInterface, Role, Tool, ToolCall};
+use serde_json::json;
+
+fn weather_tool() -> Tool {
+    Tool::new(
+        "get_weather",
+        "Current weather for a city",
+        json!({"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}),
+    )
+}
+
+#[test]
+fn test_parse_calls_for_each_family() {
+    assert_eq!(ToolFormat::detect("/models/Qwen2.5-7B-Instruct-Q4_K_M.gguf"), ToolFormat::Qwen);
+    assert_eq!(ToolFormat::detect("Hermes-3-Llama-3.1-8B.gguf"), ToolFormat::Hermes);
+    assert_eq!(ToolFormat::detect("Meta-Llama-3.1-8B-Instruct"), ToolFormat::Llama3);
+    assert_eq!(ToolFormat::detect("gemma-3-12b-it"), ToolFormat::Gemma);
+    assert_eq!(ToolFormat::detect("mistral-7b"), ToolFormat::Hermes);
+
+    let hermes = ToolFormat::Hermes
+        .parse_reply("Let me check.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n</tool_call>", &[])
+        .unwrap();
+    assert_eq!(hermes.content, "Let me check.");
+    assert_eq!(hermes.tool_calls[0].name, "get_weather");
+    assert_eq!(hermes.tool_calls[0].arguments, json!({"city": "Paris"}));
+    assert!(hermes.tool_calls[0].id.starts_with("call_"));
+
+    // A reply cut off before the closing tag, with arguments written as a string
+    let qwen = ToolFormat::Qwen
+        .parse_reply("<tool_call>\n{\"name\": \"get_weather\", \"arguments\": \"{\\\"city\\\": \\\"Oslo\\\"}\"}", &[])
+        .unwrap();
+    assert_eq!((qwen.content.as_str(), &qwen.tool_calls[0].arguments), ("", &json!({"city": "Oslo"})));
+
+    let offered = [weather_tool(), Tool::new("get_time", "", json!({"type": "object"}))];
+    let llama = ToolFormat::Llama3
+        .parse_reply("<|python_tag|>{\"name\": \"get_weather\", \"parameters\": {\"city\": \"Rome\"}}; {\"name\": \"get_time\", \"parameters\": {}}", &offered)
+        .unwrap();
+    assert_eq!(llama.tool_calls.len(), 2);
+    assert_eq!(llama.tool_calls[1].name, "get_time");
+    let answer = ToolFormat::Llama3.parse_reply("It is {\"sunny\": true} today", &offered).unwrap();
+    assert_eq!((answer.content.as_str(), answer.tool_calls.len()), ("It is {\"sunny\": true} today", 0));
+    // JSON answers that merely have a `name` are not calls
+    for reply in ["{\"name\": \"Ada\", \"age\": 36}", "{\"name\": \"Ada\", \"parameters\": {}}", "{\"name\": \"get_weather\"}"] {
+        let answer = ToolFormat::Llama3.parse_reply(reply, &offered).unwrap();
+        assert_eq!((answer.content.as_str(), answer.tool_calls.len()), (reply, 0));
+    }
+
+    let gemma = ToolFormat::Gemma
+        .parse_reply("```tool_code\nsearch(query='it\\'s \"here\"', limit=3, exact=True, tags=['a', \"b\"], filters={\"year\": 2024.5, 'lang': None})\n```", &[])
+        .unwrap();
+    assert_eq!(
+        gemma.tool_calls[0].arguments,
+        json!({"query": "it's \"here\"", "limit": 3, "exact": true, "tags": ["a", "b"], "filters": {"year": 2024.5, "lang": null}})
+    );
+
+    let broken = ToolFormat::Hermes.parse_reply("<tool_call>{\"name\": </tool_call>", &[]);
+    assert!(matches!(broken, Err(LlmError::InvalidResponse(_))));
+    assert!(ToolFormat::Gemma.parse_reply("```tool_code\nsearch(query=)\n```", &[]).is_err());
+
+    // Rendering a call and reading it back gives the same call
+    let call = ToolCall::new("search", json!({"query": "a \"b\"", "n": 2, "flags": [true, null]}));
+    let search = [Tool::new("search", "", json!({"type": "object"}))];
+    for format in [ToolFormat::Hermes, ToolFormat::Qwen, ToolFormat::Llama3, ToolFormat::Gemma] {
+        let parsed = format.parse_reply(&format.render_calls(std::slice::from_ref(&call)), &search).unwrap();
+        assert_eq!((parsed.tool_calls[0].name.as_str(), &parsed.tool_calls[0].arguments), ("search", &call.arguments), "{:?}", format);
+    }
+}
+
+#[test]
+fn test_chat_with_tools_round_trip() {
+    let mock = MockBackend::new().with_model_name("qwen2.5-7b-instruct").with_script([
+        MockReply::text("<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n</tool_call>"),
+        MockReply::text("It is sunny in Paris."),
+    ]);
+    let calls = mock.calls();
+    let tools = [weather_tool()];
+
+    let mut messages = vec![ChatMessage::system("Be brief."), ChatMessage::user("Weather in Paris?")];
+    let reply = mock.chat_with_tools(&messages, &tools).unwrap();
+    assert_eq!(reply.role, Role::Assistant);
+    assert_eq!(reply.tool_calls.len(), 1);
+    let id = reply.tool_calls[0].id.clone();
+    messages.push(reply);
+    messages.push(ChatMessage::tool(&id, "sunny, 24C"));
+
+    let answer = mock.chat_with_tools(&messages, &tools).unwrap();
+    assert_eq!(answer.content, "It is sunny in Paris.");
+    assert!(answer.tool_calls.is_empty());
+
+    let calls = calls.lock().unwrap();
+    assert!(calls[0].prompt.starts_with("System: Be brief.\n\n# Tools"));
+    assert!(calls[0].prompt.contains("\"name\":\"get_weather\""));
+    assert!(calls[1].prompt.contains("Assistant: <tool_call>\n{\"arguments\":{\"city\":\"Paris\"},\"name\":\"get_weather\"}\n</tool_call>"));
+    assert!(calls[1].prompt.contains("User: <tool_response>\nsunny, 24C\n</tool_response>"));
+
+    // OpenAI messages carry arguments as a string and survive a round trip
+    let openai = tools::message_to_openai(&messages[2]);
+    assert_eq!(openai["tool_calls"][0]["function"]["arguments"], "{\"city\":\"Paris\"}");
+    assert_eq!(tools::message_from_openai(&openai).unwrap(), messages[2]);
+    assert_eq!(tools::message_from_openai(&tools::message_to_openai(&messages[3])).unwrap(), messages[3]);
+    assert_eq!(Tool::from_openai(&weather_tool().to_openai()).unwrap(), weather_tool());
+}